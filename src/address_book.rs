@@ -1,16 +1,91 @@
-#[derive(Debug, Clone, PartialEq)]
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::history::{Change, History};
+use crate::address_book::index::SecondaryIndex;
+pub use crate::address_book::contact::{
+  ContactLabel, ContactList, EmailAddress, Labeled, PhoneNumber, PhoneNumberKind,
+};
+pub use crate::address_book::csv::{
+  ColumnMapping, CsvColumn, CsvEncoding, CsvImportOptions, CsvImportReport, CsvRowError,
+};
+pub use crate::address_book::id_generator::{
+  IdGenerator, MonotonicIdGenerator, RandomIdGenerator, SnowflakeIdGenerator,
+};
+use crate::address_book::id_generator::checked_id;
+pub use crate::address_book::dedup::{
+  ConflictPolicy, DuplicateCluster, DuplicateOptions, DuplicatePair, MergeOptions, MergeRecord,
+};
+pub use crate::address_book::geo::{Coordinate, Gazetteer, NearbyEntry};
+pub use crate::address_book::group::Group;
+pub use crate::address_book::history::DEFAULT_HISTORY_LIMIT;
+pub use crate::address_book::events::{AddressBookEvent, EventLog, EventRecord};
+pub use crate::address_book::fuzzy::{FuzzyOptions, ScoredEntry};
+pub use crate::address_book::label::{LabelLayout, LabelOptions};
+pub use crate::address_book::masking::{Masking, MaskingPolicy};
+pub use crate::address_book::name::NameOrder;
+pub use crate::address_book::persistence::{Migration, Migrations, SCHEMA_VERSION};
+pub use crate::address_book::postal_code::PostalCode;
+pub use crate::address_book::postal_dictionary::{
+  AddressConsistency, AddressSuggestion, PostalCodeDictionary, PostalCodeRecord,
+};
+pub use crate::address_book::prefecture::{Prefecture, Region};
+pub use crate::address_book::patch::{AddressEntryField, AddressEntryPatch, FieldChange};
+pub use crate::address_book::query::{Condition, Query, QueryResult, SortKey, SortOrder};
+pub use crate::address_book::shared::{AddressBookChange, SharedAddressBook};
+pub use crate::address_book::sync::{AddressBookDiff, EntryDiff, SyncConflict, SyncConflictKind};
+pub use crate::address_book::validation::{
+  CharacterSet, Rule, ValidationError, ValidationRules, Violation,
+};
+pub use crate::address_book::vcard::{VCardError, VCardImportOptions, VCardImportReport, VCardVersion};
+
+pub mod cli;
+mod contact;
+mod csv;
+mod dedup;
+mod events;
+mod fuzzy;
+mod geo;
+mod group;
+mod history;
+mod id_generator;
+mod index;
+pub mod kana;
+mod label;
+mod masking;
+mod name;
+mod patch;
+mod persistence;
+mod postal_code;
+mod postal_dictionary;
+mod prefecture;
+mod query;
+pub mod repository;
+mod shared;
+mod sync;
+mod validation;
+pub mod vcard;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AddressEntryId(pub(crate) u64);
 
 impl AddressEntryId {
-  pub(crate) fn new(value: u64) -> Self {
+  pub fn new(value: u64) -> Self {
     Self(value)
   }
 }
 
-#[derive(Debug, Clone)]
+// Debug は個人情報をマスクするため masking.rs で実装する
+#[derive(Clone, PartialEq)]
 pub struct PersonName {
   pub first_name: String,
   pub last_name: String,
+  // ふりがな
+  pub first_name_kana: Option<String>,
+  pub last_name_kana: Option<String>,
 }
 
 impl PersonName {
@@ -18,153 +93,663 @@ impl PersonName {
     Self {
       first_name: first_name.to_owned(),
       last_name: last_name.to_owned(),
+      first_name_kana: None,
+      last_name_kana: None,
     }
   }
+
+  // 空のふりがなは設定しない
+  pub fn with_kana(mut self, first_name_kana: &str, last_name_kana: &str) -> Self {
+    let kana = |s: &str| Some(s.trim().to_owned()).filter(|s| !s.is_empty());
+    self.first_name_kana = kana(first_name_kana);
+    self.last_name_kana = kana(last_name_kana);
+    self
+  }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Address {
-  pub postal_code: String,
-  pub pref: String,
+  pub postal_code: PostalCode,
+  pub pref: Prefecture,
   pub address: String,
   pub building: Option<String>,
 }
 
 impl Address {
-  pub fn new(postal_code: &str, pref: &str, address: &str, building: Option<&str>) -> Self {
+  pub fn new(
+    postal_code: PostalCode,
+    pref: Prefecture,
+    address: &str,
+    building: Option<&str>,
+  ) -> Self {
     Self {
-      postal_code: postal_code.to_owned(),
-      pref: pref.to_owned(),
+      postal_code,
+      pref,
       address: address.to_owned(),
       building: building.map(|e| e.to_owned()),
     }
   }
 }
 
-#[derive(Debug, Clone)]
+// 各フィールドの型がDebug出力をマスクするため、派生したDebugでもPIIは出ない
+#[derive(Debug, Clone, PartialEq)]
 pub struct AddressEntry {
   pub id: AddressEntryId,
   pub name: PersonName,
-  pub address: Address,
+  // 住所は必ずひとつ以上あるため、一覧は直接公開しない
+  addresses: ContactList<Address>,
+  pub phones: ContactList<PhoneNumber>,
+  pub emails: ContactList<EmailAddress>,
+  pub tags: BTreeSet<String>,
+  // 所属するグループ名。未登録のグループはエントリの追加時に作られる
+  pub groups: BTreeSet<String>,
 }
 
 impl AddressEntry {
   pub fn new(id: AddressEntryId, name: PersonName, address: Address) -> Self {
-    Self { id, name, address }
+    Self {
+      id,
+      name,
+      addresses: ContactList::new().with(ContactLabel::Home, address),
+      phones: ContactList::new(),
+      emails: ContactList::new(),
+      tags: BTreeSet::new(),
+      groups: BTreeSet::new(),
+    }
+  }
+
+  pub fn with_addresses(
+    id: AddressEntryId,
+    name: PersonName,
+    addresses: ContactList<Address>,
+  ) -> Result<Self> {
+    if addresses.is_empty() {
+      return Err(anyhow!("address entry requires at least one address"));
+    }
+    Ok(Self {
+      id,
+      name,
+      addresses,
+      phones: ContactList::new(),
+      emails: ContactList::new(),
+      tags: BTreeSet::new(),
+      groups: BTreeSet::new(),
+    })
+  }
+
+  // 主住所
+  pub fn address(&self) -> &Address {
+    self.addresses.primary().unwrap()
+  }
+
+  pub fn address_mut(&mut self) -> &mut Address {
+    self.addresses.primary_mut().unwrap()
+  }
+
+  pub fn addresses(&self) -> &ContactList<Address> {
+    &self.addresses
+  }
+
+  pub fn add_address(&mut self, label: ContactLabel, address: Address) -> usize {
+    self.addresses.push(label, address)
+  }
+
+  pub fn remove_address(&mut self, index: usize) -> Result<Labeled<Address>> {
+    if self.addresses.len() == 1 && index == 0 {
+      return Err(anyhow!("cannot remove the last address"));
+    }
+    self.addresses.remove(index)
+  }
+
+  pub fn set_primary_address(&mut self, index: usize) -> Result<()> {
+    self.addresses.set_primary(index)
+  }
+
+  // 前後の空白を除いて追加する。追加されなければfalse
+  pub fn add_tag(&mut self, tag: &str) -> bool {
+    let tag = tag.trim();
+    !tag.is_empty() && self.tags.insert(tag.to_owned())
+  }
+
+  pub fn remove_tag(&mut self, tag: &str) -> bool {
+    self.tags.remove(tag.trim())
+  }
+
+  pub fn has_tag(&self, tag: &str) -> bool {
+    self.tags.contains(tag.trim())
+  }
+
+  pub fn is_member_of(&self, group: &str) -> bool {
+    self.groups.contains(group.trim())
   }
 }
 
-#[derive(Debug, Clone)]
-pub struct AddressBook {
-  name: String,
-  entries: Vec<AddressEntry>,
+// 二次インデックスのキー。変更前後で比較してインデックスを張り替える
+#[derive(Debug, Clone, PartialEq)]
+struct IndexKeys {
+  postal_code: PostalCode,
+  pref: Prefecture,
+  last_name: String,
+  tags: BTreeSet<String>,
+  groups: BTreeSet<String>,
 }
 
-impl Default for AddressBook {
-  fn default() -> Self {
+impl IndexKeys {
+  fn of(entry: &AddressEntry) -> Self {
     Self {
-      name: String::default(),
-      entries: Vec::default(),
+      postal_code: entry.address().postal_code.clone(),
+      pref: entry.address().pref,
+      last_name: entry.name.last_name.clone(),
+      tags: entry.tags.clone(),
+      groups: entry.groups.clone(),
     }
   }
 }
 
+#[derive(Debug, Clone)]
+struct Slot {
+  seq: u64,
+  entry: AddressEntry,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AddressBook {
+  name: String,
+  next_seq: u64,
+  entries: HashMap<AddressEntryId, Slot>,
+  order: BTreeMap<u64, AddressEntryId>,
+  postal_code_index: SecondaryIndex<PostalCode>,
+  pref_index: SecondaryIndex<Prefecture>,
+  last_name_index: SecondaryIndex<String>,
+  tag_index: SecondaryIndex<String>,
+  group_index: SecondaryIndex<String>,
+  groups: BTreeMap<String, Group>,
+  id_generator: Box<dyn IdGenerator>,
+  merges: Vec<MergeRecord>,
+  history: History,
+  // イベントを記録する場合のみSome。EventLog に渡すまで溜めておく
+  events: Option<Vec<AddressBookEvent>>,
+  validation_rules: ValidationRules,
+  // 変更のたびに増える。イベントを出さない変更も含めて変化の有無を判定するのに使う
+  revision: u64,
+}
+
 impl AddressBook {
   pub fn new(name: &str) -> Self {
     Self {
       name: name.to_owned(),
-      entries: Vec::default(),
+      ..Self::default()
     }
   }
 
+  pub fn with_id_generator<G: IdGenerator + 'static>(mut self, id_generator: G) -> Self {
+    let mut id_generator: Box<dyn IdGenerator> = Box::new(id_generator);
+    self.entries.keys().for_each(|id| id_generator.observe(id));
+    self.id_generator = id_generator;
+    self
+  }
+
   pub fn name(&self) -> &str {
     &self.name
   }
 
-  pub fn add_entry(&mut self, address_entry: AddressEntry) {
-    self.entries.push(address_entry);
+  pub fn len(&self) -> usize {
+    self.entries.len()
   }
 
-  pub fn add_entries(&mut self, address_entries: &[AddressEntry]) {
-    address_entries
-      .to_vec()
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  fn index(&mut self, keys: IndexKeys, seq: u64) {
+    self.postal_code_index.insert(keys.postal_code, seq);
+    self.pref_index.insert(keys.pref, seq);
+    self.last_name_index.insert(keys.last_name, seq);
+    for tag in keys.tags {
+      self.tag_index.insert(tag, seq);
+    }
+    for group in keys.groups {
+      self.group_index.insert(group, seq);
+    }
+  }
+
+  // エントリの追加や変更で参照された未登録のグループを作る。
+  // エントリの変更と同じ操作として取り消せるよう、記録する変更を返す
+  fn create_missing_groups(&mut self, groups: &BTreeSet<String>) -> Vec<Change> {
+    let missing = groups
+      .iter()
+      .filter(|name| !self.groups.contains_key(*name))
+      .cloned()
+      .collect::<Vec<_>>();
+    missing
       .into_iter()
-      .for_each(|e| self.add_entry(e))
+      .map(|name| {
+        let group = Group::new(&name);
+        self.groups.insert(name.clone(), group.clone());
+        self.emit_group(&name, None, Some(&group));
+        Change::Group {
+          name,
+          before: None,
+          after: Some(group),
+        }
+      })
+      .collect()
   }
 
-  pub fn add_entries1(&mut self, address_entries: impl IntoIterator<Item = AddressEntry>) {
-    address_entries.into_iter().for_each(|e| self.add_entry(e))
+  fn unindex(&mut self, keys: &IndexKeys, seq: u64) {
+    self.postal_code_index.remove(&keys.postal_code, seq);
+    self.pref_index.remove(&keys.pref, seq);
+    self.last_name_index.remove(&keys.last_name, seq);
+    for tag in &keys.tags {
+      self.tag_index.remove(tag, seq);
+    }
+    for group in &keys.groups {
+      self.group_index.remove(group, seq);
+    }
   }
 
-  pub fn remove_entry(&mut self, address_entry_id: AddressEntryId) -> AddressEntry {
-    let index = self
-      .entries
+  pub fn add_entry(&mut self, address_entry: AddressEntry) -> Result<()> {
+    self.validate(&address_entry)?;
+    self.insert_entry(address_entry)
+  }
+
+  // 検証せずに追加する。保存済みのデータの読み込みやイベントの再生に使う
+  pub(crate) fn insert_entry(&mut self, address_entry: AddressEntry) -> Result<()> {
+    checked_id(address_entry.id.0)?;
+    if self.contains(&address_entry.id) {
+      return Err(anyhow!(
+        "duplicate address entry id: {:?}",
+        address_entry.id
+      ));
+    }
+    self.put_entry(address_entry);
+    Ok(())
+  }
+
+  pub(crate) fn revision(&self) -> u64 {
+    self.revision
+  }
+
+  pub(crate) fn touch(&mut self) {
+    self.revision += 1;
+  }
+
+  fn insert_slot(&mut self, seq: u64, address_entry: AddressEntry) {
+    self.touch();
+    self.id_generator.observe(&address_entry.id);
+    self.next_seq = self.next_seq.max(seq + 1);
+    self.order.insert(seq, address_entry.id.clone());
+    let keys = IndexKeys::of(&address_entry);
+    self.entries.insert(
+      address_entry.id.clone(),
+      Slot {
+        seq,
+        entry: address_entry,
+      },
+    );
+    self.index(keys, seq);
+  }
+
+  fn take_slot(&mut self, address_entry_id: &AddressEntryId) -> Option<(u64, AddressEntry)> {
+    let Slot { seq, entry } = self.entries.remove(address_entry_id)?;
+    self.touch();
+    self.order.remove(&seq);
+    self.unindex(&IndexKeys::of(&entry), seq);
+    Some((seq, entry))
+  }
+
+  // 同じIDのエントリが既にある場合は挿入位置を保ったまま置き換え、古いエントリを返す。
+  // 検証は行わないので、呼び出し側で検証済みのエントリか、既に保存されていたエントリだけを渡す
+  pub(crate) fn put_entry(&mut self, address_entry: AddressEntry) -> Option<AddressEntry> {
+    let (seq, old) = match self.take_slot(&address_entry.id) {
+      Some((seq, old)) => (seq, Some(old)),
+      None => (self.next_seq, None),
+    };
+    let mut changes = self.create_missing_groups(&address_entry.groups);
+    if self.history.is_recording() {
+      changes.push(Change::Entry {
+        id: address_entry.id.clone(),
+        before: old.clone().map(|old| (seq, Box::new(old))),
+        after: Some((seq, Box::new(address_entry.clone()))),
+      });
+    }
+    self.history.record_all(changes);
+    self.emit(old.as_ref(), Some(&address_entry));
+    self.insert_slot(seq, address_entry);
+    old
+  }
+
+  // IDを採番してエントリを追加する
+  pub fn add(&mut self, name: PersonName, address: Address) -> Result<AddressEntryId> {
+    let id = self.next_entry_id()?;
+    self.add_entry(AddressEntry::new(id.clone(), name, address))?;
+    Ok(id)
+  }
+
+  pub fn next_entry_id(&mut self) -> Result<AddressEntryId> {
+    loop {
+      let id = self.id_generator.generate()?;
+      if !self.contains(&id) {
+        return Ok(id);
+      }
+    }
+  }
+
+  // インポート中の採番用。dry_runの場合は採番器の状態を元に戻す
+  pub(crate) fn with_id_sequence<T, F>(&mut self, dry_run: bool, f: F) -> T
+  where
+    F: FnOnce(&mut dyn FnMut() -> Result<AddressEntryId>) -> T,
+  {
+    let mut id_generator = self.id_generator.clone();
+    let result = {
+      let entries = &self.entries;
+      let mut next_id = || loop {
+        let id = id_generator.generate()?;
+        if !entries.contains_key(&id) {
+          return Ok(id);
+        }
+      };
+      f(&mut next_id)
+    };
+    if !dry_run {
+      self.id_generator = id_generator;
+    }
+    result
+  }
+
+  // 重複するIDがひとつでもあれば何も追加しない
+  pub fn add_entries(&mut self, address_entries: &[AddressEntry]) -> Result<()> {
+    self.add_entries1(address_entries.iter().cloned())
+  }
+
+  pub fn add_entries1(
+    &mut self,
+    address_entries: impl IntoIterator<Item = AddressEntry>,
+  ) -> Result<()> {
+    let address_entries = address_entries.into_iter().collect::<Vec<_>>();
+    let mut ids = HashSet::new();
+    if let Some(duplicate) = address_entries
       .iter()
-      .position(|e| e.id == address_entry_id)
-      .unwrap();
-    self.entries.remove(index)
+      .find(|e| self.contains(&e.id) || !ids.insert(e.id.clone()))
+    {
+      return Err(anyhow!("duplicate address entry id: {:?}", duplicate.id));
+    }
+    for address_entry in &address_entries {
+      self.validate(address_entry)?;
+    }
+    self.transaction("add_entries", |book| {
+      address_entries.into_iter().for_each(|e| {
+        book.put_entry(e);
+      });
+      Ok(())
+    })
+  }
+
+  pub fn remove_entry(&mut self, address_entry_id: AddressEntryId) -> AddressEntry {
+    let (seq, entry) = self.take_slot(&address_entry_id).unwrap();
+    if self.history.is_recording() {
+      self.history.record(Change::Entry {
+        id: address_entry_id,
+        before: Some((seq, Box::new(entry.clone()))),
+        after: None,
+      });
+    }
+    self.emit(Some(&entry), None);
+    entry
   }
 
+  // まとめてひとつの操作として取り消せる
   pub fn remove_entries(&mut self, address_entry_ids: &[AddressEntryId]) -> Vec<AddressEntry> {
-    address_entry_ids
-      .to_vec()
-      .into_iter()
-      .fold(vec![], |mut acc, address_entry_id| {
-        acc.push(self.remove_entry(address_entry_id));
-        acc
+    self
+      .transaction("remove_entries", |book| {
+        Ok(
+          address_entry_ids
+            .iter()
+            .cloned()
+            .fold(vec![], |mut acc, address_entry_id| {
+              acc.push(book.remove_entry(address_entry_id));
+              acc
+            }),
+        )
+      })
+      .unwrap()
+  }
+
+  pub fn clear(&mut self) {
+    let ids = self.order.values().cloned().collect::<Vec<_>>();
+    self
+      .transaction("clear", |book| {
+        ids.into_iter().for_each(|id| {
+          book.remove_entry(id);
+        });
+        Ok(())
       })
+      .unwrap()
+  }
+
+  pub fn get(&self, address_entry_id: &AddressEntryId) -> Option<&AddressEntry> {
+    self.entries.get(address_entry_id).map(|slot| &slot.entry)
+  }
+
+  // 変更はハンドルのDrop時にインデックスへ反映される。検証は行わないので、
+  // 検証規則を適用したい場合は update_entry を使う
+  pub fn get_mut(&mut self, address_entry_id: &AddressEntryId) -> Option<AddressEntryMut<'_>> {
+    let slot = self.entries.get(address_entry_id)?;
+    let keys = IndexKeys::of(&slot.entry);
+    let original = Some(&slot.entry)
+      .filter(|_| self.history.is_recording() || self.events.is_some())
+      .cloned();
+    Some(AddressEntryMut {
+      original_id: address_entry_id.clone(),
+      original_keys: keys,
+      original,
+      book: self,
+    })
+  }
+
+  pub fn update_entry<F>(
+    &mut self,
+    address_entry_id: &AddressEntryId,
+    f: F,
+  ) -> Result<Vec<FieldChange>>
+  where
+    F: FnOnce(&mut AddressEntry),
+  {
+    let before = self
+      .get(address_entry_id)
+      .cloned()
+      .ok_or_else(|| anyhow!("address entry not found: {:?}", address_entry_id))?;
+    let mut after = before.clone();
+    f(&mut after);
+    after.id = before.id.clone();
+    // 検証に失敗した場合は何も変更しない
+    self.validate(&after)?;
+    let changes = FieldChange::diff(&before, &after);
+    *self.get_mut(address_entry_id).unwrap() = after;
+    Ok(changes)
+  }
+
+  pub fn patch_entry(
+    &mut self,
+    address_entry_id: &AddressEntryId,
+    patch: &AddressEntryPatch,
+  ) -> Result<Vec<FieldChange>> {
+    self.update_entry(address_entry_id, |entry| {
+      patch.apply(entry);
+    })
+  }
+
+  pub fn contains(&self, address_entry_id: &AddressEntryId) -> bool {
+    self.entries.contains_key(address_entry_id)
+  }
+
+  pub fn query(&self, query: &Query) -> QueryResult<'_> {
+    query.execute(self)
   }
 
   pub fn iter(&self) -> impl Iterator<Item = &AddressEntry> {
-    self.entries.iter()
+    self.order.values().map(move |id| &self.entries[id].entry)
+  }
+
+  fn entry_at(&self, seq: u64) -> &AddressEntry {
+    &self.entries[&self.order[&seq]].entry
+  }
+
+  pub fn find_by_postal_code<'a>(
+    &'a self,
+    postal_code: &PostalCode,
+  ) -> impl Iterator<Item = &'a AddressEntry> {
+    self
+      .postal_code_index
+      .get(postal_code)
+      .map(move |seq| self.entry_at(seq))
+  }
+
+  pub fn find_by_pref(&self, pref: Prefecture) -> impl Iterator<Item = &AddressEntry> {
+    self
+      .pref_index
+      .get(&pref)
+      .map(move |seq| self.entry_at(seq))
+  }
+
+  pub fn find_by_last_name<'a>(
+    &'a self,
+    last_name: &str,
+  ) -> impl Iterator<Item = &'a AddressEntry> {
+    self
+      .last_name_index
+      .get(last_name)
+      .map(move |seq| self.entry_at(seq))
+  }
+
+  pub fn find_by_tag<'a>(&'a self, tag: &str) -> impl Iterator<Item = &'a AddressEntry> {
+    self
+      .tag_index
+      .get(tag.trim())
+      .map(move |seq| self.entry_at(seq))
+  }
+}
+
+// AddressBook::get_mut が返す可変参照。IDの変更は破棄され、Drop時に二次インデックスを更新する
+pub struct AddressEntryMut<'a> {
+  book: &'a mut AddressBook,
+  original_id: AddressEntryId,
+  original_keys: IndexKeys,
+  // 履歴を記録する場合だけ変更前のエントリを保持する
+  original: Option<AddressEntry>,
+}
+
+impl Deref for AddressEntryMut<'_> {
+  type Target = AddressEntry;
+
+  fn deref(&self) -> &Self::Target {
+    &self.book.entries[&self.original_id].entry
   }
 }
 
+impl DerefMut for AddressEntryMut<'_> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.book.entries.get_mut(&self.original_id).unwrap().entry
+  }
+}
+
+impl Drop for AddressEntryMut<'_> {
+  fn drop(&mut self) {
+    // 元の値を持たない場合は変更の有無が分からないので、変更ありとみなす
+    if self.original.as_ref() != self.book.get(&self.original_id) {
+      self.book.touch();
+    }
+    let slot = self.book.entries.get_mut(&self.original_id).unwrap();
+    slot.entry.id = self.original_id.clone();
+    let seq = slot.seq;
+    let keys = IndexKeys::of(&slot.entry);
+    let original = &self.original;
+    let entry = Some(&slot.entry)
+      .filter(|entry| original.as_ref().is_some_and(|o| o != *entry))
+      .cloned();
+    let mut changes = self.book.create_missing_groups(&keys.groups);
+    if let (Some(original), Some(entry)) = (self.original.take(), entry) {
+      self.book.emit(Some(&original), Some(&entry));
+      changes.push(Change::Entry {
+        id: self.original_id.clone(),
+        before: Some((seq, Box::new(original))),
+        after: Some((seq, Box::new(entry))),
+      });
+    }
+    self.book.history.record_all(changes);
+    if keys != self.original_keys {
+      self.book.unindex(&self.original_keys, seq);
+      self.book.index(keys, seq);
+    }
+  }
+}
+
+// テスト用のエントリ。住所は東京都の "minato-ku" で固定
+#[cfg(test)]
+pub(crate) fn test_entry(
+  id: u64,
+  first_name: &str,
+  last_name: &str,
+  postal_code: &str,
+) -> AddressEntry {
+  AddressEntry::new(
+    AddressEntryId::new(id),
+    PersonName::new(first_name, last_name),
+    Address::new(
+      PostalCode::new(postal_code).unwrap(),
+      Prefecture::Tokyo,
+      "minato-ku",
+      None,
+    ),
+  )
+}
+
 #[cfg(test)]
 mod test {
-  use crate::address_book::{AddressBook, AddressEntry, AddressEntryId, PersonName, Address};
+  use crate::address_book::{
+    Address, AddressBook, AddressEntry, AddressEntryField, AddressEntryId, AddressEntryPatch,
+    ContactLabel, ContactList, PersonName, PostalCode, Prefecture, SnowflakeIdGenerator,
+  };
+  use crate::address_book::test_entry;
 
   #[test]
   fn test_address_book() {
-    let _address_book1 = AddressBook {
-      name: "".to_owned(),
-      entries: Vec::default(),
-    };
+    let _address_book1 = AddressBook::new("");
 
     let mut address_book = AddressBook::default();
 
     let address_entry_id = AddressEntryId::new(1);
     let personal_name = PersonName::new("Junichi", "Kato");
     let address = Address::new(
-      "111-0001",
-      "Tokyo-to",
+      PostalCode::new("111-0001").unwrap(),
+      Prefecture::Tokyo,
       "minato-ku 1",
       Some("hoge 1 building"),
     );
     let address_entry = AddressEntry::new(address_entry_id, personal_name, address);
-    address_book.add_entry(address_entry);
+    address_book.add_entry(address_entry).unwrap();
 
     let address_entry_id = AddressEntryId::new(2);
     let personal_name = PersonName::new("Taro", "Yamamoto");
     let address = Address::new(
-      "111-0002",
-      "Tokyo-to",
+      PostalCode::new("111-0002").unwrap(),
+      Prefecture::Tokyo,
       "minato-ku 2",
       Some("hoge 2 building"),
     );
     let address_entry = AddressEntry::new(address_entry_id, personal_name, address);
-    address_book.add_entry(address_entry);
+    address_book.add_entry(address_entry).unwrap();
 
     let address_entry_id = AddressEntryId::new(3);
     let personal_name = PersonName::new("Hanako", "Yamada");
     let address = Address::new(
-      "111-0003",
-      "Tokyo-to",
+      PostalCode::new("111-0003").unwrap(),
+      Prefecture::Tokyo,
       "minato-ku 3",
       Some("hoge 3 building"),
     );
     let address_entry = AddressEntry::new(address_entry_id, personal_name, address);
-    address_book.add_entry(address_entry);
+    address_book.add_entry(address_entry).unwrap();
 
     address_book.iter().for_each(|e| println!("{:?}", e));
 
@@ -172,8 +757,8 @@ mod test {
       AddressEntryId::new(4),
       PersonName::new("Hanako", "Yamada"),
       Address::new(
-        "111-0003",
-        "Tokyo-to",
+        PostalCode::new("111-0003").unwrap(),
+        Prefecture::Tokyo,
         "minato-ku 3",
         Some("hoge 3 building"),
       ),
@@ -182,15 +767,250 @@ mod test {
       AddressEntryId::new(4),
       PersonName::new("Hanako", "Yamada"),
       Address::new(
-        "111-0003",
-        "Tokyo-to",
+        PostalCode::new("111-0003").unwrap(),
+        Prefecture::Tokyo,
         "minato-ku 3",
         Some("hoge 3 building"),
       ),
     );
     let entries1 = [address_entry1.clone(), address_entry2.clone()];
-    address_book.add_entries1(entries1);
+    assert!(address_book.add_entries1(entries1).is_err());
     let entries2 = vec![address_entry1, address_entry2];
-    address_book.add_entries1(entries2);
+    assert!(address_book.add_entries1(entries2).is_err());
+    assert_eq!(address_book.len(), 3);
+  }
+
+  #[test]
+  fn test_lookup_by_id_and_index() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        test_entry(1, "Junichi", "Kato", "111-0001"),
+        test_entry(2, "Taro", "Yamada", "111-0002"),
+        test_entry(3, "Hanako", "Yamada", "111-0001"),
+      ])
+      .unwrap();
+    assert_eq!(address_book.len(), 3);
+    assert!(address_book.contains(&AddressEntryId::new(2)));
+    assert_eq!(
+      address_book
+        .get(&AddressEntryId::new(3))
+        .unwrap()
+        .name
+        .first_name,
+      "Hanako"
+    );
+    let ids = |it: &mut dyn Iterator<Item = &AddressEntry>| it.map(|e| e.id.0).collect::<Vec<_>>();
+    assert_eq!(
+      ids(&mut address_book.find_by_postal_code(&PostalCode::new("111-0001").unwrap())),
+      vec![1, 3]
+    );
+    assert_eq!(
+      ids(&mut address_book.find_by_last_name("Yamada")),
+      vec![2, 3]
+    );
+    assert_eq!(
+      ids(&mut address_book.find_by_pref(Prefecture::Tokyo)),
+      vec![1, 2, 3]
+    );
+
+    address_book.remove_entry(AddressEntryId::new(1));
+    assert!(!address_book.contains(&AddressEntryId::new(1)));
+    assert_eq!(
+      ids(&mut address_book.find_by_postal_code(&PostalCode::new("111-0001").unwrap())),
+      vec![3]
+    );
+    assert_eq!(ids(&mut address_book.iter()), vec![2, 3]);
+
+    address_book
+      .add_entry(test_entry(1, "Junichi", "Kato", "111-0001"))
+      .unwrap();
+    assert_eq!(ids(&mut address_book.iter()), vec![2, 3, 1]);
+  }
+
+  #[test]
+  fn test_get_mut_reindexes() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "Junichi", "Kato", "111-0001"))
+      .unwrap();
+    address_book
+      .add_entry(test_entry(2, "Taro", "Yamada", "111-0002"))
+      .unwrap();
+    {
+      let mut e = address_book.get_mut(&AddressEntryId::new(1)).unwrap();
+      e.address_mut().postal_code = PostalCode::new("222-0002").unwrap();
+      e.name.last_name = "Sato".to_owned();
+      e.id = AddressEntryId::new(99);
+    }
+    assert_eq!(
+      address_book
+        .find_by_postal_code(&PostalCode::new("111-0001").unwrap())
+        .count(),
+      0
+    );
+    assert_eq!(
+      address_book
+        .find_by_postal_code(&PostalCode::new("222-0002").unwrap())
+        .count(),
+      1
+    );
+    assert_eq!(address_book.find_by_last_name("Kato").count(), 0);
+    assert_eq!(address_book.find_by_last_name("Sato").count(), 1);
+    assert!(address_book.contains(&AddressEntryId::new(1)));
+    assert_eq!(
+      address_book.iter().map(|e| e.id.0).collect::<Vec<_>>(),
+      vec![1, 2]
+    );
+  }
+
+  #[test]
+  fn test_put_entry_replaces_same_id() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "Junichi", "Kato", "111-0001"))
+      .unwrap();
+    address_book
+      .add_entry(test_entry(2, "Taro", "Yamada", "111-0002"))
+      .unwrap();
+    assert!(address_book
+      .add_entry(test_entry(1, "Jun", "Kato", "333-0003"))
+      .is_err());
+    let old = address_book.put_entry(test_entry(1, "Jun", "Kato", "333-0003"));
+    assert_eq!(old.unwrap().name.first_name, "Junichi");
+    assert_eq!(address_book.len(), 2);
+    assert_eq!(
+      address_book
+        .find_by_postal_code(&PostalCode::new("111-0001").unwrap())
+        .count(),
+      0
+    );
+    assert_eq!(address_book.iter().next().unwrap().name.first_name, "Jun");
+  }
+
+  #[test]
+  fn test_update_and_patch_entry() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "Junichi", "Kato", "111-0001"))
+      .unwrap();
+    let changes = address_book
+      .update_entry(&AddressEntryId::new(1), |e| {
+        e.name.first_name = "Jun".to_owned();
+        e.address_mut().building = Some("fuga building".to_owned());
+      })
+      .unwrap();
+    assert_eq!(
+      changes.iter().map(|c| c.field).collect::<Vec<_>>(),
+      vec![AddressEntryField::FirstName, AddressEntryField::Building]
+    );
+
+    let patch = AddressEntryPatch::new()
+      .with_postal_code(PostalCode::new("222-0002").unwrap())
+      .clear_building();
+    let changes = address_book
+      .patch_entry(&AddressEntryId::new(1), &patch)
+      .unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(
+      address_book
+        .find_by_postal_code(&PostalCode::new("222-0002").unwrap())
+        .count(),
+      1
+    );
+    assert_eq!(
+      address_book
+        .get(&AddressEntryId::new(1))
+        .unwrap()
+        .address()
+        .building,
+      None
+    );
+    assert!(address_book
+      .patch_entry(&AddressEntryId::new(2), &patch)
+      .is_err());
+  }
+
+  #[test]
+  fn test_add_assigns_ids() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "Junichi", "Kato", "111-0001"))
+      .unwrap();
+    let id = address_book.add(
+      PersonName::new("Taro", "Yamada"),
+      Address::new(
+        PostalCode::new("111-0002").unwrap(),
+        Prefecture::Tokyo,
+        "minato-ku",
+        None,
+      ),
+    );
+    assert_eq!(id.unwrap(), AddressEntryId::new(2));
+    assert!(address_book
+      .add_entries(&[
+        test_entry(3, "A", "B", "111-0003"),
+        test_entry(2, "C", "D", "111-0004")
+      ])
+      .is_err());
+    assert!(!address_book.contains(&AddressEntryId::new(3)));
+
+    let mut address_book = address_book.with_id_generator(SnowflakeIdGenerator::new(1).unwrap());
+    let id = address_book.add(
+      PersonName::new("Hanako", "Yamada"),
+      Address::new(
+        PostalCode::new("111-0003").unwrap(),
+        Prefecture::Tokyo,
+        "minato-ku",
+        None,
+      ),
+    );
+    assert!(id.unwrap().0 > 2);
+    assert_eq!(address_book.len(), 3);
+
+    // TOMLに保存できないIDは追加できない
+    assert!(address_book
+      .add_entry(test_entry(u64::MAX, "E", "F", "111-0005"))
+      .is_err());
+    assert_eq!(address_book.len(), 3);
+  }
+
+  #[test]
+  fn test_multiple_addresses() {
+    let osaka = Address::new(
+      PostalCode::new("530-0001").unwrap(),
+      Prefecture::Osaka,
+      "kita-ku",
+      None,
+    );
+    assert!(AddressEntry::with_addresses(
+      AddressEntryId::new(1),
+      PersonName::new("Taro", "Yamamoto"),
+      ContactList::new()
+    )
+    .is_err());
+
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "Taro", "Yamamoto", "111-0001"))
+      .unwrap();
+    let id = AddressEntryId::new(1);
+    {
+      let mut entry = address_book.get_mut(&id).unwrap();
+      let work = entry.add_address(ContactLabel::Work, osaka);
+      assert_eq!(entry.address().pref, Prefecture::Tokyo);
+      entry.set_primary_address(work).unwrap();
+      assert!(entry.set_primary_address(5).is_err());
+    }
+    assert_eq!(address_book.find_by_pref(Prefecture::Osaka).count(), 1);
+    assert_eq!(address_book.find_by_pref(Prefecture::Tokyo).count(), 0);
+
+    let mut entry = address_book.get_mut(&id).unwrap();
+    let removed = entry.remove_address(1).unwrap();
+    assert_eq!(removed.label, ContactLabel::Work);
+    assert_eq!(entry.address().pref, Prefecture::Tokyo);
+    assert!(entry.remove_address(0).is_err());
+    drop(entry);
+    assert_eq!(address_book.find_by_pref(Prefecture::Tokyo).count(), 1);
   }
 }
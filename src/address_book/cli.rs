@@ -0,0 +1,960 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::label::{display_width, pad};
+use crate::address_book::{
+  Address, AddressBook, AddressEntry, AddressEntryId, Condition, ContactLabel, ContactList,
+  CsvEncoding, CsvImportOptions, FuzzyOptions, MaskingPolicy, PersonName, PhoneNumber,
+  PhoneNumberKind, Query, SortKey, SortOrder, VCardImportOptions, VCardVersion,
+};
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+const DEFAULT_FILE: &str = "address_book.toml";
+
+const USAGE: &str = "usage: sandbox-rs address [--file <path>] [--format table|json] <command>
+
+commands:
+  add --first-name <name> --last-name <name> --postal-code <code> --pref <pref> --address <address>
+      [--building <building>] [--first-name-kana <kana>] [--last-name-kana <kana>]
+      [--phone <number>]... [--email <address>]... [--tag <tag>]...
+  list [--sort id|reading] [--tag <tag>]
+  show <id>
+  remove <id>
+  search <text>
+  import <path> [--type csv|vcard] [--encoding utf8|sjis] [--replace] [--dry-run]
+  export [<path>] [--type csv|vcard|toml] [--encoding utf8|sjis] [--mask --salt <salt>]
+
+the file defaults to $ADDRESS_BOOK_FILE or ./address_book.toml";
+
+// 引数の誤り。終了コード2で使い方を表示する
+#[derive(Debug)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for UsageError {}
+
+fn usage_error(message: impl Into<String>) -> anyhow::Error {
+  UsageError(message.into()).into()
+}
+
+const FLAGS: [&str; 4] = ["dry-run", "help", "mask", "replace"];
+
+#[derive(Debug, Default)]
+struct Args {
+  positional: Vec<String>,
+  options: Vec<(String, String)>,
+  flags: Vec<String>,
+}
+
+impl Args {
+  fn parse(args: &[String]) -> Result<Self> {
+    let mut result = Self::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+      let name = match arg.strip_prefix("--") {
+        Some(name) => name,
+        None => {
+          result.positional.push(arg.clone());
+          continue;
+        }
+      };
+      if let Some(eq) = name.find('=') {
+        result
+          .options
+          .push((name[..eq].to_owned(), name[eq + 1..].to_owned()));
+      } else if FLAGS.contains(&name) {
+        result.flags.push(name.to_owned());
+      } else {
+        let value = iter
+          .next()
+          .ok_or_else(|| usage_error(format!("missing value for --{}", name)))?;
+        result.options.push((name.to_owned(), value.clone()));
+      }
+    }
+    Ok(result)
+  }
+
+  // 同じオプションが複数あれば最後のものを使う
+  fn option(&self, name: &str) -> Option<&str> {
+    self
+      .options
+      .iter()
+      .rev()
+      .find(|(n, _)| n == name)
+      .map(|(_, v)| v.as_str())
+  }
+
+  fn options<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    self
+      .options
+      .iter()
+      .filter(move |(n, _)| n == name)
+      .map(|(_, v)| v.as_str())
+  }
+
+  fn required(&self, name: &str) -> Result<&str> {
+    self
+      .option(name)
+      .ok_or_else(|| usage_error(format!("missing --{}", name)))
+  }
+
+  fn flag(&self, name: &str) -> bool {
+    self.flags.iter().any(|f| f == name)
+  }
+
+  fn positional(&self, index: usize, name: &str) -> Result<&str> {
+    self
+      .positional
+      .get(index)
+      .map(|s| s.as_str())
+      .ok_or_else(|| usage_error(format!("missing <{}>", name)))
+  }
+
+  fn check_options(&self, allowed: &[&str]) -> Result<()> {
+    let unknown = self
+      .options
+      .iter()
+      .map(|(n, _)| n.as_str())
+      .chain(self.flags.iter().map(|f| f.as_str()))
+      .find(|n| !allowed.contains(n) && !["file", "format"].contains(n));
+    match unknown {
+      Some(name) => Err(usage_error(format!("unknown option: --{}", name))),
+      None => Ok(()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+  Table,
+  Json,
+}
+
+// JSON出力用の最小限の値
+enum Json {
+  Null,
+  Bool(bool),
+  // f64では2^53を超える値が丸められるため、IDは整数のまま出力する
+  Integer(u64),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+  fn string(value: &str) -> Self {
+    Json::String(value.to_owned())
+  }
+
+  fn optional(value: Option<&str>) -> Self {
+    value.map(Json::string).unwrap_or(Json::Null)
+  }
+}
+
+fn write_json_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in value.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(value) => write!(f, "{}", value),
+      Json::Integer(value) => write!(f, "{}", value),
+      Json::Number(value) => write!(f, "{}", value),
+      Json::String(value) => write_json_string(f, value),
+      Json::Array(values) => {
+        write!(f, "[")?;
+        for (i, value) in values.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", value)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(fields) => {
+        write!(f, "{{")?;
+        for (i, (name, value)) in fields.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_json_string(f, name)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+fn contacts_json<T, F>(list: &ContactList<T>, key: &'static str, value: F) -> Json
+where
+  F: Fn(&T) -> Json,
+{
+  Json::Array(
+    list
+      .iter()
+      .enumerate()
+      .map(|(i, item)| {
+        Json::Object(vec![
+          ("label", Json::String(item.label.to_string())),
+          ("primary", Json::Bool(list.primary_index() == Some(i))),
+          (key, value(&item.value)),
+        ])
+      })
+      .collect(),
+  )
+}
+
+fn address_json(address: &Address) -> Json {
+  Json::Object(vec![
+    ("postal_code", Json::String(address.postal_code.to_string())),
+    ("pref", Json::string(address.pref.kanji())),
+    ("address", Json::string(&address.address)),
+    ("building", Json::optional(address.building.as_deref())),
+  ])
+}
+
+fn entry_json(entry: &AddressEntry) -> Json {
+  let name = &entry.name;
+  let strings = |values: &std::collections::BTreeSet<String>| {
+    Json::Array(values.iter().map(|v| Json::string(v)).collect())
+  };
+  Json::Object(vec![
+    ("id", Json::Integer(entry.id.0)),
+    (
+      "name",
+      Json::Object(vec![
+        ("first_name", Json::string(&name.first_name)),
+        ("last_name", Json::string(&name.last_name)),
+        (
+          "first_name_kana",
+          Json::optional(name.first_name_kana.as_deref()),
+        ),
+        (
+          "last_name_kana",
+          Json::optional(name.last_name_kana.as_deref()),
+        ),
+      ]),
+    ),
+    (
+      "addresses",
+      contacts_json(entry.addresses(), "address", address_json),
+    ),
+    (
+      "phones",
+      contacts_json(&entry.phones, "number", |p| Json::String(p.to_string())),
+    ),
+    (
+      "emails",
+      contacts_json(&entry.emails, "address", |e| Json::string(e.as_str())),
+    ),
+    ("tags", strings(&entry.tags)),
+    ("groups", strings(&entry.groups)),
+  ])
+}
+
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+  let widths = headers
+    .iter()
+    .enumerate()
+    .map(|(i, header)| {
+      rows
+        .iter()
+        .map(|row| display_width(&row[i]))
+        .chain(std::iter::once(display_width(header)))
+        .max()
+        .unwrap()
+    })
+    .collect::<Vec<_>>();
+  let line = |cells: Vec<&str>| {
+    let line = cells
+      .iter()
+      .zip(&widths)
+      .map(|(cell, width)| pad(cell, *width))
+      .collect::<Vec<_>>()
+      .join("  ");
+    format!("{}\n", line.trim_end())
+  };
+  let mut text = line(headers.to_vec());
+  for row in rows {
+    text.push_str(&line(row.iter().map(|c| c.as_str()).collect()));
+  }
+  text
+}
+
+fn entry_row(entry: &AddressEntry) -> Vec<String> {
+  let address = entry.address();
+  vec![
+    entry.id.0.to_string(),
+    entry.name.full_name(),
+    address.postal_code.to_string(),
+    format!(
+      "{}{}{}",
+      address.pref.kanji(),
+      address.address,
+      address
+        .building
+        .as_ref()
+        .map(|b| format!(" {}", b))
+        .unwrap_or_default()
+    ),
+    entry
+      .phones
+      .primary()
+      .map(|p| p.to_string())
+      .unwrap_or_default(),
+    entry
+      .emails
+      .primary()
+      .map(|e| e.to_string())
+      .unwrap_or_default(),
+  ]
+}
+
+const ENTRY_HEADERS: [&str; 6] = ["ID", "NAME", "POSTAL CODE", "ADDRESS", "PHONE", "EMAIL"];
+
+fn entries_output(entries: &[&AddressEntry], format: OutputFormat) -> String {
+  match format {
+    OutputFormat::Table => table(
+      &ENTRY_HEADERS,
+      &entries.iter().map(|e| entry_row(e)).collect::<Vec<_>>(),
+    ),
+    OutputFormat::Json => format!(
+      "{}\n",
+      Json::Array(entries.iter().map(|e| entry_json(e)).collect())
+    ),
+  }
+}
+
+fn entry_output(entry: &AddressEntry, format: OutputFormat) -> String {
+  match format {
+    OutputFormat::Table => {
+      let mut rows = vec![
+        ("id", entry.id.0.to_string()),
+        ("name", entry.name.full_name()),
+      ];
+      if entry.name.first_name_kana.is_some() || entry.name.last_name_kana.is_some() {
+        rows.push(("reading", entry.name.reading()));
+      }
+      let primary = |list_primary: Option<usize>, i: usize| {
+        if list_primary == Some(i) {
+          " (primary)"
+        } else {
+          ""
+        }
+      };
+      for (i, item) in entry.addresses().iter().enumerate() {
+        let address = &item.value;
+        rows.push((
+          "address",
+          format!(
+            "[{}] 〒{} {}{}{}{}",
+            item.label,
+            address.postal_code,
+            address.pref.kanji(),
+            address.address,
+            address
+              .building
+              .as_ref()
+              .map(|b| format!(" {}", b))
+              .unwrap_or_default(),
+            primary(entry.addresses().primary_index(), i)
+          ),
+        ));
+      }
+      for (i, item) in entry.phones.iter().enumerate() {
+        rows.push((
+          "phone",
+          format!(
+            "[{}] {}{}",
+            item.label,
+            item.value,
+            primary(entry.phones.primary_index(), i)
+          ),
+        ));
+      }
+      for (i, item) in entry.emails.iter().enumerate() {
+        rows.push((
+          "email",
+          format!(
+            "[{}] {}{}",
+            item.label,
+            item.value,
+            primary(entry.emails.primary_index(), i)
+          ),
+        ));
+      }
+      if !entry.tags.is_empty() {
+        rows.push((
+          "tags",
+          entry.tags.iter().cloned().collect::<Vec<_>>().join(", "),
+        ));
+      }
+      if !entry.groups.is_empty() {
+        rows.push((
+          "groups",
+          entry.groups.iter().cloned().collect::<Vec<_>>().join(", "),
+        ));
+      }
+      let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+      rows
+        .iter()
+        .map(|(name, value)| format!("{}  {}\n", pad(name, width), value))
+        .collect()
+    }
+    OutputFormat::Json => format!("{}\n", entry_json(entry)),
+  }
+}
+
+fn parse_id(value: &str) -> Result<AddressEntryId> {
+  value
+    .parse::<u64>()
+    .map(AddressEntryId::new)
+    .map_err(|_| usage_error(format!("invalid id: {}", value)))
+}
+
+fn parse_encoding(args: &Args) -> Result<CsvEncoding> {
+  match args.option("encoding").unwrap_or("utf8") {
+    "utf8" | "utf-8" => Ok(CsvEncoding::Utf8),
+    "sjis" | "shift_jis" => Ok(CsvEncoding::ShiftJis),
+    other => Err(usage_error(format!("unknown encoding: {}", other))),
+  }
+}
+
+// --type がなければ拡張子から判断する
+fn file_type<'a>(
+  args: &'a Args,
+  path: Option<&'a str>,
+  default: Option<&'a str>,
+) -> Result<&'a str> {
+  let extension = path
+    .and_then(|p| Path::new(p).extension())
+    .and_then(|e| e.to_str())
+    .map(|e| match e {
+      "vcf" | "vcard" => "vcard",
+      e => e,
+    });
+  args
+    .option("type")
+    .or(extension)
+    .or(default)
+    .ok_or_else(|| usage_error("missing --type"))
+}
+
+fn load(path: &str) -> Result<AddressBook> {
+  if Path::new(path).exists() {
+    AddressBook::load(path).map_err(|e| anyhow!("failed to load {}: {}", path, e))
+  } else {
+    Ok(AddressBook::new("address_book"))
+  }
+}
+
+fn find<'a>(address_book: &'a AddressBook, id: &AddressEntryId) -> Result<&'a AddressEntry> {
+  address_book
+    .get(id)
+    .ok_or_else(|| anyhow!("address entry not found: {}", id.0))
+}
+
+fn build_entry(args: &Args, id: AddressEntryId) -> Result<AddressEntry> {
+  let name = PersonName::new(args.required("first-name")?, args.required("last-name")?).with_kana(
+    args.option("first-name-kana").unwrap_or(""),
+    args.option("last-name-kana").unwrap_or(""),
+  );
+  let address = Address::new(
+    args.required("postal-code")?.parse()?,
+    args.required("pref")?.parse()?,
+    args.required("address")?,
+    args.option("building"),
+  );
+  let mut entry = AddressEntry::new(id, name, address);
+  for phone in args.options("phone") {
+    let phone = phone.parse::<PhoneNumber>()?;
+    let label = match phone.kind() {
+      PhoneNumberKind::Mobile => ContactLabel::Mobile,
+      _ => ContactLabel::Home,
+    };
+    entry.phones.push(label, phone);
+  }
+  for email in args.options("email") {
+    entry.emails.push(ContactLabel::Home, email.parse()?);
+  }
+  for tag in args.options("tag") {
+    entry.add_tag(tag);
+  }
+  Ok(entry)
+}
+
+fn execute(args: &[String], out: &mut dyn Write) -> Result<i32> {
+  // オプションはコマンドの前後どちらにも置ける
+  let mut args = Args::parse(args)?;
+  if args.flag("help") || args.positional.is_empty() {
+    writeln!(out, "{}", USAGE)?;
+    return Ok(if args.flag("help") {
+      EXIT_SUCCESS
+    } else {
+      EXIT_USAGE
+    });
+  }
+  let command = args.positional.remove(0);
+  let file = args
+    .option("file")
+    .map(|f| f.to_owned())
+    .or_else(|| std::env::var("ADDRESS_BOOK_FILE").ok())
+    .unwrap_or_else(|| DEFAULT_FILE.to_owned());
+  let format = match args.option("format").unwrap_or("table") {
+    "table" => OutputFormat::Table,
+    "json" => OutputFormat::Json,
+    other => return Err(usage_error(format!("unknown format: {}", other))),
+  };
+
+  match command.as_str() {
+    "add" => {
+      args.check_options(&[
+        "first-name",
+        "last-name",
+        "first-name-kana",
+        "last-name-kana",
+        "postal-code",
+        "pref",
+        "address",
+        "building",
+        "phone",
+        "email",
+        "tag",
+      ])?;
+      let mut address_book = load(&file)?;
+      let id = address_book.next_entry_id()?;
+      let entry = build_entry(&args, id)?;
+      address_book.add_entry(entry.clone())?;
+      address_book.save(&file)?;
+      match format {
+        OutputFormat::Table => writeln!(out, "added {}", entry.id.0)?,
+        OutputFormat::Json => write!(out, "{}", entry_output(&entry, format))?,
+      }
+    }
+    "list" => {
+      args.check_options(&["sort", "tag"])?;
+      let address_book = load(&file)?;
+      let mut query = Query::new();
+      if let Some(tag) = args.option("tag") {
+        query = query.filter(Condition::tag(tag));
+      }
+      query = match args.option("sort").unwrap_or("id") {
+        "id" => query.sort_by(SortKey::Id, SortOrder::Asc),
+        "reading" => query.sort_by(SortKey::Reading, SortOrder::Asc),
+        other => return Err(usage_error(format!("unknown sort key: {}", other))),
+      };
+      let result = query.execute(&address_book);
+      write!(out, "{}", entries_output(&result.entries, format))?;
+    }
+    "show" => {
+      args.check_options(&[])?;
+      let id = parse_id(args.positional(0, "id")?)?;
+      let address_book = load(&file)?;
+      write!(out, "{}", entry_output(find(&address_book, &id)?, format))?;
+    }
+    "remove" => {
+      args.check_options(&[])?;
+      let id = parse_id(args.positional(0, "id")?)?;
+      let mut address_book = load(&file)?;
+      find(&address_book, &id)?;
+      let entry = address_book.remove_entry(id);
+      address_book.save(&file)?;
+      match format {
+        OutputFormat::Table => writeln!(out, "removed {}", entry.id.0)?,
+        OutputFormat::Json => write!(out, "{}", entry_output(&entry, format))?,
+      }
+    }
+    "search" => {
+      args.check_options(&[])?;
+      let text = args.positional(0, "text")?;
+      let address_book = load(&file)?;
+      let result = address_book.fuzzy_search(text, &FuzzyOptions::default());
+      match format {
+        OutputFormat::Table => {
+          let rows = result
+            .iter()
+            .map(|scored| {
+              let mut row = entry_row(scored.entry);
+              row.push(format!("{:.2}", scored.score));
+              row
+            })
+            .collect::<Vec<_>>();
+          let mut headers = ENTRY_HEADERS.to_vec();
+          headers.push("SCORE");
+          write!(out, "{}", table(&headers, &rows))?;
+        }
+        OutputFormat::Json => writeln!(
+          out,
+          "{}",
+          Json::Array(
+            result
+              .iter()
+              .map(|scored| {
+                Json::Object(vec![
+                  ("score", Json::Number(scored.score)),
+                  ("entry", entry_json(scored.entry)),
+                ])
+              })
+              .collect()
+          )
+        )?,
+      }
+    }
+    "import" => {
+      args.check_options(&["type", "encoding", "replace", "dry-run"])?;
+      let path = args.positional(0, "path")?;
+      let dry_run = args.flag("dry-run");
+      // 既存のエントリと同じIDの行やカードは --replace を指定した場合だけ置き換える
+      let replace_existing = args.flag("replace");
+      let mut address_book = load(&file)?;
+      let input = fs::read(path).map_err(|e| anyhow!("failed to read {}: {}", path, e))?;
+      let (imported, replaced, errors) = match file_type(&args, Some(path), None)? {
+        "csv" => {
+          let options = CsvImportOptions {
+            encoding: parse_encoding(&args)?,
+            dry_run,
+            replace_existing,
+            ..Default::default()
+          };
+          let report = address_book.import_csv(&input, &options)?;
+          let errors = report
+            .errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+          (report.imported, report.replaced, errors)
+        }
+        "vcard" => {
+          let input = String::from_utf8(input)?;
+          // dry_runでは保存しないので、取り込んだ結果は捨てられる
+          let options = VCardImportOptions { replace_existing };
+          let report = address_book.import_vcards(&input, &options);
+          let errors = report.errors.iter().map(|e| e.to_string()).collect();
+          (report.imported, report.replaced, errors)
+        }
+        other => return Err(usage_error(format!("unknown type: {}", other))),
+      };
+      if !dry_run {
+        address_book.save(&file)?;
+      }
+      match format {
+        OutputFormat::Table => {
+          let verb = if dry_run { "would import" } else { "imported" };
+          writeln!(out, "{} {} entries", verb, imported.len())?;
+          if !replaced.is_empty() {
+            let verb = if dry_run { "would replace" } else { "replaced" };
+            writeln!(out, "{} {} entries", verb, replaced.len())?;
+          }
+          for error in &errors {
+            writeln!(out, "error: {}", error)?;
+          }
+        }
+        OutputFormat::Json => writeln!(
+          out,
+          "{}",
+          Json::Object(vec![
+            ("dry_run", Json::Bool(dry_run)),
+            (
+              "imported",
+              Json::Array(imported.iter().map(|id| Json::Integer(id.0)).collect())
+            ),
+            (
+              "replaced",
+              Json::Array(replaced.iter().map(|id| Json::Integer(id.0)).collect())
+            ),
+            (
+              "errors",
+              Json::Array(errors.iter().map(|e| Json::string(e)).collect())
+            ),
+          ])
+        )?,
+      }
+      if !errors.is_empty() {
+        return Ok(EXIT_FAILURE);
+      }
+    }
+    "export" => {
+      args.check_options(&["type", "encoding", "mask", "salt"])?;
+      let path = args
+        .positional
+        .first()
+        .map(|p| p.as_str())
+        .filter(|p| *p != "-");
+      let mut address_book = load(&file)?;
+      // 外部に渡す場合は氏名・住所などの個人情報をマスクする
+      if args.flag("mask") {
+        let policy = MaskingPolicy::default().with_salt(args.required("salt")?)?;
+        address_book = address_book.masked(&policy);
+      }
+      let content = match file_type(&args, path, Some("csv"))? {
+        "csv" => address_book.export_csv(&Default::default(), parse_encoding(&args)?)?,
+        "vcard" => address_book.export_vcards(VCardVersion::V4).into_bytes(),
+        "toml" => address_book.to_toml()?.into_bytes(),
+        other => return Err(usage_error(format!("unknown type: {}", other))),
+      };
+      match path {
+        Some(path) => {
+          fs::write(path, content).map_err(|e| anyhow!("failed to write {}: {}", path, e))?
+        }
+        None => out.write_all(&content)?,
+      }
+    }
+    other => return Err(usage_error(format!("unknown command: {}", other))),
+  }
+  Ok(EXIT_SUCCESS)
+}
+
+// sandbox-rs address 以降の引数を受け取り、終了コードを返す
+pub fn run(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+  match execute(args, out) {
+    Ok(code) => code,
+    Err(e) => {
+      let _ = writeln!(err, "error: {}", e);
+      if e.downcast_ref::<UsageError>().is_some() {
+        let _ = writeln!(err, "{}", USAGE);
+        EXIT_USAGE
+      } else {
+        EXIT_FAILURE
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sandbox-rs-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+  }
+
+  fn run_with(file: &Path, args: &[&str]) -> (i32, String, String) {
+    let mut args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    args.push(format!("--file={}", file.display()));
+    let mut out = vec![];
+    let mut err = vec![];
+    let code = run(&args, &mut out, &mut err);
+    (
+      code,
+      String::from_utf8(out).unwrap(),
+      String::from_utf8(err).unwrap(),
+    )
+  }
+
+  fn add(file: &Path, first_name: &str, last_name: &str) -> (i32, String, String) {
+    run_with(
+      file,
+      &[
+        "add",
+        "--first-name",
+        first_name,
+        "--last-name",
+        last_name,
+        "--postal-code",
+        "105-0011",
+        "--pref",
+        "東京都",
+        "--address",
+        "港区芝公園4-2-8",
+        "--phone",
+        "090-1234-5678",
+        "--tag",
+        "friend",
+      ],
+    )
+  }
+
+  #[test]
+  fn test_add_list_show_remove() {
+    let file = temp_path("crud.toml");
+    let _ = fs::remove_file(&file);
+
+    assert_eq!(
+      add(&file, "太郎", "山本"),
+      (EXIT_SUCCESS, "added 1\n".to_owned(), String::new())
+    );
+    assert_eq!(add(&file, "Hanako", "Yamada").1, "added 2\n");
+
+    let (code, out, _) = run_with(&file, &["list"]);
+    assert_eq!(code, EXIT_SUCCESS);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("ID  NAME"));
+    assert!(lines[1].starts_with("1   山本 太郎"));
+    assert!(lines[2].contains("Hanako Yamada"));
+
+    let (code, out, _) = run_with(&file, &["--format", "json", "show", "1"]);
+    assert_eq!(code, EXIT_SUCCESS);
+    assert!(out.starts_with(
+      r#"{"id":1,"name":{"first_name":"太郎","last_name":"山本","first_name_kana":null"#
+    ));
+    assert!(
+      out.contains(r#""phones":[{"label":"mobile","primary":true,"number":"090-1234-5678"}]"#)
+    );
+    assert!(out.contains(r#""tags":["friend"]"#));
+
+    assert_eq!(run_with(&file, &["remove", "1"]).1, "removed 1\n");
+    let (code, _, err) = run_with(&file, &["show", "1"]);
+    assert_eq!(code, EXIT_FAILURE);
+    assert_eq!(err, "error: address entry not found: 1\n");
+    assert_eq!(
+      run_with(&file, &["list", "--format=json"])
+        .1
+        .matches("\"id\"")
+        .count(),
+      1
+    );
+  }
+
+  #[test]
+  fn test_search_import_export() {
+    let file = temp_path("io.toml");
+    let exported = temp_path("io.vcf");
+    let _ = fs::remove_file(&file);
+    add(&file, "Taro", "Yamamoto");
+
+    let (code, out, _) = run_with(&file, &["search", "yamamoto"]);
+    assert_eq!(code, EXIT_SUCCESS);
+    assert!(out.lines().nth(1).unwrap().ends_with("1.00"));
+
+    assert_eq!(
+      run_with(&file, &["export", exported.to_str().unwrap()]).0,
+      EXIT_SUCCESS
+    );
+    let (code, out, _) = run_with(
+      &file,
+      &[
+        "import",
+        exported.to_str().unwrap(),
+        "--replace",
+        "--dry-run",
+      ],
+    );
+    assert_eq!(
+      (code, out.as_str()),
+      (
+        EXIT_SUCCESS,
+        "would import 0 entries\nwould replace 1 entries\n"
+      )
+    );
+    // UIDが一致するエントリは --replace を指定した場合だけ置き換えられる
+    let before = AddressBook::load(&file).unwrap();
+    let (code, out, _) = run_with(&file, &["import", exported.to_str().unwrap()]);
+    assert_eq!(code, EXIT_FAILURE);
+    assert_eq!(
+      out,
+      "imported 0 entries\nerror: card 0 (line 1): address entry already exists: 1\n"
+    );
+    assert_eq!(
+      run_with(&file, &["import", exported.to_str().unwrap(), "--replace"]).1,
+      "imported 0 entries\nreplaced 1 entries\n"
+    );
+    let after = AddressBook::load(&file).unwrap();
+    assert_eq!(after.len(), 1);
+    assert_eq!(
+      after.get(&AddressEntryId::new(1)),
+      before.get(&AddressEntryId::new(1))
+    );
+
+    let (code, out, _) = run_with(&file, &["export", "--type", "csv"]);
+    assert_eq!(code, EXIT_SUCCESS);
+    assert_eq!(out.lines().count(), 2);
+    assert!(out.contains("Yamamoto"));
+    let (_, out, _) = run_with(&file, &["export", "--mask", "--salt", "vendor"]);
+    assert!(out.contains("105-0011"));
+    assert!(!out.contains("Yamamoto"));
+    let (code, _, err) = run_with(&file, &["export", "--mask"]);
+    assert_eq!(code, EXIT_USAGE);
+    assert!(err.starts_with("error: missing --salt"));
+  }
+
+  #[test]
+  fn test_large_ids() {
+    let file = temp_path("large_ids.toml");
+    let exported = temp_path("large_ids.vcf");
+    let _ = fs::remove_file(&file);
+    add(&file, "Taro", "Yamamoto");
+    run_with(&file, &["export", exported.to_str().unwrap()]);
+    // Snowflake形式のID。2^53を超え、f64では表せない
+    let vcard = fs::read_to_string(&exported)
+      .unwrap()
+      .replace("UID:1", "UID:1438290375819542529");
+    fs::write(&exported, vcard).unwrap();
+
+    let _ = fs::remove_file(&file);
+    let (code, out, _) = run_with(
+      &file,
+      &["--format", "json", "import", exported.to_str().unwrap()],
+    );
+    assert_eq!(code, EXIT_SUCCESS);
+    assert_eq!(
+      out,
+      "{\"dry_run\":false,\"imported\":[1438290375819542529],\"replaced\":[],\"errors\":[]}\n"
+    );
+    let (_, out, _) = run_with(&file, &["--format", "json", "show", "1438290375819542529"]);
+    assert!(out.starts_with(r#"{"id":1438290375819542529,"#));
+  }
+
+  #[test]
+  fn test_usage_errors() {
+    let file = temp_path("usage.toml");
+    let (code, _, err) = run_with(&file, &["add", "--first-name", "Taro"]);
+    assert_eq!(code, EXIT_USAGE);
+    assert!(err.starts_with("error: missing --last-name\nusage:"));
+    assert_eq!(run_with(&file, &["show", "x"]).0, EXIT_USAGE);
+    assert_eq!(run_with(&file, &["list", "--limit", "1"]).0, EXIT_USAGE);
+    assert_eq!(run_with(&file, &["unknown"]).0, EXIT_USAGE);
+    assert_eq!(run_with(&file, &[]).0, EXIT_USAGE);
+    assert_eq!(run_with(&file, &["--help"]).0, EXIT_SUCCESS);
+    // 値の検証エラーは実行時エラー
+    let (code, _, _) = run_with(
+      &file,
+      &[
+        "add",
+        "--first-name",
+        "Taro",
+        "--last-name",
+        "Yamamoto",
+        "--postal-code",
+        "x",
+        "--pref",
+        "東京都",
+        "--address",
+        "港区",
+      ],
+    );
+    assert_eq!(code, EXIT_FAILURE);
+  }
+}
@@ -0,0 +1,371 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::postal_code::normalize_digits;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContactLabel {
+  Home,
+  Work,
+  Billing,
+  Mobile,
+  Other(String),
+}
+
+impl fmt::Display for ContactLabel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ContactLabel::Home => write!(f, "home"),
+      ContactLabel::Work => write!(f, "work"),
+      ContactLabel::Billing => write!(f, "billing"),
+      ContactLabel::Mobile => write!(f, "mobile"),
+      ContactLabel::Other(label) => write!(f, "{}", label),
+    }
+  }
+}
+
+impl From<&str> for ContactLabel {
+  fn from(value: &str) -> Self {
+    match value.trim().to_lowercase().as_str() {
+      "home" => ContactLabel::Home,
+      "work" => ContactLabel::Work,
+      "billing" => ContactLabel::Billing,
+      "mobile" | "cell" => ContactLabel::Mobile,
+      _ => ContactLabel::Other(value.trim().to_owned()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Labeled<T> {
+  pub label: ContactLabel,
+  pub value: T,
+}
+
+// ラベル付きの連絡先の一覧。最初に追加したものが主連絡先になる
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactList<T> {
+  items: Vec<Labeled<T>>,
+  primary: Option<usize>,
+}
+
+impl<T> Default for ContactList<T> {
+  fn default() -> Self {
+    Self {
+      items: vec![],
+      primary: None,
+    }
+  }
+}
+
+impl<T> ContactList<T> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with(mut self, label: ContactLabel, value: T) -> Self {
+    self.push(label, value);
+    self
+  }
+
+  pub fn push(&mut self, label: ContactLabel, value: T) -> usize {
+    self.items.push(Labeled { label, value });
+    let index = self.items.len() - 1;
+    if self.primary.is_none() {
+      self.primary = Some(index);
+    }
+    index
+  }
+
+  // 主連絡先を削除した場合は先頭のものが主連絡先になる
+  pub fn remove(&mut self, index: usize) -> Result<Labeled<T>> {
+    if index >= self.items.len() {
+      return Err(anyhow!("contact index out of range: {}", index));
+    }
+    let removed = self.items.remove(index);
+    self.primary = match self.primary {
+      _ if self.items.is_empty() => None,
+      Some(primary) if primary == index => Some(0),
+      Some(primary) if primary > index => Some(primary - 1),
+      primary => primary,
+    };
+    Ok(removed)
+  }
+
+  pub fn set_primary(&mut self, index: usize) -> Result<()> {
+    if index >= self.items.len() {
+      return Err(anyhow!("contact index out of range: {}", index));
+    }
+    self.primary = Some(index);
+    Ok(())
+  }
+
+  pub fn primary_index(&self) -> Option<usize> {
+    self.primary
+  }
+
+  pub fn primary(&self) -> Option<&T> {
+    self.primary.map(|i| &self.items[i].value)
+  }
+
+  pub fn primary_mut(&mut self) -> Option<&mut T> {
+    match self.primary {
+      Some(i) => Some(&mut self.items[i].value),
+      None => None,
+    }
+  }
+
+  pub fn get(&self, index: usize) -> Option<&Labeled<T>> {
+    self.items.get(index)
+  }
+
+  pub fn find_by_label(&self, label: &ContactLabel) -> Option<&T> {
+    self
+      .items
+      .iter()
+      .find(|item| item.label == *label)
+      .map(|item| &item.value)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Labeled<T>> {
+    self.items.iter()
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Labeled<T>> {
+    self.items.iter_mut()
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhoneNumberKind {
+  Landline,
+  Mobile,
+  IpPhone,
+  TollFree,
+  Navi,
+}
+
+// 日本国内の電話番号。数字のみで保持する。Debug は masking.rs でマスクする
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+  pub fn new(value: &str) -> Result<Self> {
+    let normalized = normalize_digits(value);
+    let mut digits = normalized
+      .chars()
+      .filter(|c| !matches!(c, '-' | ' ' | '　' | '(' | ')' | '（' | '）'))
+      .collect::<String>();
+    if let Some(rest) = digits.strip_prefix("+81") {
+      digits = format!("0{}", rest.trim_start_matches('0'));
+    }
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+      return Err(anyhow!("invalid phone number: {}", value));
+    }
+    let expected_len = match Self::kind_of(&digits) {
+      Some(PhoneNumberKind::Mobile) | Some(PhoneNumberKind::IpPhone) => 11,
+      Some(PhoneNumberKind::TollFree) if digits.starts_with("0800") => 11,
+      Some(_) => 10,
+      None => return Err(anyhow!("invalid phone number: {}", value)),
+    };
+    if digits.len() != expected_len {
+      return Err(anyhow!("invalid phone number: {}", value));
+    }
+    Ok(Self(digits))
+  }
+
+  fn kind_of(digits: &str) -> Option<PhoneNumberKind> {
+    let kind = match digits {
+      d if d.starts_with("0120") || d.starts_with("0800") => PhoneNumberKind::TollFree,
+      d if d.starts_with("0570") => PhoneNumberKind::Navi,
+      d if ["070", "080", "090"].iter().any(|p| d.starts_with(p)) => PhoneNumberKind::Mobile,
+      d if d.starts_with("050") => PhoneNumberKind::IpPhone,
+      d if d.starts_with('0') && !d.starts_with("00") => PhoneNumberKind::Landline,
+      _ => return None,
+    };
+    Some(kind)
+  }
+
+  pub fn kind(&self) -> PhoneNumberKind {
+    Self::kind_of(&self.0).unwrap()
+  }
+
+  pub fn digits(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for PhoneNumber {
+  // 市外局番の桁数は地域ごとに異なるため、固定電話は東京(03)・大阪(06)以外は区切らない
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let d = &self.0;
+    match self.kind() {
+      PhoneNumberKind::Mobile | PhoneNumberKind::IpPhone => {
+        write!(f, "{}-{}-{}", &d[..3], &d[3..7], &d[7..])
+      }
+      PhoneNumberKind::TollFree | PhoneNumberKind::Navi => {
+        write!(f, "{}-{}-{}", &d[..4], &d[4..7], &d[7..])
+      }
+      PhoneNumberKind::Landline if d.starts_with("03") || d.starts_with("06") => {
+        write!(f, "{}-{}-{}", &d[..2], &d[2..6], &d[6..])
+      }
+      PhoneNumberKind::Landline => write!(f, "{}", d),
+    }
+  }
+}
+
+impl FromStr for PhoneNumber {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Self::new(s)
+  }
+}
+
+// Debug は masking.rs でマスクする
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct EmailAddress(String);
+
+impl EmailAddress {
+  // RFC 5322 のうち実用上よく使われる dot-atom 形式のみ受け付ける。ドメイン部は小文字に揃える
+  pub fn new(value: &str) -> Result<Self> {
+    let invalid = || anyhow!("invalid email address: {}", value);
+    let value = value.trim();
+    let (local, domain) = value.rsplit_once('@').ok_or_else(invalid)?;
+    let local_ok = !local.is_empty()
+      && local.len() <= 64
+      && local.split('.').all(|part| {
+        !part.is_empty()
+          && part
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+      });
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let domain_ok = domain.len() <= 255
+      && labels.len() >= 2
+      && labels.iter().all(|label| {
+        !label.is_empty()
+          && label.len() <= 63
+          && !label.starts_with('-')
+          && !label.ends_with('-')
+          && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+      })
+      && labels
+        .last()
+        .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()));
+    if !local_ok || !domain_ok {
+      return Err(invalid());
+    }
+    Ok(Self(format!("{}@{}", local, domain.to_lowercase())))
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for EmailAddress {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl FromStr for EmailAddress {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Self::new(s)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_contact_list_primary() {
+    let mut list = ContactList::new()
+      .with(ContactLabel::Home, "a")
+      .with(ContactLabel::Work, "b")
+      .with(ContactLabel::Billing, "c");
+    assert_eq!(list.primary(), Some(&"a"));
+    list.set_primary(2).unwrap();
+    assert_eq!(list.primary(), Some(&"c"));
+    list.remove(0).unwrap();
+    assert_eq!(list.primary(), Some(&"c"));
+    list.remove(1).unwrap();
+    assert_eq!(list.primary(), Some(&"b"));
+    assert!(list.set_primary(5).is_err());
+    list.remove(0).unwrap();
+    assert_eq!(list.primary(), None);
+    assert_eq!(ContactLabel::from("Work"), ContactLabel::Work);
+    assert_eq!(
+      ContactLabel::from("school"),
+      ContactLabel::Other("school".to_owned())
+    );
+  }
+
+  #[test]
+  fn test_phone_number() {
+    let mobile = PhoneNumber::new("０９０－１２３４－５６７８").unwrap();
+    assert_eq!(mobile.kind(), PhoneNumberKind::Mobile);
+    assert_eq!(mobile.to_string(), "090-1234-5678");
+    assert_eq!(PhoneNumber::new("+81 90 1234 5678").unwrap(), mobile);
+    assert_eq!(
+      PhoneNumber::new("(03) 1234-5678").unwrap().to_string(),
+      "03-1234-5678"
+    );
+    assert_eq!(
+      PhoneNumber::new("0120-123-456").unwrap().kind(),
+      PhoneNumberKind::TollFree
+    );
+    assert_eq!(
+      PhoneNumber::new("0800-123-4567").unwrap().to_string(),
+      "0800-123-4567"
+    );
+    assert_eq!(
+      PhoneNumber::new("0467-12-3456").unwrap().digits(),
+      "0467123456"
+    );
+    assert!(PhoneNumber::new("090-1234-567").is_err());
+    assert!(PhoneNumber::new("03-1234-56789").is_err());
+    assert!(PhoneNumber::new("123-4567-8901").is_err());
+    assert!(PhoneNumber::new("090-abcd-5678").is_err());
+  }
+
+  #[test]
+  fn test_email_address() {
+    assert_eq!(
+      EmailAddress::new(" Junichi.Kato@Example.COM ")
+        .unwrap()
+        .as_str(),
+      "Junichi.Kato@example.com"
+    );
+    assert!(EmailAddress::new("a+tag@sub.example.co.jp").is_ok());
+    for invalid in &[
+      "",
+      "plain",
+      "@example.com",
+      "a@",
+      "a@localhost",
+      "a..b@example.com",
+      ".a@example.com",
+      "a@-example.com",
+      "a@example.c0m",
+      "a b@example.com",
+    ] {
+      assert!(EmailAddress::new(invalid).is_err(), "{}", invalid);
+    }
+  }
+}
@@ -0,0 +1,611 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use encoding_rs::SHIFT_JIS;
+
+use crate::address_book::id_generator::checked_id;
+use crate::address_book::{
+  Address, AddressBook, AddressEntry, AddressEntryField, AddressEntryId, ContactLabel,
+  EmailAddress, PersonName, PhoneNumber, PhoneNumberKind, PostalCode, Prefecture,
+};
+
+// RFC 4180 準拠の最小限のCSVパーサ
+
+pub fn parse_records(input: &str) -> Result<Vec<Vec<String>>> {
+  let mut records = vec![];
+  let mut record = vec![];
+  let mut field = String::new();
+  let mut in_quotes = false;
+  let mut quoted = false;
+  let mut line = 1;
+  let mut chars = input.trim_start_matches('\u{FEFF}').chars().peekable();
+  while let Some(c) = chars.next() {
+    if in_quotes {
+      match c {
+        '"' if chars.peek() == Some(&'"') => {
+          chars.next();
+          field.push('"');
+        }
+        '"' => in_quotes = false,
+        '\n' => {
+          line += 1;
+          field.push(c);
+        }
+        _ => field.push(c),
+      }
+      continue;
+    }
+    match c {
+      '"' if field.is_empty() && !quoted => {
+        in_quotes = true;
+        quoted = true;
+      }
+      '"' => return Err(anyhow!("unexpected quote at line {}", line)),
+      ',' => {
+        record.push(std::mem::take(&mut field));
+        quoted = false;
+      }
+      '\r' if chars.peek() == Some(&'\n') => {}
+      '\n' | '\r' => {
+        record.push(std::mem::take(&mut field));
+        records.push(std::mem::take(&mut record));
+        quoted = false;
+        line += 1;
+      }
+      _ if quoted => return Err(anyhow!("unexpected character after quote at line {}", line)),
+      _ => field.push(c),
+    }
+  }
+  if in_quotes {
+    return Err(anyhow!("unterminated quote at line {}", line));
+  }
+  if !field.is_empty() || !record.is_empty() || quoted {
+    record.push(field);
+    records.push(record);
+  }
+  Ok(records)
+}
+
+pub fn write_field(field: &str) -> String {
+  if field.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_owned()
+  }
+}
+
+pub fn write_record<S: AsRef<str>>(fields: &[S]) -> String {
+  let mut line = fields
+    .iter()
+    .map(|f| write_field(f.as_ref()))
+    .collect::<Vec<_>>()
+    .join(",");
+  line.push_str("\r\n");
+  line
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvEncoding {
+  Utf8,
+  ShiftJis,
+}
+
+impl CsvEncoding {
+  pub fn decode(&self, bytes: &[u8]) -> Result<String> {
+    match self {
+      CsvEncoding::Utf8 => Ok(String::from_utf8(bytes.to_vec())?),
+      CsvEncoding::ShiftJis => {
+        let (text, had_errors) = SHIFT_JIS.decode_without_bom_handling(bytes);
+        if had_errors {
+          return Err(anyhow!("invalid Shift_JIS byte sequence"));
+        }
+        Ok(text.into_owned())
+      }
+    }
+  }
+
+  pub fn encode(&self, text: &str) -> Result<Vec<u8>> {
+    match self {
+      CsvEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+      CsvEncoding::ShiftJis => {
+        let (bytes, _, had_errors) = SHIFT_JIS.encode(text);
+        if had_errors {
+          return Err(anyhow!("text cannot be represented in Shift_JIS"));
+        }
+        Ok(bytes.into_owned())
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+  Id,
+  Field(AddressEntryField),
+}
+
+impl fmt::Display for CsvColumn {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CsvColumn::Id => write!(f, "id"),
+      CsvColumn::Field(field) => write!(f, "{}", field),
+    }
+  }
+}
+
+// ヘッダー名とフィールドの対応。エクスポート時はこの順で列を出力する
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+  columns: Vec<(String, CsvColumn)>,
+}
+
+impl Default for ColumnMapping {
+  fn default() -> Self {
+    Self::new()
+      .map("id", CsvColumn::Id)
+      .map("first_name", CsvColumn::Field(AddressEntryField::FirstName))
+      .map("last_name", CsvColumn::Field(AddressEntryField::LastName))
+      .map(
+        "first_name_kana",
+        CsvColumn::Field(AddressEntryField::FirstNameKana),
+      )
+      .map(
+        "last_name_kana",
+        CsvColumn::Field(AddressEntryField::LastNameKana),
+      )
+      .map(
+        "postal_code",
+        CsvColumn::Field(AddressEntryField::PostalCode),
+      )
+      .map("pref", CsvColumn::Field(AddressEntryField::Pref))
+      .map("address", CsvColumn::Field(AddressEntryField::Address))
+      .map("building", CsvColumn::Field(AddressEntryField::Building))
+      .map("phone", CsvColumn::Field(AddressEntryField::Phone))
+      .map("email", CsvColumn::Field(AddressEntryField::Email))
+  }
+}
+
+impl ColumnMapping {
+  pub fn new() -> Self {
+    Self { columns: vec![] }
+  }
+
+  pub fn map(mut self, header: &str, column: CsvColumn) -> Self {
+    self.columns.retain(|(_, c)| *c != column);
+    self.columns.push((header.to_owned(), column));
+    self
+  }
+
+  fn header_of(&self, column: CsvColumn) -> Option<&str> {
+    self
+      .columns
+      .iter()
+      .find(|(_, c)| *c == column)
+      .map(|(h, _)| h.as_str())
+  }
+
+  fn value_of(column: CsvColumn, entry: &AddressEntry) -> String {
+    match column {
+      CsvColumn::Id => entry.id.0.to_string(),
+      CsvColumn::Field(field) => field.get(entry).unwrap_or_default(),
+    }
+  }
+}
+
+const REQUIRED_FIELDS: [AddressEntryField; 5] = [
+  AddressEntryField::FirstName,
+  AddressEntryField::LastName,
+  AddressEntryField::PostalCode,
+  AddressEntryField::Pref,
+  AddressEntryField::Address,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvImportOptions {
+  pub mapping: ColumnMapping,
+  pub encoding: CsvEncoding,
+  // trueの場合は検証のみ行い、AddressBookを変更しない
+  pub dry_run: bool,
+  // trueの場合はid列が既存のエントリと一致する行でそのエントリを置き換える。falseならその行はエラー
+  pub replace_existing: bool,
+}
+
+impl Default for CsvImportOptions {
+  fn default() -> Self {
+    Self {
+      mapping: ColumnMapping::default(),
+      encoding: CsvEncoding::Utf8,
+      dry_run: false,
+      replace_existing: false,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvRowError {
+  // ヘッダー行を1行目とした行番号
+  pub row: usize,
+  pub message: String,
+}
+
+impl fmt::Display for CsvRowError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "row {}: {}", self.row, self.message)
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CsvImportReport {
+  // 新しく追加したエントリ
+  pub imported: Vec<AddressEntryId>,
+  // replace_existing で置き換えた既存のエントリ
+  pub replaced: Vec<AddressEntryId>,
+  pub errors: Vec<CsvRowError>,
+}
+
+pub(crate) fn build_entry<F>(values: F, id: AddressEntryId) -> Result<AddressEntry>
+where
+  F: Fn(CsvColumn) -> Option<String>,
+{
+  let field = |field: AddressEntryField| values(CsvColumn::Field(field)).unwrap_or_default();
+  let missing = REQUIRED_FIELDS
+    .iter()
+    .filter(|f| field(**f).trim().is_empty())
+    .map(|f| f.to_string())
+    .collect::<Vec<_>>();
+  if !missing.is_empty() {
+    return Err(anyhow!("missing value: {}", missing.join(", ")));
+  }
+  let building = field(AddressEntryField::Building);
+  let mut entry = AddressEntry::new(
+    id,
+    PersonName::new(
+      &field(AddressEntryField::FirstName),
+      &field(AddressEntryField::LastName),
+    )
+    .with_kana(
+      &field(AddressEntryField::FirstNameKana),
+      &field(AddressEntryField::LastNameKana),
+    ),
+    Address::new(
+      PostalCode::new(&field(AddressEntryField::PostalCode))?,
+      Prefecture::parse(&field(AddressEntryField::Pref))?,
+      &field(AddressEntryField::Address),
+      Some(building.as_str()).filter(|b| !b.is_empty()),
+    ),
+  );
+  let phone = field(AddressEntryField::Phone);
+  if !phone.trim().is_empty() {
+    let phone = PhoneNumber::new(&phone)?;
+    let label = match phone.kind() {
+      PhoneNumberKind::Mobile => ContactLabel::Mobile,
+      _ => ContactLabel::Home,
+    };
+    entry.phones.push(label, phone);
+  }
+  let email = field(AddressEntryField::Email);
+  if !email.trim().is_empty() {
+    entry
+      .emails
+      .push(ContactLabel::Home, EmailAddress::new(&email)?);
+  }
+  Ok(entry)
+}
+
+impl AddressBook {
+  pub fn export_csv(&self, mapping: &ColumnMapping, encoding: CsvEncoding) -> Result<Vec<u8>> {
+    let mut text = write_record(
+      &mapping
+        .columns
+        .iter()
+        .map(|(header, _)| header.as_str())
+        .collect::<Vec<_>>(),
+    );
+    for entry in self.iter() {
+      text.push_str(&write_record(
+        &mapping
+          .columns
+          .iter()
+          .map(|(_, column)| ColumnMapping::value_of(*column, entry))
+          .collect::<Vec<_>>(),
+      ));
+    }
+    encoding.encode(&text)
+  }
+
+  // ファイル全体が読めない場合やヘッダーが足りない場合のみErrを返し、行ごとのエラーはレポートに集める
+  pub fn import_csv(
+    &mut self,
+    input: &[u8],
+    options: &CsvImportOptions,
+  ) -> Result<CsvImportReport> {
+    let text = options.encoding.decode(input)?;
+    let mut records = parse_records(&text)?.into_iter();
+    let headers = records
+      .next()
+      .ok_or_else(|| anyhow!("missing header row"))?
+      .into_iter()
+      .map(|h| h.trim().to_owned())
+      .collect::<Vec<_>>();
+    let position = |column: CsvColumn| {
+      let header = options.mapping.header_of(column)?;
+      headers.iter().position(|h| h == header)
+    };
+    let missing = REQUIRED_FIELDS
+      .iter()
+      .filter(|f| position(CsvColumn::Field(**f)).is_none())
+      .map(|f| {
+        options
+          .mapping
+          .header_of(CsvColumn::Field(*f))
+          .unwrap_or("?")
+          .to_owned()
+      })
+      .collect::<Vec<_>>();
+    if !missing.is_empty() {
+      return Err(anyhow!("missing columns: {}", missing.join(", ")));
+    }
+
+    let rules = self.validation_rules().clone();
+    let (mut report, rows) = self.with_id_sequence(options.dry_run, |next_id| {
+      let mut report = CsvImportReport::default();
+      let mut rows = vec![];
+      let mut seen = HashSet::new();
+      for (i, record) in records.enumerate() {
+        let row = i + 2;
+        if record.iter().all(|v| v.trim().is_empty()) {
+          continue;
+        }
+        let values = |column: CsvColumn| position(column).and_then(|p| record.get(p)).cloned();
+        let id = match values(CsvColumn::Id).filter(|v| !v.trim().is_empty()) {
+          Some(id) => id
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| anyhow!("invalid id: {}", id))
+            .and_then(checked_id),
+          None => next_id(),
+        };
+        match id
+          .and_then(|id| build_entry(values, id))
+          .and_then(|e| e.validate(&rules).map(|_| e))
+          .and_then(|e| {
+            if seen.insert(e.id.clone()) {
+              Ok(e)
+            } else {
+              Err(anyhow!("duplicate id in file: {}", e.id.0))
+            }
+          }) {
+          Ok(entry) => rows.push((row, entry)),
+          Err(e) => report.errors.push(CsvRowError {
+            row,
+            message: e.to_string(),
+          }),
+        }
+      }
+      (report, rows)
+    });
+    let mut entries = vec![];
+    for (row, entry) in rows {
+      match (self.contains(&entry.id), options.replace_existing) {
+        (false, _) => report.imported.push(entry.id.clone()),
+        (true, true) => report.replaced.push(entry.id.clone()),
+        (true, false) => {
+          report.errors.push(CsvRowError {
+            row,
+            message: format!("address entry already exists: {}", entry.id.0),
+          });
+          continue;
+        }
+      }
+      entries.push(entry);
+    }
+    report.errors.sort_by_key(|e| e.row);
+    if !options.dry_run {
+      self.transaction("import_csv", |book| {
+        entries.into_iter().for_each(|e| {
+          book.put_entry(e);
+        });
+        Ok(())
+      })?;
+    }
+    Ok(report)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_records() {
+    let records = parse_records("a,\"b,c\",\"d\"\"e\"\r\n1,,\"multi\nline\"\n").unwrap();
+    assert_eq!(
+      records,
+      vec![
+        vec!["a".to_owned(), "b,c".to_owned(), "d\"e".to_owned()],
+        vec!["1".to_owned(), "".to_owned(), "multi\nline".to_owned()],
+      ]
+    );
+    assert!(parse_records("\"abc").is_err());
+    assert!(parse_records("ab\"c").is_err());
+  }
+
+  #[test]
+  fn test_write_record() {
+    let line = write_record(&["a", "b,c", "d\"e", ""]);
+    assert_eq!(line, "a,\"b,c\",\"d\"\"e\",\r\n");
+    assert_eq!(
+      parse_records(&line).unwrap(),
+      vec![vec!["a", "b,c", "d\"e", ""]]
+    );
+  }
+
+  fn address_book() -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        AddressEntry::new(
+          AddressEntryId::new(1),
+          PersonName::new("純一", "加藤"),
+          Address::new(
+            PostalCode::new("111-0001").unwrap(),
+            Prefecture::Tokyo,
+            "港区芝公園1-2",
+            Some("ほげ\"ビル\", 1F"),
+          ),
+        ),
+        AddressEntry::new(
+          AddressEntryId::new(2),
+          PersonName::new("Taro", "Yamamoto"),
+          Address::new(
+            PostalCode::new("530-0001").unwrap(),
+            Prefecture::Osaka,
+            "大阪市北区梅田",
+            None,
+          ),
+        ),
+      ])
+      .unwrap();
+    address_book
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for encoding in &[CsvEncoding::Utf8, CsvEncoding::ShiftJis] {
+      let bytes = address_book()
+        .export_csv(&ColumnMapping::default(), *encoding)
+        .unwrap();
+      let mut imported = AddressBook::new("imported");
+      let options = CsvImportOptions {
+        encoding: *encoding,
+        ..CsvImportOptions::default()
+      };
+      let report = imported.import_csv(&bytes, &options).unwrap();
+      assert!(report.errors.is_empty(), "{:?}", report.errors);
+      assert_eq!(imported.len(), 2);
+      let entry = imported.get(&AddressEntryId::new(1)).unwrap();
+      assert_eq!(entry.name.last_name, "加藤");
+      assert_eq!(
+        entry.address().building.as_deref(),
+        Some("ほげ\"ビル\", 1F")
+      );
+      assert_eq!(
+        imported
+          .get(&AddressEntryId::new(2))
+          .unwrap()
+          .address()
+          .building,
+        None
+      );
+    }
+    assert!(CsvEncoding::ShiftJis.encode("🍣").is_err());
+  }
+
+  #[test]
+  fn test_import_duplicate_and_existing_ids() {
+    let input = "\
+id,first_name,last_name,postal_code,pref,address
+2,花子,山田,105-0011,東京都,港区芝公園
+5,次郎,高橋,530-0001,大阪府,梅田
+5,三郎,高橋,530-0001,大阪府,梅田
+9223372036854775808,四郎,高橋,530-0001,大阪府,梅田
+";
+    let mut address_book = address_book();
+    let report = address_book
+      .import_csv(input.as_bytes(), &CsvImportOptions::default())
+      .unwrap();
+    assert_eq!(report.imported, vec![AddressEntryId::new(5)]);
+    assert!(report.replaced.is_empty());
+    assert_eq!(
+      report
+        .errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>(),
+      vec![
+        "row 2: address entry already exists: 2",
+        "row 4: duplicate id in file: 5",
+        "row 5: address entry id out of range: 9223372036854775808",
+      ]
+    );
+    assert_eq!(
+      address_book
+        .get(&AddressEntryId::new(2))
+        .unwrap()
+        .name
+        .first_name,
+      "Taro"
+    );
+
+    let options = CsvImportOptions {
+      replace_existing: true,
+      ..CsvImportOptions::default()
+    };
+    let report = address_book.import_csv(input.as_bytes(), &options).unwrap();
+    assert!(report.imported.is_empty());
+    assert_eq!(
+      report.replaced,
+      vec![AddressEntryId::new(2), AddressEntryId::new(5)]
+    );
+    assert_eq!(report.errors.len(), 2);
+    assert_eq!(
+      address_book
+        .get(&AddressEntryId::new(2))
+        .unwrap()
+        .name
+        .first_name,
+      "花子"
+    );
+  }
+
+  #[test]
+  fn test_import_with_mapping_and_dry_run() {
+    let input = "\
+氏,名,〒,都道府県,住所,備考
+山田,花子,１０５－００１１,東京都,港区芝公園,
+鈴木,一郎,invalid,大阪府,梅田,
+佐藤,,530-0001,大阪府,梅田,
+高橋,次郎,530-0001,大阪府,梅田\t1,
+";
+    let mapping = ColumnMapping::new()
+      .map("名", CsvColumn::Field(AddressEntryField::FirstName))
+      .map("氏", CsvColumn::Field(AddressEntryField::LastName))
+      .map("〒", CsvColumn::Field(AddressEntryField::PostalCode))
+      .map("都道府県", CsvColumn::Field(AddressEntryField::Pref))
+      .map("住所", CsvColumn::Field(AddressEntryField::Address));
+    let mut address_book = address_book();
+    let mut options = CsvImportOptions {
+      mapping,
+      dry_run: true,
+      ..CsvImportOptions::default()
+    };
+    let report = address_book.import_csv(input.as_bytes(), &options).unwrap();
+    assert_eq!(report.imported, vec![AddressEntryId::new(3)]);
+    assert_eq!(
+      report.errors.iter().map(|e| e.row).collect::<Vec<_>>(),
+      vec![3, 4, 5]
+    );
+    assert_eq!(report.errors[1].message, "missing value: name.first_name");
+    assert_eq!(
+      report.errors[2].message,
+      "invalid address entry: address.address: contains a disallowed character: '\\t'"
+    );
+    assert_eq!(address_book.len(), 2);
+
+    options.dry_run = false;
+    address_book.import_csv(input.as_bytes(), &options).unwrap();
+    assert_eq!(address_book.len(), 3);
+    let entry = address_book.get(&AddressEntryId::new(3)).unwrap();
+    assert_eq!(entry.address().postal_code.to_string(), "105-0011");
+
+    let err = address_book
+      .import_csv("名,氏\n".as_bytes(), &CsvImportOptions::default())
+      .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "missing columns: first_name, last_name, postal_code, pref, address"
+    );
+  }
+}
@@ -0,0 +1,527 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::history::Change;
+use crate::address_book::fuzzy::{edit_similarity, ngram_similarity};
+use crate::address_book::kana::phonetic_key;
+use crate::address_book::postal_code::normalize_digits;
+use crate::address_book::{
+  Address, AddressBook, AddressEntry, AddressEntryField, AddressEntryId, ContactList, FieldChange,
+  PersonName,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateOptions {
+  pub threshold: f64,
+  pub ngram_size: usize,
+}
+
+impl Default for DuplicateOptions {
+  fn default() -> Self {
+    Self {
+      threshold: 0.8,
+      ngram_size: 2,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicatePair {
+  pub a: AddressEntryId,
+  pub b: AddressEntryId,
+  pub score: f64,
+}
+
+// ids は AddressBook の挿入順、pairs はスコアの高い順
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateCluster {
+  pub ids: Vec<AddressEntryId>,
+  pub pairs: Vec<DuplicatePair>,
+}
+
+impl DuplicateCluster {
+  pub fn score(&self) -> f64 {
+    self.pairs.iter().map(|p| p.score).fold(0.0, f64::max)
+  }
+}
+
+fn name_key(entry: &AddressEntry) -> String {
+  phonetic_key(&format!(
+    "{}{}",
+    entry.name.last_name, entry.name.first_name
+  ))
+}
+
+fn address_key(address: &Address) -> String {
+  normalize_digits(&format!(
+    "{}{}",
+    address.address,
+    address.building.as_deref().unwrap_or("")
+  ))
+  .chars()
+  .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '丁' | '目' | '番' | '地' | '号'))
+  .collect::<String>()
+  .to_lowercase()
+}
+
+fn shares_contact<T: PartialEq>(a: &ContactList<T>, b: &ContactList<T>) -> bool {
+  a.iter().any(|x| b.iter().any(|y| x.value == y.value))
+}
+
+// 読みを揃えた氏名と郵便番号が一致すれば1.0。それ以外は氏名・住所・郵便番号の重み付き平均
+pub fn duplicate_score(a: &AddressEntry, b: &AddressEntry, ngram_size: usize) -> f64 {
+  let (name_a, name_b) = (name_key(a), name_key(b));
+  let same_postal_code = a.address().postal_code == b.address().postal_code;
+  if name_a == name_b && same_postal_code {
+    return 1.0;
+  }
+  let name =
+    (edit_similarity(&name_a, &name_b) + ngram_similarity(&name_a, &name_b, ngram_size)) / 2.0;
+  let address = ngram_similarity(
+    &address_key(a.address()),
+    &address_key(b.address()),
+    ngram_size,
+  );
+  let postal_code = if same_postal_code { 1.0 } else { 0.0 };
+  let score = 0.5 * name + 0.3 * address + 0.2 * postal_code;
+  // 電話番号かメールアドレスが共通なら同一人物の可能性が高い
+  if name >= 0.8 && (shares_contact(&a.phones, &b.phones) || shares_contact(&a.emails, &b.emails)) {
+    score.max(0.95)
+  } else {
+    score
+  }
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+  let mut root = i;
+  while parents[root] != root {
+    root = parents[root];
+  }
+  let mut i = i;
+  while parents[i] != root {
+    let next = parents[i];
+    parents[i] = root;
+    i = next;
+  }
+  root
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+  // マージ先の値を残す
+  KeepTarget,
+  // マージ元の値で上書きする
+  TakeSource,
+  // 長い方を採る(同じ長さならマージ先)
+  PreferLonger,
+  // 値が食い違えばマージを中止する
+  Reject,
+}
+
+// 空の値は常にもう一方で埋める。ポリシーは両方に値があって異なる場合にだけ使う
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeOptions {
+  pub default_policy: ConflictPolicy,
+  overrides: HashMap<AddressEntryField, ConflictPolicy>,
+}
+
+impl Default for MergeOptions {
+  fn default() -> Self {
+    Self::new(ConflictPolicy::KeepTarget)
+  }
+}
+
+impl MergeOptions {
+  pub fn new(default_policy: ConflictPolicy) -> Self {
+    Self {
+      default_policy,
+      overrides: HashMap::new(),
+    }
+  }
+
+  pub fn with(mut self, field: AddressEntryField, policy: ConflictPolicy) -> Self {
+    self.overrides.insert(field, policy);
+    self
+  }
+
+  pub fn policy(&self, field: AddressEntryField) -> ConflictPolicy {
+    self
+      .overrides
+      .get(&field)
+      .copied()
+      .unwrap_or(self.default_policy)
+  }
+
+  fn resolve(
+    &self,
+    field: AddressEntryField,
+    target: Option<String>,
+    source: Option<String>,
+  ) -> Result<Option<String>> {
+    let (target, source) = match (target, source) {
+      (Some(t), Some(s)) if !t.is_empty() && !s.is_empty() => (t, s),
+      (Some(t), _) if !t.is_empty() => return Ok(Some(t)),
+      (_, source) => return Ok(source.filter(|s| !s.is_empty())),
+    };
+    if target == source {
+      return Ok(Some(target));
+    }
+    Ok(Some(match self.policy(field) {
+      ConflictPolicy::KeepTarget => target,
+      ConflictPolicy::TakeSource => source,
+      ConflictPolicy::PreferLonger if source.chars().count() > target.chars().count() => source,
+      ConflictPolicy::PreferLonger => target,
+      ConflictPolicy::Reject => {
+        return Err(anyhow!(
+          "conflicting {}: {:?} and {:?}",
+          field,
+          target,
+          source
+        ))
+      }
+    }))
+  }
+}
+
+fn union<T: Clone + PartialEq>(target: &ContactList<T>, source: &ContactList<T>) -> ContactList<T> {
+  let mut list = target.clone();
+  for item in source.iter() {
+    if list.iter().all(|x| x.value != item.value) {
+      list.push(item.label.clone(), item.value.clone());
+    }
+  }
+  list
+}
+
+pub(crate) fn set_primary_value<T: PartialEq>(
+  list: &mut ContactList<T>,
+  value: Option<&T>,
+) -> Result<()> {
+  if let Some(index) = value.and_then(|v| list.iter().position(|x| x.value == *v)) {
+    list.set_primary(index)?;
+  }
+  Ok(())
+}
+
+// source を target に統合したエントリを返す。IDは target のものを使う。
+// 主住所はフィールドごとにポリシーで決め、それ以外の住所・電話番号・メールアドレスとタグ・グループは和集合をとる
+pub fn merge_entries(
+  target: &AddressEntry,
+  source: &AddressEntry,
+  options: &MergeOptions,
+) -> Result<AddressEntry> {
+  let resolve =
+    |field: AddressEntryField| options.resolve(field, field.get(target), field.get(source));
+  let required = |field: AddressEntryField| -> Result<String> {
+    resolve(field)?.ok_or_else(|| anyhow!("missing {}", field))
+  };
+
+  let name = PersonName::new(
+    &required(AddressEntryField::FirstName)?,
+    &required(AddressEntryField::LastName)?,
+  )
+  .with_kana(
+    &resolve(AddressEntryField::FirstNameKana)?.unwrap_or_default(),
+    &resolve(AddressEntryField::LastNameKana)?.unwrap_or_default(),
+  );
+  let primary = Address::new(
+    required(AddressEntryField::PostalCode)?.parse()?,
+    required(AddressEntryField::Pref)?.parse()?,
+    &required(AddressEntryField::Address)?,
+    resolve(AddressEntryField::Building)?.as_deref(),
+  );
+  let mut addresses = union(target.addresses(), source.addresses());
+  let position = addresses.iter().position(|a| a.value == primary);
+  let index = match position {
+    Some(index) => index,
+    None => {
+      let label = target.addresses().get(0).unwrap().label.clone();
+      addresses.push(label, primary)
+    }
+  };
+  addresses.set_primary(index)?;
+  let mut merged = AddressEntry::with_addresses(target.id.clone(), name, addresses)?;
+
+  merged.phones = union(&target.phones, &source.phones);
+  let phone = resolve(AddressEntryField::Phone)?;
+  let phone = [target.phones.primary(), source.phones.primary()]
+    .iter()
+    .flatten()
+    .find(|p| Some(p.to_string()) == phone)
+    .cloned();
+  set_primary_value(&mut merged.phones, phone)?;
+
+  merged.emails = union(&target.emails, &source.emails);
+  let email = resolve(AddressEntryField::Email)?;
+  let email = [target.emails.primary(), source.emails.primary()]
+    .iter()
+    .flatten()
+    .find(|e| Some(e.to_string()) == email)
+    .cloned();
+  set_primary_value(&mut merged.emails, email)?;
+
+  merged.tags = target.tags.union(&source.tags).cloned().collect();
+  merged.groups = target.groups.union(&source.groups).cloned().collect();
+  Ok(merged)
+}
+
+// マージで消えたIDと統合先のID
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeRecord {
+  pub survivor: AddressEntryId,
+  pub merged: AddressEntryId,
+}
+
+impl AddressBook {
+  // 同じ郵便番号か同じ読みの氏名を持つエントリ同士だけを比較する
+  pub fn find_duplicates(&self, options: &DuplicateOptions) -> Vec<DuplicateCluster> {
+    let entries = self.iter().collect::<Vec<_>>();
+    let mut blocks: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+      blocks
+        .entry(format!("postal:{}", entry.address().postal_code.digits()))
+        .or_default()
+        .push(i);
+      blocks
+        .entry(format!("name:{}", phonetic_key(&entry.name.last_name)))
+        .or_default()
+        .push(i);
+    }
+
+    let mut compared = HashSet::new();
+    let mut pairs = vec![];
+    for block in blocks.values() {
+      for (n, &i) in block.iter().enumerate() {
+        for &j in &block[n + 1..] {
+          if !compared.insert((i, j)) {
+            continue;
+          }
+          let score = duplicate_score(entries[i], entries[j], options.ngram_size);
+          if score >= options.threshold {
+            pairs.push((i, j, score));
+          }
+        }
+      }
+    }
+
+    let mut parents = (0..entries.len()).collect::<Vec<_>>();
+    for &(i, j, _) in &pairs {
+      let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+      parents[a.max(b)] = a.min(b);
+    }
+    let mut clusters: Vec<DuplicateCluster> = vec![];
+    let mut cluster_of: HashMap<usize, usize> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+      let root = find(&mut parents, i);
+      if root == i && pairs.iter().all(|&(a, b, _)| a != i && b != i) {
+        continue;
+      }
+      let index = *cluster_of.entry(root).or_insert_with(|| {
+        clusters.push(DuplicateCluster {
+          ids: vec![],
+          pairs: vec![],
+        });
+        clusters.len() - 1
+      });
+      clusters[index].ids.push(entry.id.clone());
+    }
+    for (i, j, score) in pairs {
+      let index = cluster_of[&find(&mut parents, i)];
+      clusters[index].pairs.push(DuplicatePair {
+        a: entries[i.min(j)].id.clone(),
+        b: entries[i.max(j)].id.clone(),
+        score,
+      });
+    }
+    for cluster in clusters.iter_mut() {
+      cluster
+        .pairs
+        .sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    }
+    clusters.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal));
+    clusters
+  }
+
+  // merged を survivor に統合して merged を削除する。変更された survivor のフィールドを返す
+  pub fn merge_entries(
+    &mut self,
+    survivor: &AddressEntryId,
+    merged: &AddressEntryId,
+    options: &MergeOptions,
+  ) -> Result<Vec<FieldChange>> {
+    if survivor == merged {
+      return Err(anyhow!("cannot merge an entry into itself: {:?}", survivor));
+    }
+    let target = self
+      .get(survivor)
+      .ok_or_else(|| anyhow!("address entry not found: {:?}", survivor))?;
+    let source = self
+      .get(merged)
+      .ok_or_else(|| anyhow!("address entry not found: {:?}", merged))?;
+    let entry = merge_entries(target, source, options)?;
+    let changes = FieldChange::diff(target, &entry);
+    let record = MergeRecord {
+      survivor: survivor.clone(),
+      merged: merged.clone(),
+    };
+    self.transaction("merge_entries", |book| {
+      book.put_entry(entry);
+      book.remove_entry(record.merged.clone());
+      book.history.record(Change::Merge(record.clone()));
+      book.merges.push(record);
+      Ok(changes)
+    })
+  }
+
+  pub fn merge_history(&self) -> &[MergeRecord] {
+    &self.merges
+  }
+
+  // マージで消えたIDを現在の統合先までたどる
+  pub fn resolve_merged_id(&self, id: &AddressEntryId) -> Option<AddressEntryId> {
+    let mut id = id.clone();
+    for _ in 0..=self.merges.len() {
+      if self.contains(&id) {
+        return Some(id);
+      }
+      id = self
+        .merges
+        .iter()
+        .rev()
+        .find(|r| r.merged == id)?
+        .survivor
+        .clone();
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, ContactLabel};
+
+  fn entry(
+    id: u64,
+    first_name: &str,
+    last_name: &str,
+    postal_code: &str,
+    address: &str,
+  ) -> AddressEntry {
+    let mut entry = test_entry(id, first_name, last_name, postal_code);
+    entry.address_mut().address = address.to_owned();
+    entry
+  }
+
+  fn address_book() -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    let mut kana = entry(4, "じゅんいち", "かとう", "105-0011", "港区芝公園1-2-3");
+    kana
+      .phones
+      .push(ContactLabel::Mobile, "090-1234-5678".parse().unwrap());
+    kana.add_tag("vip");
+    address_book
+      .add_entries(&[
+        entry(1, "Junichi", "Kato", "105-0011", "港区芝公園1丁目2番3号"),
+        entry(2, "Taro", "Yamamoto", "530-0001", "大阪市北区梅田"),
+        entry(3, "Junichi", "Katou", "105-0011", "港区芝公園１－２－３"),
+        kana,
+        entry(5, "Hanako", "Yamada", "105-0011", "港区芝公園4"),
+      ])
+      .unwrap();
+    address_book
+  }
+
+  #[test]
+  fn test_find_duplicates() {
+    let address_book = address_book();
+    let clusters = address_book.find_duplicates(&DuplicateOptions::default());
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(
+      clusters[0].ids,
+      vec![
+        AddressEntryId::new(1),
+        AddressEntryId::new(3),
+        AddressEntryId::new(4)
+      ]
+    );
+    assert_eq!(clusters[0].score(), 1.0);
+    assert!(clusters[0].pairs.iter().all(|p| p.score >= 0.8));
+
+    let options = DuplicateOptions {
+      threshold: 1.1,
+      ..DuplicateOptions::default()
+    };
+    assert!(address_book.find_duplicates(&options).is_empty());
+  }
+
+  #[test]
+  fn test_merge_entries() {
+    let mut address_book = address_book();
+    address_book
+      .get_mut(&AddressEntryId::new(1))
+      .unwrap()
+      .emails
+      .push(ContactLabel::Home, "kato@example.com".parse().unwrap());
+
+    let options = MergeOptions::default().with(AddressEntryField::Address, ConflictPolicy::Reject);
+    assert!(address_book
+      .merge_entries(&AddressEntryId::new(1), &AddressEntryId::new(4), &options)
+      .is_err());
+    assert!(address_book
+      .merge_entries(&AddressEntryId::new(1), &AddressEntryId::new(1), &options)
+      .is_err());
+    assert_eq!(address_book.len(), 5);
+
+    let options = MergeOptions::new(ConflictPolicy::KeepTarget)
+      .with(AddressEntryField::Address, ConflictPolicy::PreferLonger);
+    let changes = address_book
+      .merge_entries(&AddressEntryId::new(1), &AddressEntryId::new(4), &options)
+      .unwrap();
+    assert_eq!(
+      changes.iter().map(|c| c.field).collect::<Vec<_>>(),
+      vec![AddressEntryField::Phone]
+    );
+    let entry = address_book.get(&AddressEntryId::new(1)).unwrap();
+    assert_eq!(entry.name.last_name, "Kato");
+    assert_eq!(entry.address().address, "港区芝公園1丁目2番3号");
+    assert_eq!(entry.addresses().len(), 2);
+    assert_eq!(entry.phones.len(), 1);
+    assert_eq!(entry.emails.len(), 1);
+    assert!(entry.has_tag("vip"));
+    assert!(!address_book.contains(&AddressEntryId::new(4)));
+
+    let options = MergeOptions::new(ConflictPolicy::TakeSource);
+    address_book
+      .merge_entries(&AddressEntryId::new(3), &AddressEntryId::new(1), &options)
+      .unwrap();
+    let entry = address_book.get(&AddressEntryId::new(3)).unwrap();
+    assert_eq!(entry.name.last_name, "Kato");
+    assert_eq!(entry.emails.primary().unwrap().as_str(), "kato@example.com");
+
+    assert_eq!(
+      address_book.merge_history(),
+      &[
+        MergeRecord {
+          survivor: AddressEntryId::new(1),
+          merged: AddressEntryId::new(4),
+        },
+        MergeRecord {
+          survivor: AddressEntryId::new(3),
+          merged: AddressEntryId::new(1),
+        },
+      ]
+    );
+    assert_eq!(
+      address_book.resolve_merged_id(&AddressEntryId::new(4)),
+      Some(AddressEntryId::new(3))
+    );
+    assert_eq!(
+      address_book.resolve_merged_id(&AddressEntryId::new(9)),
+      None
+    );
+  }
+}
@@ -0,0 +1,701 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use anyhow::Result;
+use toml::value::Table;
+use toml::Value;
+
+use crate::address_book::csv::{parse_records, write_record};
+use crate::address_book::persistence::{entry_from_toml, entry_to_toml};
+use crate::address_book::{
+  AddressBook, AddressEntry, AddressEntryField, AddressEntryId, FieldChange, Group,
+  DEFAULT_HISTORY_LIMIT,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressBookEvent {
+  EntryAdded(AddressEntry),
+  EntryRemoved(AddressEntryId),
+  // 再生のために変更後のエントリ全体も持つ
+  EntryUpdated {
+    entry: AddressEntry,
+    changes: Vec<FieldChange>,
+  },
+  // グループの定義の変更。メンバーの所属の変更はエントリのイベントになる
+  GroupCreated(Group),
+  GroupUpdated(Group),
+  GroupRemoved(String),
+}
+
+impl AddressBookEvent {
+  // グループのイベントではNone
+  pub fn entry_id(&self) -> Option<&AddressEntryId> {
+    match self {
+      AddressBookEvent::EntryAdded(entry) => Some(&entry.id),
+      AddressBookEvent::EntryRemoved(id) => Some(id),
+      AddressBookEvent::EntryUpdated { entry, .. } => Some(&entry.id),
+      AddressBookEvent::GroupCreated(_)
+      | AddressBookEvent::GroupUpdated(_)
+      | AddressBookEvent::GroupRemoved(_) => None,
+    }
+  }
+
+  fn kind(&self) -> &'static str {
+    match self {
+      AddressBookEvent::EntryAdded(_) => "entry_added",
+      AddressBookEvent::EntryRemoved(_) => "entry_removed",
+      AddressBookEvent::EntryUpdated { .. } => "entry_updated",
+      AddressBookEvent::GroupCreated(_) => "group_created",
+      AddressBookEvent::GroupUpdated(_) => "group_updated",
+      AddressBookEvent::GroupRemoved(_) => "group_removed",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventRecord {
+  // 1始まりの通し番号
+  pub sequence: u64,
+  pub timestamp: SystemTime,
+  pub actor: String,
+  pub event: AddressBookEvent,
+}
+
+impl AddressBook {
+  pub fn with_events(mut self) -> Self {
+    self.events.get_or_insert_with(Vec::new);
+    self
+  }
+
+  // 溜まったイベントを取り出す。記録していなければ空
+  pub fn take_events(&mut self) -> Vec<AddressBookEvent> {
+    self.events.as_mut().map(std::mem::take).unwrap_or_default()
+  }
+
+  pub(crate) fn emit(&mut self, before: Option<&AddressEntry>, after: Option<&AddressEntry>) {
+    let events = match self.events.as_mut() {
+      Some(events) => events,
+      None => return,
+    };
+    let event = match (before, after) {
+      (None, Some(after)) => AddressBookEvent::EntryAdded(after.clone()),
+      (Some(before), None) => AddressBookEvent::EntryRemoved(before.id.clone()),
+      (Some(before), Some(after)) if before != after => AddressBookEvent::EntryUpdated {
+        entry: after.clone(),
+        changes: FieldChange::diff(before, after),
+      },
+      _ => return,
+    };
+    events.push(event);
+  }
+
+  pub(crate) fn emit_group(&mut self, name: &str, before: Option<&Group>, after: Option<&Group>) {
+    let events = match self.events.as_mut() {
+      Some(events) => events,
+      None => return,
+    };
+    let event = match (before, after) {
+      (None, Some(after)) => AddressBookEvent::GroupCreated(after.clone()),
+      (Some(_), None) => AddressBookEvent::GroupRemoved(name.to_owned()),
+      (Some(before), Some(after)) if before != after => {
+        AddressBookEvent::GroupUpdated(after.clone())
+      }
+      _ => return,
+    };
+    events.push(event);
+  }
+
+  // 取り消しで復元したエントリも末尾に追加されるため、再生結果の並び順は元と異なることがある
+  pub fn apply_event(&mut self, event: &AddressBookEvent) -> Result<()> {
+    match event {
+      AddressBookEvent::EntryAdded(entry) => self.insert_entry(entry.clone()),
+      AddressBookEvent::EntryRemoved(id)
+      | AddressBookEvent::EntryUpdated {
+        entry: AddressEntry { id, .. },
+        ..
+      } if !self.contains(id) => Err(anyhow!("address entry not found: {:?}", id)),
+      AddressBookEvent::EntryRemoved(id) => {
+        self.remove_entry(id.clone());
+        Ok(())
+      }
+      AddressBookEvent::EntryUpdated { entry, .. } => {
+        self.put_entry(entry.clone());
+        Ok(())
+      }
+      AddressBookEvent::GroupCreated(group) if self.group(&group.name).is_some() => {
+        Err(anyhow!("duplicate group: {}", group.name))
+      }
+      AddressBookEvent::GroupUpdated(Group { name, .. }) | AddressBookEvent::GroupRemoved(name)
+        if self.group(name).is_none() =>
+      {
+        Err(anyhow!("group not found: {}", name))
+      }
+      AddressBookEvent::GroupCreated(group) | AddressBookEvent::GroupUpdated(group) => {
+        self.set_group(&group.name, Some(group.clone()));
+        Ok(())
+      }
+      AddressBookEvent::GroupRemoved(name) => {
+        self.set_group(name, None);
+        Ok(())
+      }
+    }
+  }
+}
+
+fn is_snapshot_point(interval: Option<u64>, len: u64) -> bool {
+  interval.is_some_and(|i| len.is_multiple_of(i))
+}
+
+fn group_to_toml(name: &str, description: Option<&str>) -> Result<String> {
+  let mut table = Table::new();
+  table.insert("name".to_owned(), Value::from(name));
+  if let Some(description) = description {
+    table.insert("description".to_owned(), Value::from(description));
+  }
+  Ok(toml::to_string(&Value::Table(table))?)
+}
+
+fn group_from_toml(table: &Table) -> Result<Group> {
+  let name = table
+    .get("name")
+    .and_then(|v| v.as_str())
+    .ok_or_else(|| anyhow!("missing name"))?;
+  Ok(Group {
+    name: name.to_owned(),
+    description: table
+      .get("description")
+      .and_then(|v| v.as_str())
+      .map(|d| d.to_owned()),
+  })
+}
+
+fn changes_to_toml(changes: &[FieldChange]) -> Value {
+  let changes = changes
+    .iter()
+    .map(|change| {
+      let mut table = Table::new();
+      table.insert("field".to_owned(), Value::from(change.field.to_string()));
+      if let Some(old_value) = &change.old_value {
+        table.insert("old_value".to_owned(), Value::from(old_value.as_str()));
+      }
+      if let Some(new_value) = &change.new_value {
+        table.insert("new_value".to_owned(), Value::from(new_value.as_str()));
+      }
+      Value::Table(table)
+    })
+    .collect();
+  Value::Array(changes)
+}
+
+fn changes_from_toml(value: Option<&Value>) -> Result<Vec<FieldChange>> {
+  let changes = match value {
+    Some(Value::Array(changes)) => changes.as_slice(),
+    Some(_) => return Err(anyhow!("changes must be an array")),
+    None => &[],
+  };
+  changes
+    .iter()
+    .map(|change| {
+      let string = |key: &str| {
+        change
+          .get(key)
+          .and_then(|v| v.as_str())
+          .map(|s| s.to_owned())
+      };
+      let field = string("field").unwrap_or_default();
+      let field = AddressEntryField::ALL
+        .iter()
+        .find(|f| f.to_string() == field)
+        .ok_or_else(|| anyhow!("unknown field: {}", field))?;
+      Ok(FieldChange {
+        field: *field,
+        old_value: string("old_value"),
+        new_value: string("new_value"),
+      })
+    })
+    .collect()
+}
+
+// sequence,timestamp(ミリ秒),actor,kind,id,payload(TOML) の1行1イベント。グループのイベントの id は空
+fn to_record(record: &EventRecord) -> Result<String> {
+  let millis = record
+    .timestamp
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis())
+    .unwrap_or(0);
+  let payload = match &record.event {
+    AddressBookEvent::EntryAdded(entry) => toml::to_string(&entry_to_toml(entry))?,
+    AddressBookEvent::EntryRemoved(_) => String::new(),
+    AddressBookEvent::EntryUpdated { entry, changes } => {
+      let mut table = Table::new();
+      table.insert("entry".to_owned(), entry_to_toml(entry));
+      table.insert("changes".to_owned(), changes_to_toml(changes));
+      toml::to_string(&Value::Table(table))?
+    }
+    AddressBookEvent::GroupCreated(group) | AddressBookEvent::GroupUpdated(group) => {
+      group_to_toml(&group.name, group.description.as_deref())?
+    }
+    AddressBookEvent::GroupRemoved(name) => group_to_toml(name, None)?,
+  };
+  Ok(write_record(&[
+    record.sequence.to_string(),
+    millis.to_string(),
+    record.actor.clone(),
+    record.event.kind().to_owned(),
+    record
+      .event
+      .entry_id()
+      .map(|id| id.0.to_string())
+      .unwrap_or_default(),
+    payload,
+  ]))
+}
+
+fn from_record(fields: &[String]) -> Result<EventRecord> {
+  if fields.len() != 6 {
+    return Err(anyhow!("expected 6 fields but got {}", fields.len()));
+  }
+  let number = |i: usize| {
+    fields[i]
+      .parse::<u64>()
+      .map_err(|_| anyhow!("invalid number: {}", fields[i]))
+  };
+  let id = match fields[4].as_str() {
+    "" => None,
+    _ => Some(AddressEntryId::new(number(4)?)),
+  };
+  let payload = || match fields[5].parse::<Value>()? {
+    Value::Table(table) => Ok(table),
+    _ => Err(anyhow!("payload must be a table")),
+  };
+  let event = match fields[3].as_str() {
+    "entry_added" => AddressBookEvent::EntryAdded(entry_from_toml(&payload()?)?),
+    "entry_removed" => {
+      AddressBookEvent::EntryRemoved(id.clone().ok_or_else(|| anyhow!("missing id"))?)
+    }
+    "entry_updated" => {
+      let payload = payload()?;
+      let entry = payload
+        .get("entry")
+        .and_then(|v| v.as_table())
+        .ok_or_else(|| anyhow!("missing entry"))?;
+      AddressBookEvent::EntryUpdated {
+        entry: entry_from_toml(entry)?,
+        changes: changes_from_toml(payload.get("changes"))?,
+      }
+    }
+    "group_created" => AddressBookEvent::GroupCreated(group_from_toml(&payload()?)?),
+    "group_updated" => AddressBookEvent::GroupUpdated(group_from_toml(&payload()?)?),
+    "group_removed" => AddressBookEvent::GroupRemoved(group_from_toml(&payload()?)?.name),
+    kind => return Err(anyhow!("unknown event: {}", kind)),
+  };
+  if event.entry_id() != id.as_ref() {
+    return Err(anyhow!("id mismatch: {}", fields[4]));
+  }
+  Ok(EventRecord {
+    sequence: number(0)?,
+    timestamp: UNIX_EPOCH + Duration::from_millis(number(1)?),
+    actor: fields[2].clone(),
+    event,
+  })
+}
+
+// イベントの追記専用ログ。追記時に現在の状態へ適用して検証し、一定件数ごとにスナップショットをとる
+#[derive(Debug)]
+pub struct EventLog {
+  records: Vec<EventRecord>,
+  head: AddressBook,
+  // 適用済みイベント数ごとの状態
+  snapshots: BTreeMap<u64, AddressBook>,
+  snapshot_interval: Option<u64>,
+  file: Option<File>,
+}
+
+impl EventLog {
+  pub fn new(name: &str) -> Self {
+    let mut snapshots = BTreeMap::new();
+    let head = AddressBook::new(name).with_history_limit(0);
+    snapshots.insert(0, head.clone());
+    Self {
+      records: vec![],
+      head,
+      snapshots,
+      snapshot_interval: Some(100),
+      file: None,
+    }
+  }
+
+  // 既存のログを読み込み、以降の追記をファイルにも書く。末尾の書きかけの行は切り捨てる
+  pub fn open<P: AsRef<Path>>(path: P, name: &str) -> Result<Self> {
+    let path = path.as_ref();
+    let mut content = match fs::read_to_string(path) {
+      Ok(content) => content,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+      Err(e) => return Err(e.into()),
+    };
+    let mut log = Self::new(name);
+    let mut torn = false;
+    let records = parse_records(&content).and_then(|records| {
+      records
+        .iter()
+        .map(|fields| from_record(fields))
+        .collect::<Result<Vec<_>>>()
+    });
+    let records = match records {
+      Ok(records) => records,
+      Err(_) if !content.is_empty() && !content.ends_with('\n') => {
+        content.truncate(content.rfind('\n').map(|i| i + 1).unwrap_or(0));
+        torn = true;
+        parse_records(&content)?
+          .iter()
+          .map(|fields| from_record(fields))
+          .collect::<Result<Vec<_>>>()?
+      }
+      Err(e) => return Err(e),
+    };
+    for (i, record) in records.into_iter().enumerate() {
+      if record.sequence != i as u64 + 1 {
+        return Err(anyhow!(
+          "record {}: unexpected sequence {}",
+          i + 1,
+          record.sequence
+        ));
+      }
+      log
+        .push(record)
+        .map_err(|e| anyhow!("record {}: {}", i + 1, e))?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    if torn {
+      file.set_len(content.len() as u64)?;
+    }
+    log.file = Some(file);
+    Ok(log)
+  }
+
+  // None の場合は自動でスナップショットをとらない
+  pub fn with_snapshot_interval(mut self, interval: Option<u64>) -> Self {
+    self.snapshot_interval = interval.filter(|i| *i > 0);
+    self
+  }
+
+  fn push(&mut self, record: EventRecord) -> Result<()> {
+    self.head.apply_event(&record.event)?;
+    self.records.push(record);
+    let len = self.len() as u64;
+    if is_snapshot_point(self.snapshot_interval, len) {
+      self.snapshots.insert(len, self.head.clone());
+    }
+    Ok(())
+  }
+
+  // 適用できないイベントが含まれる場合や書き込みに失敗した場合は何も追記しない。
+  // head に直接適用し、失敗したらトランザクションで元に戻す。最後のイベントの通し番号を返す
+  pub fn append(&mut self, actor: &str, events: Vec<AddressBookEvent>) -> Result<u64> {
+    let timestamp = SystemTime::now();
+    let records = events
+      .into_iter()
+      .enumerate()
+      .map(|(i, event)| EventRecord {
+        sequence: (self.len() + i + 1) as u64,
+        timestamp,
+        actor: actor.to_owned(),
+        event,
+      })
+      .collect::<Vec<_>>();
+    let interval = self.snapshot_interval;
+    let file = &mut self.file;
+    let snapshots = self.head.transaction("append", |head| {
+      let mut snapshots = Vec::new();
+      for record in &records {
+        head.apply_event(&record.event)?;
+        if is_snapshot_point(interval, record.sequence) {
+          snapshots.push((record.sequence, head.clone_outside_transaction()));
+        }
+      }
+      if let Some(file) = file.as_mut() {
+        let content = records.iter().map(to_record).collect::<Result<String>>()?;
+        file.write_all(content.as_bytes())?;
+        file.sync_data()?;
+      }
+      Ok(snapshots)
+    })?;
+    self.records.extend(records);
+    self.snapshots.extend(snapshots);
+    Ok(self.len() as u64)
+  }
+
+  pub fn len(&self) -> usize {
+    self.records.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.records.is_empty()
+  }
+
+  pub fn records(&self) -> &[EventRecord] {
+    &self.records
+  }
+
+  // 誰がいつどのエントリを変更したか
+  pub fn history_of(&self, id: &AddressEntryId) -> Vec<&EventRecord> {
+    self
+      .records
+      .iter()
+      .filter(|r| r.event.entry_id() == Some(id))
+      .collect()
+  }
+
+  pub fn state(&self) -> &AddressBook {
+    &self.head
+  }
+
+  pub fn snapshot(&mut self) -> u64 {
+    let len = self.len() as u64;
+    self.snapshots.insert(len, self.head.clone());
+    len
+  }
+
+  // 先頭から sequence 番目までのイベントを適用した状態
+  pub fn state_at(&self, sequence: u64) -> Result<AddressBook> {
+    if sequence > self.len() as u64 {
+      return Err(anyhow!(
+        "sequence out of range: {} (latest is {})",
+        sequence,
+        self.len()
+      ));
+    }
+    let (start, snapshot) = self.snapshots.range(..=sequence).next_back().unwrap();
+    let mut address_book = snapshot.clone();
+    for record in &self.records[*start as usize..sequence as usize] {
+      address_book.apply_event(&record.event)?;
+    }
+    address_book.set_history_limit(DEFAULT_HISTORY_LIMIT);
+    Ok(address_book)
+  }
+
+  pub fn state_at_time(&self, timestamp: SystemTime) -> Result<AddressBook> {
+    let sequence = self
+      .records
+      .iter()
+      .take_while(|r| r.timestamp <= timestamp)
+      .count();
+    self.state_at(sequence as u64)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, AddressEntryPatch};
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sandbox-rs-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let _ = fs::remove_file(&path);
+    path
+  }
+
+  fn ids(address_book: &AddressBook) -> Vec<u64> {
+    address_book.iter().map(|e| e.id.0).collect()
+  }
+
+  #[test]
+  fn test_events() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "Taro", "Kato", "111-0001"))
+      .unwrap();
+    assert!(address_book.take_events().is_empty());
+
+    let mut address_book = AddressBook::new("test").with_events();
+    address_book
+      .add_entries(&[
+        test_entry(1, "Taro", "Kato", "111-0001"),
+        test_entry(2, "Taro", "Yamamoto", "111-0001"),
+      ])
+      .unwrap();
+    address_book
+      .patch_entry(
+        &AddressEntryId::new(2),
+        &AddressEntryPatch::new().with_last_name("Suzuki"),
+      )
+      .unwrap();
+    // 変化のない更新とロールバックした変更はイベントにならない
+    address_book
+      .update_entry(&AddressEntryId::new(1), |_| {})
+      .unwrap();
+    let _ = address_book.transaction("failed", |book| {
+      book.remove_entry(AddressEntryId::new(1));
+      Err::<(), _>(anyhow!("abort"))
+    });
+    address_book.remove_entry(AddressEntryId::new(1));
+    address_book.undo().unwrap();
+
+    let events = address_book.take_events();
+    assert_eq!(
+      events
+        .iter()
+        .map(|e| (e.kind(), e.entry_id().unwrap().0))
+        .collect::<Vec<_>>(),
+      vec![
+        ("entry_added", 1),
+        ("entry_added", 2),
+        ("entry_updated", 2),
+        ("entry_removed", 1),
+        ("entry_added", 1),
+      ]
+    );
+    match &events[2] {
+      AddressBookEvent::EntryUpdated { changes, .. } => {
+        assert_eq!(changes[0].field, AddressEntryField::LastName);
+        assert_eq!(changes[0].new_value.as_deref(), Some("Suzuki"));
+      }
+      event => panic!("unexpected event: {:?}", event),
+    }
+    assert!(address_book.take_events().is_empty());
+
+    let mut log = EventLog::new("test").with_snapshot_interval(Some(2));
+    assert_eq!(log.append("alice", events).unwrap(), 5);
+    assert_eq!(ids(log.state()), vec![2, 1]);
+    assert!(log
+      .append(
+        "bob",
+        vec![AddressBookEvent::EntryRemoved(AddressEntryId::new(9))]
+      )
+      .is_err());
+    assert_eq!(log.len(), 5);
+
+    assert_eq!(ids(&log.state_at(0).unwrap()), Vec::<u64>::new());
+    assert_eq!(ids(&log.state_at(1).unwrap()), vec![1]);
+    assert_eq!(ids(&log.state_at(4).unwrap()), vec![2]);
+    let state = log.state_at(3).unwrap();
+    assert_eq!(state.find_by_last_name("Suzuki").count(), 1);
+    assert!(log.state_at(6).is_err());
+    assert_eq!(
+      log
+        .history_of(&AddressEntryId::new(2))
+        .iter()
+        .map(|r| (r.sequence, r.actor.as_str()))
+        .collect::<Vec<_>>(),
+      vec![(2, "alice"), (3, "alice")]
+    );
+    assert_eq!(
+      ids(&log.state_at_time(UNIX_EPOCH).unwrap()),
+      Vec::<u64>::new()
+    );
+    // スナップショットから復元した状態でも取り消しができる
+    let mut state = log.state_at(2).unwrap();
+    state
+      .add_entry(test_entry(3, "Taro", "Yamada", "111-0001"))
+      .unwrap();
+    assert!(state.can_undo());
+    state.undo().unwrap();
+    assert_eq!(ids(&state), vec![1, 2]);
+  }
+
+  #[test]
+  fn test_file_log() {
+    let path = temp_path("events.log");
+    let mut address_book = AddressBook::new("test").with_events();
+    let mut log = EventLog::open(&path, "test").unwrap();
+    address_book
+      .add_entry(test_entry(1, "Taro", "Kato", "111-0001"))
+      .unwrap();
+    address_book
+      .add_entry(test_entry(2, "Taro", "Yamamoto", "111-0001"))
+      .unwrap();
+    log.append("alice", address_book.take_events()).unwrap();
+    address_book
+      .patch_entry(
+        &AddressEntryId::new(1),
+        &AddressEntryPatch::new().with_building("ビル,1F"),
+      )
+      .unwrap();
+    address_book.remove_entry(AddressEntryId::new(2));
+    log.append("bob", address_book.take_events()).unwrap();
+    drop(log);
+
+    // 末尾の書きかけの行は捨てて、続きから追記できる
+    OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .and_then(|mut f| f.write_all(b"5,0,carol,entry_added,3"))
+      .unwrap();
+    let mut log = EventLog::open(&path, "test").unwrap();
+    assert_eq!(log.len(), 4);
+    assert_eq!(log.records()[3].actor, "bob");
+    assert_eq!(
+      log.state().get(&AddressEntryId::new(1)),
+      address_book.get(&AddressEntryId::new(1))
+    );
+    assert_eq!(ids(log.state()), vec![1]);
+    address_book
+      .add_entry(test_entry(3, "Taro", "Yamada", "111-0001"))
+      .unwrap();
+    log.append("carol", address_book.take_events()).unwrap();
+    drop(log);
+
+    let log = EventLog::open(&path, "test").unwrap();
+    assert_eq!(ids(log.state()), vec![1, 3]);
+    assert_eq!(ids(&log.state_at(2).unwrap()), vec![1, 2]);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_group_events() {
+    let path = temp_path("group_events.log");
+    let mut address_book = AddressBook::new("test").with_events();
+    let mut log = EventLog::open(&path, "test").unwrap();
+    address_book
+      .create_group("friends", Some("old friends"))
+      .unwrap();
+    address_book
+      .add_entry(test_entry(1, "Taro", "Kato", "111-0001"))
+      .unwrap();
+    address_book
+      .add_to_group(&AddressEntryId::new(1), "friends")
+      .unwrap();
+    let mut tagged = test_entry(2, "Taro", "Yamamoto", "111-0001");
+    tagged.groups.insert("customers".to_owned());
+    address_book.add_entry(tagged).unwrap();
+    address_book.rename_group("friends", "family").unwrap();
+    address_book.set_group_description("family", None).unwrap();
+    address_book.remove_group("customers");
+    log.append("alice", address_book.take_events()).unwrap();
+
+    // 適用できないイベントを含む追記は、途中まで適用した分も含めて取り消す
+    assert!(log
+      .append(
+        "bob",
+        vec![
+          AddressBookEvent::GroupCreated(Group::new("neighbors")),
+          AddressBookEvent::GroupRemoved("unknown".to_owned()),
+        ]
+      )
+      .is_err());
+    assert!(log.state().group("neighbors").is_none());
+    drop(log);
+
+    let log = EventLog::open(&path, "test").unwrap();
+    assert_eq!(
+      log.state().groups().collect::<Vec<_>>(),
+      address_book.groups().collect::<Vec<_>>()
+    );
+    assert_eq!(
+      log.state().iter().collect::<Vec<_>>(),
+      address_book.iter().collect::<Vec<_>>()
+    );
+    assert!(log
+      .records()
+      .iter()
+      .any(|r| r.event == AddressBookEvent::GroupRemoved("customers".to_owned())));
+    fs::remove_file(&path).unwrap();
+  }
+}
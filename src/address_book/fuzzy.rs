@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::address_book::kana::phonetic_key;
+use crate::address_book::{AddressBook, AddressEntry, PersonName};
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+  let a = a.chars().collect::<Vec<_>>();
+  let b = b.chars().collect::<Vec<_>>();
+  let mut prev = (0..=b.len()).collect::<Vec<_>>();
+  let mut cur = vec![0; b.len() + 1];
+  for i in 1..=a.len() {
+    cur[0] = i;
+    for j in 1..=b.len() {
+      let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+      cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+    }
+    std::mem::swap(&mut prev, &mut cur);
+  }
+  prev[b.len()]
+}
+
+pub fn edit_similarity(a: &str, b: &str) -> f64 {
+  let len = a.chars().count().max(b.chars().count());
+  if len == 0 {
+    return 1.0;
+  }
+  1.0 - edit_distance(a, b) as f64 / len as f64
+}
+
+// n が0の場合は1文字ずつの集合とする
+fn ngrams(s: &str, n: usize) -> HashSet<Vec<char>> {
+  let n = n.max(1);
+  let chars = s.chars().collect::<Vec<_>>();
+  if chars.len() < n {
+    return std::iter::once(chars).filter(|c| !c.is_empty()).collect();
+  }
+  chars.windows(n).map(|w| w.to_vec()).collect()
+}
+
+// n-gram集合のDice係数
+pub fn ngram_similarity(a: &str, b: &str, n: usize) -> f64 {
+  let a = ngrams(a, n);
+  let b = ngrams(b, n);
+  if a.is_empty() && b.is_empty() {
+    return 1.0;
+  }
+  let common = a.intersection(&b).count();
+  2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyOptions {
+  pub threshold: f64,
+  pub limit: Option<usize>,
+  pub ngram_size: usize,
+}
+
+impl Default for FuzzyOptions {
+  fn default() -> Self {
+    Self {
+      threshold: 0.6,
+      limit: None,
+      ngram_size: 2,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScoredEntry<'a> {
+  pub entry: &'a AddressEntry,
+  pub score: f64,
+}
+
+fn candidates(name: &PersonName) -> Vec<String> {
+  vec![
+    name.first_name.clone(),
+    name.last_name.clone(),
+    format!("{}{}", name.first_name, name.last_name),
+    format!("{}{}", name.last_name, name.first_name),
+  ]
+}
+
+// 読み(ローマ字)に揃えてから編集距離とn-gramの平均をとる
+pub fn name_score(query: &str, name: &PersonName, ngram_size: usize) -> f64 {
+  let query = phonetic_key(query);
+  candidates(name)
+    .iter()
+    .map(|candidate| {
+      let candidate = phonetic_key(candidate);
+      (edit_similarity(&query, &candidate) + ngram_similarity(&query, &candidate, ngram_size)) / 2.0
+    })
+    .fold(0.0, f64::max)
+}
+
+impl AddressBook {
+  pub fn fuzzy_search(&self, query: &str, options: &FuzzyOptions) -> Vec<ScoredEntry<'_>> {
+    let mut result = self
+      .iter()
+      .map(|entry| ScoredEntry {
+        entry,
+        score: name_score(query, &entry.name, options.ngram_size),
+      })
+      .filter(|scored| scored.score >= options.threshold)
+      .collect::<Vec<_>>();
+    result.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    if let Some(limit) = options.limit {
+      result.truncate(limit);
+    }
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, AddressEntryId};
+
+  #[test]
+  fn test_edit_distance() {
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("かとう", "かとお"), 1);
+    assert_eq!(ngram_similarity("yamada", "yamada", 2), 1.0);
+    assert_eq!(ngram_similarity("abc", "xyz", 2), 0.0);
+    assert_eq!(ngram_similarity("abc", "cab", 0), 1.0);
+    assert_eq!(ngram_similarity("", "", 0), 1.0);
+  }
+
+  #[test]
+  fn test_fuzzy_search() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        test_entry(1, "Junichi", "Kato", "111-0001"),
+        test_entry(2, "Taro", "Yamamoto", "111-0002"),
+        test_entry(3, "Hanako", "Yamada", "111-0003"),
+      ])
+      .unwrap();
+
+    let result = address_book.fuzzy_search("Yamda", &FuzzyOptions::default());
+    assert_eq!(result[0].entry.id, AddressEntryId::new(3));
+    assert!(result.iter().all(|s| s.entry.id != AddressEntryId::new(1)));
+
+    let result = address_book.fuzzy_search("かとう", &FuzzyOptions::default());
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].entry.id, AddressEntryId::new(1));
+    assert_eq!(result[0].score, 1.0);
+
+    let options = FuzzyOptions {
+      threshold: 0.0,
+      limit: Some(2),
+      ..FuzzyOptions::default()
+    };
+    let result = address_book.fuzzy_search("ヤマモト タロウ", &options);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[0].entry.id, AddressEntryId::new(2));
+
+    // ngram_size が0でもパニックしない
+    let options = FuzzyOptions {
+      ngram_size: 0,
+      ..FuzzyOptions::default()
+    };
+    let result = address_book.fuzzy_search("Yamda", &options);
+    assert_eq!(result[0].entry.id, AddressEntryId::new(3));
+  }
+}
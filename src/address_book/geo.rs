@@ -0,0 +1,334 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::csv::parse_records;
+use crate::address_book::{Address, AddressBook, AddressEntry, PostalCode};
+
+// 地球の平均半径(km)
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+// 緯度1度あたりの距離(km)
+const KM_PER_DEGREE: f64 = 111.195;
+// グリッドの1セルの大きさ(度)。約11km四方
+const CELL_DEGREES: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinate {
+  pub latitude: f64,
+  pub longitude: f64,
+}
+
+impl Coordinate {
+  pub fn new(latitude: f64, longitude: f64) -> Result<Self> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+      return Err(anyhow!("invalid coordinate: {}, {}", latitude, longitude));
+    }
+    Ok(Self {
+      latitude,
+      longitude,
+    })
+  }
+
+  // haversine公式による大円距離
+  pub fn distance_km(&self, other: &Coordinate) -> f64 {
+    let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (other.longitude - self.longitude).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+  }
+
+  fn cell(&self) -> (i32, i32) {
+    (
+      (self.latitude / CELL_DEGREES).floor() as i32,
+      (self.longitude / CELL_DEGREES).floor() as i32,
+    )
+  }
+}
+
+// 郵便番号ごとの代表点。セル単位のグリッドで近傍の郵便番号を引けるようにする
+#[derive(Debug, Clone, Default)]
+pub struct Gazetteer {
+  coordinates: HashMap<PostalCode, Coordinate>,
+  cells: HashMap<(i32, i32), Vec<PostalCode>>,
+}
+
+impl Gazetteer {
+  // 郵便番号,緯度,経度 のCSVを読み込む。先頭行は見出しでもよい
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let content = std::fs::read_to_string(path)?;
+    Self::from_csv_str(&content)
+  }
+
+  pub fn from_csv_str(content: &str) -> Result<Self> {
+    let mut gazetteer = Self::default();
+    for (i, record) in parse_records(content)?.into_iter().enumerate() {
+      if i == 0 && record.first().is_some_and(|c| c == "postal_code") {
+        continue;
+      }
+      if record.len() < 3 {
+        return Err(anyhow!("line {}: expected 3 columns", i + 1));
+      }
+      let postal_code =
+        PostalCode::new(&record[0]).map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+      let parse = |value: &str| {
+        value
+          .trim()
+          .parse::<f64>()
+          .map_err(|_| anyhow!("line {}: invalid number: {}", i + 1, value))
+      };
+      let coordinate = Coordinate::new(parse(&record[1])?, parse(&record[2])?)
+        .map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+      gazetteer.insert(postal_code, coordinate);
+    }
+    Ok(gazetteer)
+  }
+
+  pub fn insert(&mut self, postal_code: PostalCode, coordinate: Coordinate) {
+    if let Some(old) = self.coordinates.insert(postal_code.clone(), coordinate) {
+      if let Some(codes) = self.cells.get_mut(&old.cell()) {
+        codes.retain(|c| *c != postal_code);
+      }
+    }
+    self
+      .cells
+      .entry(coordinate.cell())
+      .or_default()
+      .push(postal_code);
+  }
+
+  pub fn len(&self) -> usize {
+    self.coordinates.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.coordinates.is_empty()
+  }
+
+  pub fn lookup(&self, postal_code: &PostalCode) -> Option<Coordinate> {
+    self.coordinates.get(postal_code).copied()
+  }
+
+  // 中心から半径内にある郵便番号と距離
+  pub fn within_km(&self, center: &Coordinate, radius_km: f64) -> Vec<(&PostalCode, f64)> {
+    let lat_cells = (radius_km / KM_PER_DEGREE / CELL_DEGREES).ceil();
+    // 高緯度ほど経度1度あたりの距離が短くなる
+    let cos = (center.latitude.abs() + lat_cells * CELL_DEGREES)
+      .min(89.0)
+      .to_radians()
+      .cos();
+    let lon_cells = (radius_km / (KM_PER_DEGREE * cos) / CELL_DEGREES).ceil();
+    // 列挙するセル数はi32に変換する前に浮動小数点数で見積もる。半径が無限大やNaNの場合も全件を見る
+    let span = (2.0 * lat_cells + 1.0) * (2.0 * lon_cells + 1.0);
+    let codes: Box<dyn Iterator<Item = &PostalCode>> = if span < self.cells.len() as f64 {
+      let (lat_cells, lon_cells) = (lat_cells as i32, lon_cells as i32);
+      let (row, col) = center.cell();
+      Box::new(
+        (row - lat_cells..=row + lat_cells)
+          .flat_map(move |r| (col - lon_cells..=col + lon_cells).map(move |c| (r, c)))
+          .filter_map(move |cell| self.cells.get(&cell))
+          .flatten(),
+      )
+    } else {
+      // 範囲が広すぎる場合はセルを列挙するより全件を見る方が速い
+      Box::new(self.coordinates.keys())
+    };
+    codes
+      .map(|code| (code, self.coordinates[code].distance_km(center)))
+      .filter(|(_, distance)| *distance <= radius_km)
+      .collect()
+  }
+}
+
+impl Address {
+  pub fn geocode(&self, gazetteer: &Gazetteer) -> Option<Coordinate> {
+    gazetteer.lookup(&self.postal_code)
+  }
+}
+
+impl AddressEntry {
+  // 主住所どうしの距離。どちらかの郵便番号が未登録ならNone
+  pub fn distance_km(&self, other: &AddressEntry, gazetteer: &Gazetteer) -> Option<f64> {
+    let from = self.address().geocode(gazetteer)?;
+    let to = other.address().geocode(gazetteer)?;
+    Some(from.distance_km(&to))
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct NearbyEntry<'a> {
+  pub entry: &'a AddressEntry,
+  pub distance_km: f64,
+}
+
+impl AddressBook {
+  // 主住所が中心から半径内にあるエントリを近い順に返す
+  pub fn find_within_km(
+    &self,
+    gazetteer: &Gazetteer,
+    center: &Coordinate,
+    radius_km: f64,
+  ) -> Vec<NearbyEntry<'_>> {
+    let mut result = gazetteer
+      .within_km(center, radius_km)
+      .into_iter()
+      .flat_map(|(code, distance_km)| {
+        self
+          .find_by_postal_code(code)
+          .map(move |entry| NearbyEntry { entry, distance_km })
+      })
+      .collect::<Vec<_>>();
+    result.sort_by(|a, b| {
+      a.distance_km
+        .partial_cmp(&b.distance_km)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| a.entry.id.0.cmp(&b.entry.id.0))
+    });
+    result
+  }
+
+  pub fn find_near_entry(
+    &self,
+    gazetteer: &Gazetteer,
+    entry: &AddressEntry,
+    radius_km: f64,
+  ) -> Vec<NearbyEntry<'_>> {
+    match entry.address().geocode(gazetteer) {
+      Some(center) => self
+        .find_within_km(gazetteer, &center, radius_km)
+        .into_iter()
+        .filter(|nearby| nearby.entry.id != entry.id)
+        .collect(),
+      None => vec![],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::test_entry;
+
+  const GAZETTEER: &str = "\
+postal_code,latitude,longitude
+105-0011,35.6586,139.7454
+100-0005,35.6812,139.7671
+530-0001,34.7025,135.4959
+060-0005,43.0687,141.3508
+";
+
+  #[test]
+  fn test_distance() {
+    let tokyo = Coordinate::new(35.6812, 139.7671).unwrap();
+    let osaka = Coordinate::new(34.7025, 135.4959).unwrap();
+    assert!((tokyo.distance_km(&osaka) - 403.0).abs() < 2.0);
+    assert_eq!(tokyo.distance_km(&tokyo), 0.0);
+    assert!(Coordinate::new(91.0, 0.0).is_err());
+
+    let gazetteer = Gazetteer::from_csv_str(GAZETTEER).unwrap();
+    assert_eq!(gazetteer.len(), 4);
+    let distance = test_entry(1, "Taro", "Yamamoto", "105-0011")
+      .distance_km(&test_entry(2, "Taro", "Yamamoto", "100-0005"), &gazetteer)
+      .unwrap();
+    assert!((distance - 3.2).abs() < 0.2);
+    assert_eq!(
+      test_entry(1, "Taro", "Yamamoto", "105-0011")
+        .distance_km(&test_entry(2, "Taro", "Yamamoto", "999-9999"), &gazetteer),
+      None
+    );
+  }
+
+  #[test]
+  fn test_find_within_km() {
+    let gazetteer = Gazetteer::from_csv_str(GAZETTEER).unwrap();
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        test_entry(1, "Taro", "Yamamoto", "530-0001"),
+        test_entry(2, "Taro", "Yamamoto", "105-0011"),
+        test_entry(3, "Taro", "Yamamoto", "100-0005"),
+        test_entry(4, "Taro", "Yamamoto", "060-0005"),
+        test_entry(5, "Taro", "Yamamoto", "999-9999"),
+      ])
+      .unwrap();
+    let ids =
+      |result: Vec<NearbyEntry<'_>>| result.iter().map(|n| n.entry.id.0).collect::<Vec<_>>();
+
+    let center = Coordinate::new(35.6812, 139.7671).unwrap();
+    assert_eq!(
+      ids(address_book.find_within_km(&gazetteer, &center, 5.0)),
+      vec![3, 2]
+    );
+    assert_eq!(
+      ids(address_book.find_within_km(&gazetteer, &center, 500.0)),
+      vec![3, 2, 1]
+    );
+    assert_eq!(
+      ids(address_book.find_within_km(&gazetteer, &center, 5000.0)),
+      vec![3, 2, 1, 4]
+    );
+    assert_eq!(
+      ids(address_book.find_near_entry(
+        &gazetteer,
+        &test_entry(2, "Taro", "Yamamoto", "105-0011"),
+        5.0
+      )),
+      vec![3]
+    );
+    for radius in &[1e12, f64::MAX, f64::INFINITY] {
+      assert_eq!(
+        ids(address_book.find_within_km(&gazetteer, &center, *radius)),
+        vec![3, 2, 1, 4]
+      );
+    }
+    assert!(address_book
+      .find_within_km(&gazetteer, &center, f64::NAN)
+      .is_empty());
+    assert!(address_book
+      .find_within_km(&gazetteer, &center, -1.0)
+      .is_empty());
+  }
+
+  #[test]
+  fn test_within_km_grid() {
+    let mut gazetteer = Gazetteer::default();
+    for i in 0..400 {
+      let coordinate = Coordinate::new(
+        35.0 + (i / 20) as f64 * 0.05,
+        139.0 + (i % 20) as f64 * 0.05,
+      )
+      .unwrap();
+      gazetteer.insert(
+        PostalCode::new(&format!("{:07}", 1000000 + i)).unwrap(),
+        coordinate,
+      );
+    }
+    // グリッドで絞り込んだ結果が全件走査と一致する
+    let center = Coordinate::new(35.42, 139.33).unwrap();
+    let mut found = gazetteer
+      .within_km(&center, 12.0)
+      .into_iter()
+      .map(|(code, _)| code.clone())
+      .collect::<Vec<_>>();
+    found.sort();
+    let mut expected = gazetteer
+      .coordinates
+      .iter()
+      .filter(|(_, c)| c.distance_km(&center) <= 12.0)
+      .map(|(code, _)| code.clone())
+      .collect::<Vec<_>>();
+    expected.sort();
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+  }
+
+  #[test]
+  fn test_invalid_csv() {
+    assert!(Gazetteer::from_csv_str("105-0011,35.6\n").is_err());
+    assert!(Gazetteer::from_csv_str("105-0011,north,139.7\n").is_err());
+    assert!(Gazetteer::from_csv_str("105-0011,135.6,139.7\n").is_err());
+  }
+}
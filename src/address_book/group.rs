@@ -0,0 +1,284 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::history::Change;
+use crate::address_book::{AddressBook, AddressEntry, AddressEntryId};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+  pub name: String,
+  pub description: Option<String>,
+}
+
+impl Group {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      description: None,
+    }
+  }
+}
+
+fn group_name(name: &str) -> Result<&str> {
+  match name.trim() {
+    "" => Err(anyhow!("group name must not be empty")),
+    name => Ok(name),
+  }
+}
+
+// メンバーシップはエントリ側の groups に持ち、AddressBook はグループの定義とインデックスを持つ
+impl AddressBook {
+  pub fn create_group(&mut self, name: &str, description: Option<&str>) -> Result<()> {
+    let name = group_name(name)?;
+    if self.groups.contains_key(name) {
+      return Err(anyhow!("duplicate group: {}", name));
+    }
+    let group = Group {
+      name: name.to_owned(),
+      description: description.map(|d| d.to_owned()),
+    };
+    self.set_group(name, Some(group));
+    Ok(())
+  }
+
+  // グループ定義の変更はここを通して履歴とイベントに記録する
+  pub(crate) fn set_group(&mut self, name: &str, group: Option<Group>) -> Option<Group> {
+    self.touch();
+    let before = match &group {
+      Some(group) => self.groups.insert(name.to_owned(), group.clone()),
+      None => self.groups.remove(name),
+    };
+    self.emit_group(name, before.as_ref(), group.as_ref());
+    self.history.record(Change::Group {
+      name: name.to_owned(),
+      before: before.clone(),
+      after: group,
+    });
+    before
+  }
+
+  pub fn group(&self, name: &str) -> Option<&Group> {
+    self.groups.get(name.trim())
+  }
+
+  // 名前順
+  pub fn groups(&self) -> impl Iterator<Item = &Group> {
+    self.groups.values()
+  }
+
+  pub fn set_group_description(&mut self, name: &str, description: Option<&str>) -> Result<()> {
+    let mut group = self
+      .group(name)
+      .cloned()
+      .ok_or_else(|| anyhow!("group not found: {}", name))?;
+    group.description = description.map(|d| d.to_owned());
+    self.set_group(&group.name.clone(), Some(group));
+    Ok(())
+  }
+
+  fn member_ids(&self, name: &str) -> Vec<AddressEntryId> {
+    self.members(name).map(|e| e.id.clone()).collect()
+  }
+
+  // メンバーの所属も新しい名前に付け替える
+  pub fn rename_group(&mut self, name: &str, new_name: &str) -> Result<()> {
+    let (name, new_name) = (group_name(name)?, group_name(new_name)?);
+    if name == new_name {
+      return Ok(());
+    }
+    if self.groups.contains_key(new_name) {
+      return Err(anyhow!("duplicate group: {}", new_name));
+    }
+    if !self.groups.contains_key(name) {
+      return Err(anyhow!("group not found: {}", name));
+    }
+    self.transaction("rename_group", |book| {
+      let mut group = book.set_group(name, None).unwrap();
+      group.name = new_name.to_owned();
+      book.set_group(new_name, Some(group));
+      for id in book.member_ids(name) {
+        let mut entry = book.get_mut(&id).unwrap();
+        entry.groups.remove(name);
+        entry.groups.insert(new_name.to_owned());
+      }
+      Ok(())
+    })
+  }
+
+  // メンバーからも所属を外す。エントリ自体は削除しない
+  pub fn remove_group(&mut self, name: &str) -> Option<Group> {
+    let name = name.trim();
+    if !self.groups.contains_key(name) {
+      return None;
+    }
+    self
+      .transaction("remove_group", |book| {
+        let group = book.set_group(name, None);
+        for id in book.member_ids(name) {
+          book.get_mut(&id).unwrap().groups.remove(name);
+        }
+        Ok(group)
+      })
+      .unwrap()
+  }
+
+  // 既に所属していればfalse
+  pub fn add_to_group(&mut self, address_entry_id: &AddressEntryId, name: &str) -> Result<bool> {
+    let name = group_name(name)?;
+    if !self.groups.contains_key(name) {
+      return Err(anyhow!("group not found: {}", name));
+    }
+    let mut entry = self
+      .get_mut(address_entry_id)
+      .ok_or_else(|| anyhow!("address entry not found: {:?}", address_entry_id))?;
+    Ok(entry.groups.insert(name.to_owned()))
+  }
+
+  pub fn remove_from_group(
+    &mut self,
+    address_entry_id: &AddressEntryId,
+    name: &str,
+  ) -> Result<bool> {
+    let mut entry = self
+      .get_mut(address_entry_id)
+      .ok_or_else(|| anyhow!("address entry not found: {:?}", address_entry_id))?;
+    Ok(entry.groups.remove(name.trim()))
+  }
+
+  pub fn members<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a AddressEntry> {
+    self
+      .group_index
+      .get(name.trim())
+      .map(move |seq| self.entry_at(seq))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, Condition, Query};
+
+  fn address_book() -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    for (id, first_name, last_name) in &[(1, "Junichi", "Kato"), (2, "Taro", "Yamamoto")] {
+      address_book
+        .add_entry(test_entry(*id, first_name, last_name, "111-0001"))
+        .unwrap();
+    }
+    address_book
+  }
+
+  fn ids<'a>(entries: impl Iterator<Item = &'a AddressEntry>) -> Vec<u64> {
+    entries.map(|e| e.id.0).collect()
+  }
+
+  #[test]
+  fn test_group_crud() {
+    let mut address_book = address_book();
+    address_book
+      .create_group("customers", Some("取引先"))
+      .unwrap();
+    address_book.create_group(" suppliers ", None).unwrap();
+    assert!(address_book.create_group("customers", None).is_err());
+    assert!(address_book.create_group(" ", None).is_err());
+    assert_eq!(
+      address_book
+        .groups()
+        .map(|g| g.name.as_str())
+        .collect::<Vec<_>>(),
+      vec!["customers", "suppliers"]
+    );
+
+    let (kato, yamamoto) = (AddressEntryId::new(1), AddressEntryId::new(2));
+    assert!(address_book.add_to_group(&kato, "customers").unwrap());
+    assert!(!address_book.add_to_group(&kato, "customers").unwrap());
+    assert!(address_book.add_to_group(&kato, "suppliers").unwrap());
+    assert!(address_book.add_to_group(&yamamoto, "customers").unwrap());
+    assert!(address_book.add_to_group(&yamamoto, "unknown").is_err());
+    assert!(address_book
+      .add_to_group(&AddressEntryId::new(9), "customers")
+      .is_err());
+    assert_eq!(ids(address_book.members("customers")), vec![1, 2]);
+
+    address_book.rename_group("customers", "clients").unwrap();
+    assert!(address_book.rename_group("clients", "suppliers").is_err());
+    assert_eq!(
+      address_book
+        .group("clients")
+        .unwrap()
+        .description
+        .as_deref(),
+      Some("取引先")
+    );
+    assert_eq!(ids(address_book.members("customers")), Vec::<u64>::new());
+    assert_eq!(ids(address_book.members("clients")), vec![1, 2]);
+    assert!(address_book.get(&kato).unwrap().is_member_of("clients"));
+
+    assert!(address_book.remove_from_group(&kato, "clients").unwrap());
+    assert_eq!(ids(address_book.members("clients")), vec![2]);
+
+    let removed = address_book.remove_group("suppliers").unwrap();
+    assert_eq!(removed.name, "suppliers");
+    assert!(address_book.get(&kato).unwrap().groups.is_empty());
+    assert!(address_book.remove_group("suppliers").is_none());
+  }
+
+  #[test]
+  fn test_tags_and_cascade() {
+    let mut address_book = address_book();
+    let (kato, yamamoto) = (AddressEntryId::new(1), AddressEntryId::new(2));
+    {
+      let mut entry = address_book.get_mut(&kato).unwrap();
+      assert!(entry.add_tag(" vip "));
+      assert!(!entry.add_tag("vip"));
+      assert!(!entry.add_tag(""));
+      entry.add_tag("osaka-office");
+      // 未登録のグループは自動で作られる
+      entry.groups.insert("customers".to_owned());
+    }
+    address_book.get_mut(&yamamoto).unwrap().add_tag("vip");
+    assert!(address_book.group("customers").is_some());
+    assert_eq!(ids(address_book.find_by_tag("vip")), vec![1, 2]);
+
+    let query = Query::new().filter(Condition::tag("vip").and(!Condition::in_group("customers")));
+    assert_eq!(
+      ids(query.execute(&address_book).entries.into_iter()),
+      vec![2]
+    );
+
+    address_book.get_mut(&yamamoto).unwrap().remove_tag("vip");
+    assert_eq!(ids(address_book.find_by_tag("vip")), vec![1]);
+
+    address_book.remove_entry(kato);
+    assert_eq!(ids(address_book.find_by_tag("vip")), Vec::<u64>::new());
+    assert_eq!(ids(address_book.members("customers")), Vec::<u64>::new());
+    assert!(address_book.group("customers").is_some());
+  }
+
+  #[test]
+  fn test_implicit_groups_are_undone() {
+    let mut address_book = address_book();
+    let mut entry = test_entry(3, "Hanako", "Yamada", "111-0001");
+    entry.groups.insert("friends".to_owned());
+    address_book.add_entry(entry).unwrap();
+    assert!(address_book.group("friends").is_some());
+    assert_eq!(address_book.undo_label(), Some("add_entry"));
+
+    // エントリの追加とグループの作成はひとつの操作として取り消される
+    address_book.undo().unwrap();
+    assert!(!address_book.contains(&AddressEntryId::new(3)));
+    assert!(address_book.group("friends").is_none());
+    address_book.redo().unwrap();
+    assert_eq!(ids(address_book.members("friends")), vec![3]);
+
+    address_book
+      .get_mut(&AddressEntryId::new(1))
+      .unwrap()
+      .groups
+      .insert("family".to_owned());
+    assert_eq!(address_book.undo_label(), Some("update_entry"));
+    address_book.undo().unwrap();
+    assert!(address_book.group("family").is_none());
+    assert!(address_book.group("friends").is_some());
+  }
+}
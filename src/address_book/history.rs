@@ -0,0 +1,414 @@
+use std::collections::VecDeque;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::{AddressBook, AddressEntry, AddressEntryId, Group, MergeRecord};
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+// 取り消し可能な変更。エントリは挿入位置(seq)ごと前後の状態を持つ
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Change {
+  Entry {
+    id: AddressEntryId,
+    before: Option<(u64, Box<AddressEntry>)>,
+    after: Option<(u64, Box<AddressEntry>)>,
+  },
+  Group {
+    name: String,
+    before: Option<Group>,
+    after: Option<Group>,
+  },
+  Merge(MergeRecord),
+}
+
+#[derive(Debug, Clone)]
+struct Transaction {
+  label: String,
+  changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+  undo: VecDeque<Transaction>,
+  redo: Vec<Transaction>,
+  limit: usize,
+  // 実行中の一番外側のトランザクション
+  open: Option<Transaction>,
+  replaying: bool,
+}
+
+impl Default for History {
+  fn default() -> Self {
+    Self {
+      undo: VecDeque::new(),
+      redo: vec![],
+      limit: DEFAULT_HISTORY_LIMIT,
+      open: None,
+      replaying: false,
+    }
+  }
+}
+
+impl History {
+  // 履歴を残さない設定でも、トランザクション中はロールバックのために記録する
+  pub(crate) fn is_recording(&self) -> bool {
+    !self.replaying && (self.open.is_some() || self.limit > 0)
+  }
+
+  pub(crate) fn record(&mut self, change: Change) {
+    self.record_all(vec![change]);
+  }
+
+  // まとめて渡した変更はひとつの操作として記録する。操作の名前は最後の変更で決める
+  pub(crate) fn record_all(&mut self, changes: Vec<Change>) {
+    if !self.is_recording() || changes.is_empty() {
+      return;
+    }
+    match self.open.as_mut() {
+      Some(transaction) => transaction.changes.extend(changes),
+      None => {
+        let label = match changes.last().unwrap() {
+          Change::Entry { before: None, .. } => "add_entry",
+          Change::Entry { after: None, .. } => "remove_entry",
+          Change::Entry { .. } => "update_entry",
+          Change::Group { .. } => "update_group",
+          Change::Merge(_) => "merge_entries",
+        };
+        self.commit(Transaction {
+          label: label.to_owned(),
+          changes,
+        });
+      }
+    }
+  }
+
+  fn commit(&mut self, transaction: Transaction) {
+    if transaction.changes.is_empty() || self.limit == 0 {
+      return;
+    }
+    self.redo.clear();
+    self.undo.push_back(transaction);
+    self.truncate();
+  }
+
+  fn truncate(&mut self) {
+    while self.undo.len() > self.limit {
+      self.undo.pop_front();
+    }
+  }
+}
+
+impl AddressBook {
+  pub fn with_history_limit(mut self, limit: usize) -> Self {
+    self.set_history_limit(limit);
+    self
+  }
+
+  // 0 の場合は履歴を残さない
+  pub fn set_history_limit(&mut self, limit: usize) {
+    self.history.limit = limit;
+    self.history.truncate();
+  }
+
+  pub fn clear_history(&mut self) {
+    self.history.undo.clear();
+    self.history.redo.clear();
+  }
+
+  // 実行中のトランザクションを持たない複製。トランザクションの途中の状態を保存するのに使う
+  pub(crate) fn clone_outside_transaction(&self) -> AddressBook {
+    let mut address_book = self.clone();
+    address_book.history.open = None;
+    address_book
+  }
+
+  // f の中の変更をひとつの操作として記録する。Err の場合は f の中の変更をすべて元に戻す。
+  // 入れ子にした場合は一番外側のトランザクションにまとめられる
+  pub fn transaction<T, F>(&mut self, label: &str, f: F) -> Result<T>
+  where
+    F: FnOnce(&mut AddressBook) -> Result<T>,
+  {
+    let outermost = self.history.open.is_none();
+    if outermost {
+      self.history.open = Some(Transaction {
+        label: label.to_owned(),
+        changes: vec![],
+      });
+    }
+    let savepoint = self.history.open.as_ref().unwrap().changes.len();
+    let event_savepoint = self.events.as_ref().map(|events| events.len());
+    let result = f(self);
+    if result.is_err() {
+      let changes = self
+        .history
+        .open
+        .as_mut()
+        .unwrap()
+        .changes
+        .split_off(savepoint);
+      self.replay(changes.iter().rev(), false);
+      // ロールバックした変更のイベントは打ち消すイベントを出さずに捨てる
+      if let (Some(events), Some(len)) = (self.events.as_mut(), event_savepoint) {
+        events.truncate(len);
+      }
+    }
+    if outermost {
+      let transaction = self.history.open.take().unwrap();
+      self.history.commit(transaction);
+    }
+    result
+  }
+
+  fn apply(&mut self, change: &Change, forward: bool) {
+    self.touch();
+    match change {
+      Change::Entry { id, before, after } => {
+        let state = if forward { after } else { before };
+        let current = self.take_slot(id).map(|(_, entry)| entry);
+        self.emit(current.as_ref(), state.as_ref().map(|(_, e)| e.as_ref()));
+        if let Some((seq, entry)) = state {
+          self.insert_slot(*seq, *entry.clone());
+        }
+      }
+      Change::Group {
+        name,
+        before,
+        after,
+      } => {
+        let (current, state) = if forward {
+          (before, after)
+        } else {
+          (after, before)
+        };
+        self.emit_group(name, current.as_ref(), state.as_ref());
+        match state {
+          Some(group) => {
+            self.groups.insert(name.clone(), group.clone());
+          }
+          None => {
+            self.groups.remove(name);
+          }
+        }
+      }
+      Change::Merge(record) if forward => self.merges.push(record.clone()),
+      Change::Merge(record) => {
+        if let Some(i) = self.merges.iter().rposition(|r| r == record) {
+          self.merges.remove(i);
+        }
+      }
+    }
+  }
+
+  fn replay<'a>(&mut self, changes: impl Iterator<Item = &'a Change>, forward: bool) {
+    self.history.replaying = true;
+    changes.for_each(|change| self.apply(change, forward));
+    self.history.replaying = false;
+  }
+
+  pub fn can_undo(&self) -> bool {
+    !self.history.undo.is_empty()
+  }
+
+  pub fn can_redo(&self) -> bool {
+    !self.history.redo.is_empty()
+  }
+
+  pub fn undo_label(&self) -> Option<&str> {
+    self.history.undo.back().map(|t| t.label.as_str())
+  }
+
+  pub fn redo_label(&self) -> Option<&str> {
+    self.history.redo.last().map(|t| t.label.as_str())
+  }
+
+  // 取り消す操作がなければfalse
+  pub fn undo(&mut self) -> Result<bool> {
+    if self.history.open.is_some() {
+      return Err(anyhow!("cannot undo inside a transaction"));
+    }
+    let transaction = match self.history.undo.pop_back() {
+      Some(transaction) => transaction,
+      None => return Ok(false),
+    };
+    self.replay(transaction.changes.iter().rev(), false);
+    self.history.redo.push(transaction);
+    Ok(true)
+  }
+
+  pub fn redo(&mut self) -> Result<bool> {
+    if self.history.open.is_some() {
+      return Err(anyhow!("cannot redo inside a transaction"));
+    }
+    let transaction = match self.history.redo.pop() {
+      Some(transaction) => transaction,
+      None => return Ok(false),
+    };
+    self.replay(transaction.changes.iter(), true);
+    self.history.undo.push_back(transaction);
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, AddressEntryPatch};
+
+  fn ids(address_book: &AddressBook) -> Vec<u64> {
+    address_book.iter().map(|e| e.id.0).collect()
+  }
+
+  #[test]
+  fn test_undo_redo() {
+    let mut address_book = AddressBook::new("test");
+    assert!(!address_book.undo().unwrap());
+    address_book
+      .add_entries(&[
+        test_entry(1, "Taro", "Kato", "111-0001"),
+        test_entry(2, "Taro", "Yamamoto", "111-0001"),
+        test_entry(3, "Taro", "Yamada", "111-0001"),
+      ])
+      .unwrap();
+    address_book
+      .add_entry(test_entry(4, "Taro", "Sato", "111-0001"))
+      .unwrap();
+    address_book
+      .patch_entry(
+        &AddressEntryId::new(2),
+        &AddressEntryPatch::new().with_last_name("Suzuki"),
+      )
+      .unwrap();
+    address_book.remove_entries(&[AddressEntryId::new(2), AddressEntryId::new(3)]);
+    assert_eq!(ids(&address_book), vec![1, 4]);
+    assert_eq!(address_book.undo_label(), Some("remove_entries"));
+
+    // 削除を取り消すと元の位置に戻る
+    assert!(address_book.undo().unwrap());
+    assert_eq!(ids(&address_book), vec![1, 2, 3, 4]);
+    assert_eq!(address_book.find_by_last_name("Suzuki").count(), 1);
+    assert!(address_book.undo().unwrap());
+    assert_eq!(address_book.find_by_last_name("Suzuki").count(), 0);
+    assert_eq!(address_book.find_by_last_name("Yamamoto").count(), 1);
+    assert!(address_book.undo().unwrap());
+    assert!(address_book.undo().unwrap());
+    assert!(address_book.is_empty());
+    assert!(!address_book.can_undo());
+
+    assert!(address_book.redo().unwrap());
+    assert_eq!(ids(&address_book), vec![1, 2, 3]);
+    assert_eq!(address_book.redo_label(), Some("add_entry"));
+
+    // 新しい変更で redo の履歴は消える
+    address_book.remove_entry(AddressEntryId::new(1));
+    assert!(!address_book.can_redo());
+    address_book.clear();
+    assert!(address_book.is_empty());
+    address_book.undo().unwrap();
+    assert_eq!(ids(&address_book), vec![2, 3]);
+  }
+
+  #[test]
+  fn test_transaction_rollback_and_limit() {
+    let mut address_book = AddressBook::new("test").with_history_limit(2);
+    address_book
+      .add_entry(test_entry(1, "Taro", "Kato", "111-0001"))
+      .unwrap();
+    let result = address_book.transaction("bulk", |book| {
+      book.add_entry(test_entry(2, "Taro", "Yamamoto", "111-0001"))?;
+      book.remove_entry(AddressEntryId::new(1));
+      book.transaction("nested", |book| {
+        book.add_entry(test_entry(2, "Taro", "Yamada", "111-0001"))
+      })
+    });
+    assert!(result.is_err());
+    assert_eq!(ids(&address_book), vec![1]);
+    assert_eq!(address_book.undo_label(), Some("add_entry"));
+
+    address_book
+      .transaction("bulk", |book| {
+        book.add_entry(test_entry(2, "Taro", "Yamamoto", "111-0001"))?;
+        // 内側の失敗は内側の変更だけを戻す
+        let nested = book.transaction("nested", |book| {
+          book.add_entry(test_entry(3, "Taro", "Yamada", "111-0001"))?;
+          book.add_entry(test_entry(1, "Taro", "Sato", "111-0001"))
+        });
+        assert!(nested.is_err());
+        assert!(book.undo().is_err());
+        Ok(())
+      })
+      .unwrap();
+    assert_eq!(ids(&address_book), vec![1, 2]);
+    assert_eq!(address_book.undo_label(), Some("bulk"));
+
+    address_book
+      .add_entry(test_entry(3, "Taro", "Yamada", "111-0001"))
+      .unwrap();
+    assert!(address_book.undo().unwrap());
+    assert!(address_book.undo().unwrap());
+    assert!(!address_book.undo().unwrap());
+    assert_eq!(ids(&address_book), vec![1]);
+
+    address_book.set_history_limit(0);
+    address_book
+      .add_entry(test_entry(2, "Taro", "Yamamoto", "111-0001"))
+      .unwrap();
+    assert!(!address_book.can_undo());
+    assert!(address_book
+      .transaction("bulk", |book| {
+        book.add_entry(test_entry(3, "Taro", "Yamada", "111-0001"))?;
+        book.add_entry(test_entry(3, "Taro", "Yamada", "111-0001"))
+      })
+      .is_err());
+    assert_eq!(ids(&address_book), vec![1, 2]);
+  }
+
+  #[test]
+  fn test_undo_group_and_merge() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        test_entry(1, "Taro", "Kato", "111-0001"),
+        test_entry(2, "Taro", "Kato", "111-0001"),
+      ])
+      .unwrap();
+    address_book
+      .create_group("customers", Some("取引先"))
+      .unwrap();
+    address_book
+      .add_to_group(&AddressEntryId::new(1), "customers")
+      .unwrap();
+    address_book.remove_group("customers");
+    address_book.undo().unwrap();
+    assert_eq!(
+      address_book
+        .group("customers")
+        .unwrap()
+        .description
+        .as_deref(),
+      Some("取引先")
+    );
+    assert_eq!(address_book.members("customers").count(), 1);
+
+    address_book.rename_group("customers", "clients").unwrap();
+    address_book.undo().unwrap();
+    assert!(address_book.group("clients").is_none());
+    assert_eq!(address_book.members("customers").count(), 1);
+
+    address_book
+      .merge_entries(
+        &AddressEntryId::new(1),
+        &AddressEntryId::new(2),
+        &Default::default(),
+      )
+      .unwrap();
+    assert_eq!(address_book.undo_label(), Some("merge_entries"));
+    address_book.undo().unwrap();
+    assert_eq!(ids(&address_book), vec![1, 2]);
+    assert!(address_book.merge_history().is_empty());
+    address_book.redo().unwrap();
+    assert_eq!(ids(&address_book), vec![1]);
+    assert_eq!(address_book.merge_history().len(), 1);
+  }
+}
@@ -0,0 +1,261 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::AddressEntryId;
+
+// TOMLの整数(i64)に収まるよう、IDは63ビット以内にする
+pub(crate) const MAX_ID: u64 = i64::MAX as u64;
+
+// 保存できない範囲のIDはエントリを作る時点で拒否する
+pub(crate) fn checked_id(id: u64) -> Result<AddressEntryId> {
+  if id > MAX_ID {
+    return Err(anyhow!("address entry id out of range: {}", id));
+  }
+  Ok(AddressEntryId::new(id))
+}
+
+pub trait IdGenerator: fmt::Debug + Send + Sync {
+  // 採番できるIDを使い切った場合はErr
+  fn generate(&mut self) -> Result<AddressEntryId>;
+
+  // AddressBookに追加されたIDを通知する。採番の重複を避けたい実装が使う
+  fn observe(&mut self, _id: &AddressEntryId) {}
+
+  fn box_clone(&self) -> Box<dyn IdGenerator>;
+}
+
+impl Clone for Box<dyn IdGenerator> {
+  fn clone(&self) -> Self {
+    self.box_clone()
+  }
+}
+
+impl Default for Box<dyn IdGenerator> {
+  fn default() -> Self {
+    Box::new(MonotonicIdGenerator::default())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct MonotonicIdGenerator {
+  next: u64,
+}
+
+impl Default for MonotonicIdGenerator {
+  fn default() -> Self {
+    Self::new(1)
+  }
+}
+
+impl MonotonicIdGenerator {
+  pub fn new(start: u64) -> Self {
+    Self { next: start }
+  }
+}
+
+impl IdGenerator for MonotonicIdGenerator {
+  fn generate(&mut self) -> Result<AddressEntryId> {
+    let id = checked_id(self.next).map_err(|_| anyhow!("address entry ids are exhausted"))?;
+    self.next += 1;
+    Ok(id)
+  }
+
+  fn observe(&mut self, id: &AddressEntryId) {
+    self.next = self.next.max(id.0.saturating_add(1));
+  }
+
+  fn box_clone(&self) -> Box<dyn IdGenerator> {
+    Box::new(self.clone())
+  }
+}
+
+// 41ビットのミリ秒タイムスタンプ + 10ビットのノードID + 12ビットのシーケンス
+#[derive(Debug, Clone)]
+pub struct SnowflakeIdGenerator {
+  node_id: u64,
+  epoch_millis: u64,
+  last_timestamp: u64,
+  sequence: u64,
+}
+
+impl SnowflakeIdGenerator {
+  // 2021-01-01T00:00:00Z
+  pub const DEFAULT_EPOCH_MILLIS: u64 = 1_609_459_200_000;
+  const NODE_ID_BITS: u64 = 10;
+  const SEQUENCE_BITS: u64 = 12;
+  const TIMESTAMP_BITS: u64 = 63 - Self::NODE_ID_BITS - Self::SEQUENCE_BITS;
+
+  // node_id は10ビットに収まらなければErr
+  pub fn new(node_id: u16) -> Result<Self> {
+    Self::with_epoch(node_id, Self::DEFAULT_EPOCH_MILLIS)
+  }
+
+  pub fn with_epoch(node_id: u16, epoch_millis: u64) -> Result<Self> {
+    if u64::from(node_id) >= 1 << Self::NODE_ID_BITS {
+      return Err(anyhow!(
+        "node_id must be less than {}: {}",
+        1 << Self::NODE_ID_BITS,
+        node_id
+      ));
+    }
+    Ok(Self {
+      node_id: u64::from(node_id),
+      epoch_millis,
+      last_timestamp: 0,
+      sequence: 0,
+    })
+  }
+
+  fn current_timestamp(&self) -> u64 {
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_millis() as u64)
+      .unwrap_or(0);
+    now.saturating_sub(self.epoch_millis)
+  }
+
+  pub fn timestamp_of(id: &AddressEntryId) -> u64 {
+    id.0 >> (Self::NODE_ID_BITS + Self::SEQUENCE_BITS)
+  }
+}
+
+impl IdGenerator for SnowflakeIdGenerator {
+  // 時計が戻った場合やシーケンスを使い切った場合は、直前のタイムスタンプを先へ進めて単調増加を保つ。
+  // タイムスタンプが41ビットを超えた場合はErr
+  fn generate(&mut self) -> Result<AddressEntryId> {
+    let mut timestamp = self.current_timestamp().max(self.last_timestamp);
+    let mut sequence = 0;
+    if timestamp == self.last_timestamp {
+      sequence = (self.sequence + 1) & ((1 << Self::SEQUENCE_BITS) - 1);
+      if sequence == 0 {
+        timestamp += 1;
+      }
+    }
+    if timestamp >= 1 << Self::TIMESTAMP_BITS {
+      return Err(anyhow!("address entry ids are exhausted"));
+    }
+    self.last_timestamp = timestamp;
+    self.sequence = sequence;
+    let id = (timestamp << (Self::NODE_ID_BITS + Self::SEQUENCE_BITS))
+      | (self.node_id << Self::SEQUENCE_BITS)
+      | sequence;
+    checked_id(id)
+  }
+
+  fn box_clone(&self) -> Box<dyn IdGenerator> {
+    Box::new(self.clone())
+  }
+}
+
+// xorshift64*。暗号用途ではない
+#[derive(Debug, Clone)]
+pub struct RandomIdGenerator {
+  state: u64,
+}
+
+impl Default for RandomIdGenerator {
+  fn default() -> Self {
+    Self::with_seed(RandomState::new().build_hasher().finish())
+  }
+}
+
+impl RandomIdGenerator {
+  pub fn with_seed(seed: u64) -> Self {
+    Self {
+      state: if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+      } else {
+        seed
+      },
+    }
+  }
+}
+
+impl IdGenerator for RandomIdGenerator {
+  fn generate(&mut self) -> Result<AddressEntryId> {
+    loop {
+      self.state ^= self.state >> 12;
+      self.state ^= self.state << 25;
+      self.state ^= self.state >> 27;
+      let id = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) & MAX_ID;
+      if id != 0 {
+        return Ok(AddressEntryId::new(id));
+      }
+    }
+  }
+
+  fn box_clone(&self) -> Box<dyn IdGenerator> {
+    Box::new(self.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashSet;
+
+  #[test]
+  fn test_monotonic() {
+    let mut generator = MonotonicIdGenerator::default();
+    assert_eq!(generator.generate().unwrap(), AddressEntryId::new(1));
+    generator.observe(&AddressEntryId::new(10));
+    assert_eq!(generator.generate().unwrap(), AddressEntryId::new(11));
+    generator.observe(&AddressEntryId::new(3));
+    assert_eq!(generator.generate().unwrap(), AddressEntryId::new(12));
+  }
+
+  #[test]
+  fn test_monotonic_exhausted() {
+    let mut generator = MonotonicIdGenerator::new(MAX_ID);
+    assert_eq!(generator.generate().unwrap(), AddressEntryId::new(MAX_ID));
+    assert!(generator.generate().is_err());
+    generator.observe(&AddressEntryId::new(u64::MAX));
+    assert_eq!(
+      generator.generate().unwrap_err().to_string(),
+      "address entry ids are exhausted"
+    );
+    assert!(checked_id(MAX_ID + 1).is_err());
+  }
+
+  #[test]
+  fn test_snowflake_is_time_ordered() {
+    let mut generator = SnowflakeIdGenerator::new(7).unwrap();
+    let ids = (0..10_000)
+      .map(|_| generator.generate().unwrap().0)
+      .collect::<Vec<_>>();
+    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    assert!(ids.iter().all(|id| *id <= MAX_ID));
+    assert_eq!((ids[0] >> 12) & 0x3FF, 7);
+    assert!(SnowflakeIdGenerator::timestamp_of(&AddressEntryId::new(ids[0])) > 0);
+  }
+
+  #[test]
+  fn test_snowflake_limits() {
+    assert!(SnowflakeIdGenerator::new(1023).is_ok());
+    assert!(SnowflakeIdGenerator::new(1024).is_err());
+
+    // エポックを過去に置いて、タイムスタンプが41ビットを超えた状態にする
+    let mut generator = SnowflakeIdGenerator::with_epoch(1, 0).unwrap();
+    generator.last_timestamp = (1 << 41) - 1;
+    generator.sequence = 4095;
+    assert!(generator.generate().is_err());
+    assert!(generator.generate().is_err());
+  }
+
+  #[test]
+  fn test_random() {
+    let mut generator = RandomIdGenerator::with_seed(42);
+    let ids = (0..1000)
+      .map(|_| generator.generate().unwrap().0)
+      .collect::<HashSet<_>>();
+    assert_eq!(ids.len(), 1000);
+    assert!(ids.iter().all(|id| *id != 0 && *id <= MAX_ID));
+    let mut same_seed = RandomIdGenerator::with_seed(42);
+    assert!(ids.contains(&same_seed.generate().unwrap().0));
+  }
+}
@@ -0,0 +1,58 @@
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+
+// キーごとに挿入順(seq)の集合を保持する二次インデックス
+#[derive(Debug, Clone)]
+pub(crate) struct SecondaryIndex<K> {
+  map: HashMap<K, BTreeSet<u64>>,
+}
+
+impl<K> Default for SecondaryIndex<K> {
+  fn default() -> Self {
+    Self {
+      map: HashMap::default(),
+    }
+  }
+}
+
+impl<K: Hash + Eq> SecondaryIndex<K> {
+  pub(crate) fn insert(&mut self, key: K, seq: u64) {
+    self.map.entry(key).or_default().insert(seq);
+  }
+
+  pub(crate) fn remove(&mut self, key: &K, seq: u64) {
+    if let Some(seqs) = self.map.get_mut(key) {
+      seqs.remove(&seq);
+      if seqs.is_empty() {
+        self.map.remove(key);
+      }
+    }
+  }
+
+  pub(crate) fn get<Q>(&self, key: &Q) -> impl Iterator<Item = u64> + '_
+  where
+    K: std::borrow::Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.map.get(key).into_iter().flatten().copied()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_secondary_index() {
+    let mut index = SecondaryIndex::default();
+    index.insert("a".to_owned(), 2);
+    index.insert("a".to_owned(), 1);
+    index.insert("b".to_owned(), 3);
+    assert_eq!(index.get("a").collect::<Vec<_>>(), vec![1, 2]);
+    index.remove(&"a".to_owned(), 1);
+    assert_eq!(index.get("a").collect::<Vec<_>>(), vec![2]);
+    index.remove(&"a".to_owned(), 2);
+    assert_eq!(index.get("a").count(), 0);
+    assert!(!index.map.contains_key("a"));
+  }
+}
@@ -0,0 +1,322 @@
+// かな表記とローマ字表記を比較するための変換ユーティリティ
+
+pub fn katakana_to_hiragana(s: &str) -> String {
+  s.chars()
+    .map(|c| match c {
+      '\u{30A1}'..='\u{30F6}' => std::char::from_u32(c as u32 - 0x60).unwrap_or(c),
+      _ => c,
+    })
+    .collect()
+}
+
+fn hiragana_to_romaji_unit(c: char) -> Option<&'static str> {
+  let romaji = match c {
+    'あ' | 'ぁ' => "a",
+    'い' | 'ぃ' | 'ゐ' => "i",
+    'う' | 'ぅ' => "u",
+    'え' | 'ぇ' | 'ゑ' => "e",
+    'お' | 'ぉ' | 'を' => "o",
+    'か' | 'ゕ' => "ka",
+    'き' => "ki",
+    'く' => "ku",
+    'け' | 'ゖ' => "ke",
+    'こ' => "ko",
+    'が' => "ga",
+    'ぎ' => "gi",
+    'ぐ' => "gu",
+    'げ' => "ge",
+    'ご' => "go",
+    'さ' => "sa",
+    'し' => "shi",
+    'す' => "su",
+    'せ' => "se",
+    'そ' => "so",
+    'ざ' => "za",
+    'じ' | 'ぢ' => "ji",
+    'ず' | 'づ' => "zu",
+    'ぜ' => "ze",
+    'ぞ' => "zo",
+    'た' => "ta",
+    'ち' => "chi",
+    'つ' => "tsu",
+    'て' => "te",
+    'と' => "to",
+    'だ' => "da",
+    'で' => "de",
+    'ど' => "do",
+    'な' => "na",
+    'に' => "ni",
+    'ぬ' => "nu",
+    'ね' => "ne",
+    'の' => "no",
+    'は' => "ha",
+    'ひ' => "hi",
+    'ふ' => "fu",
+    'へ' => "he",
+    'ほ' => "ho",
+    'ば' => "ba",
+    'び' => "bi",
+    'ぶ' => "bu",
+    'べ' => "be",
+    'ぼ' => "bo",
+    'ぱ' => "pa",
+    'ぴ' => "pi",
+    'ぷ' => "pu",
+    'ぺ' => "pe",
+    'ぽ' => "po",
+    'ま' => "ma",
+    'み' => "mi",
+    'む' => "mu",
+    'め' => "me",
+    'も' => "mo",
+    'や' | 'ゃ' => "ya",
+    'ゆ' | 'ゅ' => "yu",
+    'よ' | 'ょ' => "yo",
+    'ら' => "ra",
+    'り' => "ri",
+    'る' => "ru",
+    'れ' => "re",
+    'ろ' => "ro",
+    'わ' | 'ゎ' => "wa",
+    'ん' => "n",
+    'ゔ' => "vu",
+    _ => return None,
+  };
+  Some(romaji)
+}
+
+fn last_vowel(s: &str) -> Option<char> {
+  s.chars().rev().find(|c| "aiueo".contains(*c))
+}
+
+// ヘボン式でローマ字化する。かな以外の文字はそのまま残す
+pub fn to_romaji(s: &str) -> String {
+  let chars = katakana_to_hiragana(s).chars().collect::<Vec<_>>();
+  let mut result = String::new();
+  let mut double_next = false;
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    if c == 'っ' {
+      double_next = true;
+      i += 1;
+      continue;
+    }
+    if c == 'ー' {
+      if let Some(v) = last_vowel(&result) {
+        result.push(v);
+      }
+      i += 1;
+      continue;
+    }
+    let mut unit = match hiragana_to_romaji_unit(c) {
+      Some(unit) => unit.to_owned(),
+      None => {
+        result.push(c);
+        i += 1;
+        continue;
+      }
+    };
+    if let Some(small) = chars.get(i + 1).filter(|n| matches!(n, 'ゃ' | 'ゅ' | 'ょ')) {
+      if unit.len() > 1 && unit.ends_with('i') {
+        let vowel = &hiragana_to_romaji_unit(*small).unwrap()[1..];
+        unit.pop();
+        if !matches!(unit.as_str(), "sh" | "ch" | "j") {
+          unit.push('y');
+        }
+        unit.push_str(vowel);
+        i += 1;
+      }
+    }
+    if double_next {
+      match unit.chars().next() {
+        Some('c') => result.push('t'),
+        Some(first) if !"aiueon".contains(first) => result.push(first),
+        _ => {}
+      }
+      double_next = false;
+    }
+    result.push_str(&unit);
+    i += 1;
+  }
+  result
+}
+
+// 表記揺れ(長音・撥音の書き分け)を吸収した比較用のキー
+pub fn phonetic_key(s: &str) -> String {
+  let romaji = to_romaji(&s.to_lowercase())
+    .chars()
+    .filter(|c| c.is_ascii_alphabetic())
+    .collect::<String>();
+  let chars = romaji.chars().collect::<Vec<_>>();
+  let mut key = String::new();
+  for (i, c) in chars.iter().enumerate() {
+    let prev = key.chars().last();
+    match (prev, c) {
+      (Some('o'), 'u') | (Some('o'), 'o') | (Some('u'), 'u') => continue,
+      (Some('o'), 'h') if chars.get(i + 1).is_none_or(|n| !"aiueoy".contains(*n)) => continue,
+      (_, 'm') if chars.get(i + 1).is_some_and(|n| "bpm".contains(*n)) => key.push('n'),
+      _ => key.push(*c),
+    }
+  }
+  key
+}
+
+const HALF_WIDTH_KATAKANA: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+fn combine_mark(base: char, mark: char) -> Option<char> {
+  let (from, to) = match mark {
+    '゛' | '\u{3099}' => (
+      "かきくけこさしすせそたちつてとはひふへほカキクケコサシスセソタチツテトハヒフヘホウ",
+      "がぎぐげござじずぜぞだぢづでどばびぶべぼガギグゲゴザジズゼゾダヂヅデドバビブベボヴ",
+    ),
+    '゜' | '\u{309A}' => ("はひふへほハヒフヘホ", "ぱぴぷぺぽパピプペポ"),
+    _ => return None,
+  };
+  from
+    .chars()
+    .position(|c| c == base)
+    .and_then(|i| to.chars().nth(i))
+}
+
+// 全角英数字・記号を半角に、半角カナを全角にする。濁点・半濁点は前の文字と合成する
+pub fn normalize_width(s: &str) -> String {
+  let mut result = String::new();
+  for c in s.chars() {
+    let c = match c {
+      '\u{FF01}'..='\u{FF5E}' => std::char::from_u32(c as u32 - 0xFEE0).unwrap(),
+      '\u{3000}' => ' ',
+      '\u{FF61}'..='\u{FF9F}' => HALF_WIDTH_KATAKANA
+        .chars()
+        .nth((c as u32 - 0xFF61) as usize)
+        .unwrap(),
+      _ => c,
+    };
+    match result.pop() {
+      Some(prev) => match combine_mark(prev, c) {
+        Some(combined) => result.push(combined),
+        None => {
+          result.push(prev);
+          result.push(c);
+        }
+      },
+      None => result.push(c),
+    }
+  }
+  result
+}
+
+fn replace_char(c: char, from: &str, to: &str) -> Option<char> {
+  from
+    .chars()
+    .position(|f| f == c)
+    .and_then(|i| to.chars().nth(i))
+}
+
+// 五十音順で並べるためのキー。清音・大文字で比べ、同じなら濁音・小書き、表記の順に比べる
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CollationKey {
+  primary: String,
+  secondary: String,
+  original: String,
+}
+
+pub fn collation_key(s: &str) -> CollationKey {
+  let secondary = katakana_to_hiragana(&normalize_width(s))
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase();
+  let mut primary = String::new();
+  for c in secondary.chars() {
+    let c = match c {
+      // 長音は直前の母音として扱う
+      'ー' => match last_vowel(&to_romaji(&primary)) {
+        Some(v) => replace_char(v, "aiueo", "あいうえお").unwrap(),
+        None => c,
+      },
+      _ => replace_char(
+        c,
+        "がぎぐげござじずぜぞだぢづでどばびぶべぼぱぴぷぺぽゔぁぃぅぇぉっゃゅょゎゕゖ",
+        "かきくけこさしすせそたちつてとはひふへほはひふへほうあいうえおつやゆよわかけ",
+      )
+      .unwrap_or(c),
+    };
+    primary.push(c);
+  }
+  CollationKey {
+    primary,
+    secondary,
+    original: s.to_owned(),
+  }
+}
+
+pub fn collate(a: &str, b: &str) -> std::cmp::Ordering {
+  collation_key(a).cmp(&collation_key(b))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_to_romaji() {
+    assert_eq!(to_romaji("かとう"), "katou");
+    assert_eq!(to_romaji("キョウコ"), "kyouko");
+    assert_eq!(to_romaji("しゅんすけ"), "shunsuke");
+    assert_eq!(to_romaji("いっちゃん"), "itchan");
+    assert_eq!(to_romaji("はっとり"), "hattori");
+    assert_eq!(to_romaji("ジュンイチ"), "junichi");
+    assert_eq!(to_romaji("ラーメン"), "raamen");
+  }
+
+  #[test]
+  fn test_phonetic_key() {
+    assert_eq!(phonetic_key("Katou"), phonetic_key("Kato"));
+    assert_eq!(phonetic_key("かとう"), phonetic_key("KATO"));
+    assert_eq!(phonetic_key("Ohno"), phonetic_key("おおの"));
+    assert_eq!(phonetic_key("Namba"), phonetic_key("なんば"));
+    assert_eq!(phonetic_key("Yuuki"), phonetic_key("ゆうき"));
+  }
+
+  #[test]
+  fn test_normalize_width() {
+    assert_eq!(normalize_width("ｶﾞｯｺｳ　ＡＢＣ１２３"), "ガッコウ ABC123");
+    assert_eq!(normalize_width("ﾊﾟﾝ"), "パン");
+    assert_eq!(normalize_width("か\u{3099}"), "が");
+  }
+
+  #[test]
+  fn test_collate() {
+    let mut names = vec![
+      "わたなべ",
+      "カトウ",
+      "がとう",
+      "ｱｵｷ",
+      "かとう",
+      "いとう",
+      "ｶｰﾄ",
+    ];
+    names.sort_by(|a, b| collate(a, b));
+    assert_eq!(
+      names,
+      vec![
+        "ｱｵｷ",
+        "いとう",
+        "ｶｰﾄ",
+        "かとう",
+        "カトウ",
+        "がとう",
+        "わたなべ"
+      ]
+    );
+    // 姓で先に比べる
+    assert_eq!(
+      collate("やま たろう", "やまぐち はなこ"),
+      std::cmp::Ordering::Less
+    );
+    assert_eq!(collation_key("ﾔﾏﾀﾞ").secondary, "やまだ");
+    assert_eq!(collation_key("ＹａｍａＤＡ").secondary, "yamada");
+    assert_eq!(collation_key("ヤマダ").primary, "やまた");
+  }
+}
@@ -0,0 +1,331 @@
+use crate::address_book::{Address, AddressBook, AddressEntry, PersonName};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelLayout {
+  // 横書き
+  Horizontal,
+  // 縦書き。郵便番号だけは横書きで先頭に置く
+  Vertical,
+  // 国外向けの英語表記
+  Western,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelOptions {
+  pub layout: LabelLayout,
+  // 和文の宛名に付ける敬称。英語表記では使わない
+  pub honorific: String,
+  // ラベルシートの横の枚数
+  pub columns: usize,
+}
+
+impl Default for LabelOptions {
+  fn default() -> Self {
+    Self::new(LabelLayout::Horizontal)
+  }
+}
+
+impl LabelOptions {
+  pub fn new(layout: LabelLayout) -> Self {
+    Self {
+      layout,
+      honorific: "様".to_owned(),
+      columns: 2,
+    }
+  }
+
+  pub fn with_honorific(mut self, honorific: &str) -> Self {
+    self.honorific = honorific.to_owned();
+    self
+  }
+
+  pub fn with_columns(mut self, columns: usize) -> Self {
+    self.columns = columns.max(1);
+    self
+  }
+}
+
+impl PersonName {
+  // 姓 名
+  pub fn japanese_name(&self) -> String {
+    join_non_empty(&[&self.last_name, &self.first_name], " ")
+  }
+
+  // 名 姓
+  pub fn western_name(&self) -> String {
+    join_non_empty(&[&self.first_name, &self.last_name], " ")
+  }
+}
+
+impl Address {
+  // 〒郵便番号、都道府県と住所、建物名の順
+  pub fn japanese_lines(&self) -> Vec<String> {
+    let address = self.address.trim();
+    // 住所に都道府県名が含まれていれば重ねて付けない
+    let address = if address.starts_with(self.pref.kanji()) {
+      address.to_owned()
+    } else {
+      format!("{}{}", self.pref.kanji(), address)
+    };
+    let mut lines = vec![format!("〒{}", self.postal_code), address];
+    lines.extend(self.building());
+    lines
+  }
+
+  // 建物名、住所、都道府県と郵便番号、国名の順
+  pub fn western_lines(&self) -> Vec<String> {
+    let mut lines = self.building().collect::<Vec<_>>();
+    lines.push(self.address.trim().to_owned());
+    lines.push(format!(
+      "{} {}",
+      self.pref.name_without_suffix(),
+      self.postal_code
+    ));
+    lines.push("JAPAN".to_owned());
+    lines
+  }
+
+  fn building(&self) -> impl Iterator<Item = String> + '_ {
+    self
+      .building
+      .iter()
+      .map(|b| b.trim())
+      .filter(|b| !b.is_empty())
+      .map(|b| b.to_owned())
+  }
+}
+
+impl AddressEntry {
+  // 主住所で宛名ラベルを作る
+  pub fn label(&self, options: &LabelOptions) -> Vec<String> {
+    let address = self.address();
+    let name = match options.honorific.trim() {
+      "" => self.name.japanese_name(),
+      honorific => format!("{} {}", self.name.japanese_name(), honorific),
+    };
+    match options.layout {
+      LabelLayout::Horizontal => {
+        let mut lines = address.japanese_lines();
+        lines.push(String::new());
+        lines.push(name);
+        lines
+      }
+      LabelLayout::Vertical => {
+        let mut lines = address.japanese_lines();
+        let postal_code = lines.remove(0);
+        // 右の列から住所、建物名、空き、宛名の順
+        lines.push(String::new());
+        lines.push(name);
+        let mut label = vec![postal_code, String::new()];
+        label.extend(vertical(&lines));
+        label
+      }
+      LabelLayout::Western => {
+        let mut lines = vec![self.name.western_name()];
+        lines.extend(address.western_lines());
+        lines
+      }
+    }
+  }
+}
+
+impl AddressBook {
+  // 登録順にラベルを columns 枚ずつ横に並べたテキスト。行の間は切り取り線で区切る
+  pub fn label_sheet(&self, options: &LabelOptions) -> String {
+    let labels = self.iter().map(|e| e.label(options)).collect::<Vec<_>>();
+    let cell_width = labels
+      .iter()
+      .flatten()
+      .map(|line| display_width(line))
+      .max()
+      .unwrap_or(0);
+    let columns = options.columns.max(1);
+    let rule = "-".repeat(cell_width * columns + " | ".len() * (columns - 1));
+    labels
+      .chunks(columns)
+      .map(|row| {
+        let height = row.iter().map(|label| label.len()).max().unwrap_or(0);
+        (0..height)
+          .map(|i| {
+            let line = row
+              .iter()
+              .map(|label| pad(label.get(i).map(|l| l.as_str()).unwrap_or(""), cell_width))
+              .collect::<Vec<_>>()
+              .join(" | ");
+            line.trim_end().to_owned()
+          })
+          .collect::<Vec<_>>()
+          .join("\n")
+      })
+      .collect::<Vec<_>>()
+      .join(&format!("\n{}\n", rule))
+  }
+}
+
+pub(crate) fn join_non_empty(parts: &[&str], separator: &str) -> String {
+  parts
+    .iter()
+    .map(|p| p.trim())
+    .filter(|p| !p.is_empty())
+    .collect::<Vec<_>>()
+    .join(separator)
+}
+
+// 各行を1列とし、右から左へ並べる
+fn vertical(lines: &[String]) -> Vec<String> {
+  let columns = lines
+    .iter()
+    .map(|line| line.chars().map(vertical_char).collect::<Vec<_>>())
+    .collect::<Vec<_>>();
+  let height = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+  (0..height)
+    .map(|i| {
+      let row = columns
+        .iter()
+        .rev()
+        .map(|column| column.get(i).copied().unwrap_or('　'))
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join("　");
+      row.trim_end_matches('　').to_owned()
+    })
+    .collect()
+}
+
+// 縦書きでは数字を漢数字に、英字と記号を全角にする
+fn vertical_char(c: char) -> char {
+  match c {
+    '0'..='9' => "〇一二三四五六七八九"
+      .chars()
+      .nth(c as usize - '0' as usize)
+      .unwrap(),
+    '-' | '－' | 'ー' | '−' => '｜',
+    ' ' => '　',
+    '!'..='~' => std::char::from_u32(c as u32 - '!' as u32 + '！' as u32).unwrap(),
+    _ => c,
+  }
+}
+
+// 全角文字を幅2として数える
+pub(crate) fn display_width(s: &str) -> usize {
+  s.chars()
+    .map(|c| match c {
+      '\u{FF61}'..='\u{FF9F}' => 1,
+      c if c >= '\u{1100}' => 2,
+      _ => 1,
+    })
+    .sum()
+}
+
+pub(crate) fn pad(s: &str, width: usize) -> String {
+  format!(
+    "{}{}",
+    s,
+    " ".repeat(width.saturating_sub(display_width(s)))
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, AddressEntryId, PostalCode, Prefecture};
+
+  fn entry(id: u64, last_name: &str, first_name: &str, building: Option<&str>) -> AddressEntry {
+    let mut entry = test_entry(id, first_name, last_name, "105-0011");
+    entry.address_mut().address = "港区芝公園4-2-8".to_owned();
+    entry.address_mut().building = building.map(|b| b.to_owned());
+    entry
+  }
+
+  #[test]
+  fn test_horizontal_and_western() {
+    let entry = entry(1, "山本", "太郎", Some("東京タワー 3F"));
+    assert_eq!(
+      entry.label(&LabelOptions::default()),
+      vec![
+        "〒105-0011",
+        "東京都港区芝公園4-2-8",
+        "東京タワー 3F",
+        "",
+        "山本 太郎 様"
+      ]
+    );
+    assert_eq!(
+      entry.label(&LabelOptions::default().with_honorific("御中"))[4],
+      "山本 太郎 御中"
+    );
+
+    let entry = AddressEntry::new(
+      AddressEntryId::new(2),
+      PersonName::new("Taro", "Yamamoto"),
+      Address::new(
+        PostalCode::new("105-0011").unwrap(),
+        Prefecture::Tokyo,
+        "4-2-8 Shibakoen, Minato-ku",
+        None,
+      ),
+    );
+    assert_eq!(
+      entry.label(&LabelOptions::new(LabelLayout::Western)),
+      vec![
+        "Taro Yamamoto",
+        "4-2-8 Shibakoen, Minato-ku",
+        "Tokyo 105-0011",
+        "JAPAN"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_vertical() {
+    let entry = entry(1, "山本", "太郎", Some("A棟"));
+    let label = entry.label(&LabelOptions::new(LabelLayout::Vertical));
+    assert_eq!(label[0], "〒105-0011");
+    assert_eq!(label[1], "");
+    // 右から住所、建物名、空き列、宛名
+    assert_eq!(label[2], "山　　　Ａ　東");
+    assert_eq!(label[3], "本　　　棟　京");
+    assert_eq!(label[4], "　　　　　　都");
+    assert_eq!(label[5], "太　　　　　港");
+    assert_eq!(label[12], "　　　　　　二");
+    assert_eq!(label[14], "　　　　　　八");
+    assert_eq!(label.len(), 15);
+  }
+
+  #[test]
+  fn test_label_sheet() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        entry(1, "山本", "太郎", None),
+        entry(2, "加藤", "純一", None),
+        entry(3, "佐藤", "花子", None),
+      ])
+      .unwrap();
+    let sheet = address_book.label_sheet(&LabelOptions::default());
+    let lines = sheet.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 9);
+    // セルの幅は一番長い住所の表示幅に揃える
+    assert_eq!(
+      lines[0],
+      format!("〒105-0011{}| 〒105-0011", " ".repeat(12))
+    );
+    assert_eq!(
+      lines[3],
+      format!("山本 太郎 様{}| 加藤 純一 様", " ".repeat(10))
+    );
+    assert!(lines[4].chars().all(|c| c == '-'));
+    assert_eq!(lines[8], "佐藤 花子 様");
+    assert_eq!(
+      address_book
+        .label_sheet(&LabelOptions::default().with_columns(3))
+        .lines()
+        .count(),
+      4
+    );
+    assert_eq!(
+      AddressBook::new("empty").label_sheet(&LabelOptions::default()),
+      ""
+    );
+  }
+}
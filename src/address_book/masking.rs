@@ -0,0 +1,417 @@
+use std::fmt;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::address_book::{
+  Address, AddressBook, AddressEntry, AddressEntryField, EmailAddress, FieldChange, PersonName,
+  PhoneNumber,
+};
+
+const MASK: &str = "***";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Masking {
+  Keep,
+  // 先頭のn文字だけ残し、残りを *** に置き換える
+  Partial(usize),
+  // ソルトを鍵にしたHMAC-SHA256に置き換える。同じソルトなら同じ値は同じハッシュになる
+  Hash,
+  // 任意項目はNoneに、必須項目は空文字列にする
+  Remove,
+}
+
+impl Masking {
+  fn apply(&self, value: &str, salt: &str) -> Option<String> {
+    match self {
+      Masking::Keep => Some(value.to_owned()),
+      Masking::Partial(visible) => {
+        let kept = value.chars().take(*visible).collect::<String>();
+        if kept.len() == value.len() {
+          Some(kept)
+        } else {
+          Some(format!("{}{}", kept, MASK))
+        }
+      }
+      Masking::Hash => Some(format!("#{}", hmac_sha256_hex(salt, value))),
+      Masking::Remove => None,
+    }
+  }
+}
+
+// 先頭64bitを16進数で返す
+fn hmac_sha256_hex(salt: &str, value: &str) -> String {
+  let mut mac =
+    Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC accepts any key length");
+  mac.update(value.as_bytes());
+  mac.finalize().into_bytes()[..8]
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+fn random_salt() -> String {
+  let mut bytes = [0u8; 32];
+  getrandom::getrandom(&mut bytes).expect("failed to read the OS random number generator");
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ソルトはハッシュの規則を加えた時点でランダムに作るため、with_salt を指定しない限りハッシュは実行ごとに変わる。
+// ハッシュを使わないポリシーはソルトを持たない
+#[derive(Clone, PartialEq)]
+pub struct MaskingPolicy {
+  rules: Vec<(AddressEntryField, Masking)>,
+  salt: Option<String>,
+}
+
+impl Default for MaskingPolicy {
+  // 外部に渡す前提の既定値。氏名はハッシュ、住所は先頭だけ残し、建物名と連絡先は消す
+  fn default() -> Self {
+    use AddressEntryField::*;
+    Self::none()
+      .mask(FirstName, Masking::Hash)
+      .mask(LastName, Masking::Hash)
+      .mask(FirstNameKana, Masking::Remove)
+      .mask(LastNameKana, Masking::Remove)
+      .mask(Address, Masking::Partial(3))
+      .mask(Building, Masking::Remove)
+      .mask(Phone, Masking::Remove)
+      .mask(Email, Masking::Remove)
+  }
+}
+
+impl MaskingPolicy {
+  // すべての項目をそのまま残す
+  pub fn none() -> Self {
+    Self {
+      rules: vec![],
+      salt: None,
+    }
+  }
+
+  // Debug出力用。値の有無と先頭だけが分かるようにする
+  pub fn debug() -> Self {
+    use AddressEntryField::*;
+    Self::none()
+      .mask(FirstName, Masking::Partial(1))
+      .mask(LastName, Masking::Partial(1))
+      .mask(FirstNameKana, Masking::Partial(1))
+      .mask(LastNameKana, Masking::Partial(1))
+      .mask(Address, Masking::Partial(3))
+      .mask(Building, Masking::Partial(0))
+      .mask(Phone, Masking::Partial(3))
+      .mask(Email, Masking::Partial(1))
+  }
+
+  // 同じフィールドを複数回指定した場合は最後のものを使う
+  pub fn mask(mut self, field: AddressEntryField, masking: Masking) -> Self {
+    if masking == Masking::Hash && self.salt.is_none() {
+      self.salt = Some(random_salt());
+    }
+    self.rules.retain(|(f, _)| *f != field);
+    self.rules.push((field, masking));
+    self
+  }
+
+  // エクスポートをまたいで同じハッシュにしたい場合に指定する。空のソルトは使えない
+  pub fn with_salt(mut self, salt: &str) -> Result<Self> {
+    if salt.is_empty() {
+      return Err(anyhow!("salt must not be empty"));
+    }
+    self.salt = Some(salt.to_owned());
+    Ok(self)
+  }
+
+  pub fn masking(&self, field: AddressEntryField) -> &Masking {
+    self
+      .rules
+      .iter()
+      .find(|(f, _)| *f == field)
+      .map(|(_, masking)| masking)
+      .unwrap_or(&Masking::Keep)
+  }
+
+  pub fn salt(&self) -> Option<&str> {
+    self.salt.as_deref()
+  }
+
+  fn required(&self, field: AddressEntryField, value: &str) -> String {
+    self
+      .masking(field)
+      .apply(value, self.salt().unwrap_or_default())
+      .unwrap_or_default()
+  }
+
+  fn optional(&self, field: AddressEntryField, value: Option<&str>) -> Option<String> {
+    value.and_then(|v| {
+      self
+        .masking(field)
+        .apply(v, self.salt().unwrap_or_default())
+    })
+  }
+}
+
+impl PersonName {
+  pub fn masked(&self, policy: &MaskingPolicy) -> PersonName {
+    use AddressEntryField::*;
+    PersonName {
+      first_name: policy.required(FirstName, &self.first_name),
+      last_name: policy.required(LastName, &self.last_name),
+      first_name_kana: policy.optional(FirstNameKana, self.first_name_kana.as_deref()),
+      last_name_kana: policy.optional(LastNameKana, self.last_name_kana.as_deref()),
+    }
+  }
+}
+
+impl Address {
+  // 郵便番号と都道府県は地域の集計に使うため残す
+  pub fn masked(&self, policy: &MaskingPolicy) -> Address {
+    Address {
+      postal_code: self.postal_code.clone(),
+      pref: self.pref,
+      address: policy.required(AddressEntryField::Address, &self.address),
+      building: policy.optional(AddressEntryField::Building, self.building.as_deref()),
+    }
+  }
+}
+
+impl AddressEntry {
+  // 電話番号とメールアドレスは型の制約上部分的に隠せないため、Keep以外は削除する
+  pub fn masked(&self, policy: &MaskingPolicy) -> AddressEntry {
+    let mut entry = self.clone();
+    entry.name = self.name.masked(policy);
+    for item in entry.addresses.iter_mut() {
+      item.value = item.value.masked(policy);
+    }
+    if *policy.masking(AddressEntryField::Phone) != Masking::Keep {
+      entry.phones = Default::default();
+    }
+    if *policy.masking(AddressEntryField::Email) != Masking::Keep {
+      entry.emails = Default::default();
+    }
+    entry
+  }
+}
+
+impl AddressBook {
+  // マスクしたエントリを持つ新しいアドレス帳。エクスポートの前に使う。
+  // グループの定義と検証規則は引き継ぎ、変更履歴とイベントは引き継がない
+  pub fn masked(&self, policy: &MaskingPolicy) -> AddressBook {
+    let mut masked =
+      AddressBook::new(self.name()).with_validation_rules(self.validation_rules.clone());
+    masked.groups = self.groups.clone();
+    for entry in self.iter() {
+      masked.insert_entry(entry.masked(policy)).unwrap();
+    }
+    masked
+  }
+}
+
+// ソルトはハッシュの鍵なので出力しない
+impl fmt::Debug for MaskingPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MaskingPolicy")
+      .field("rules", &self.rules)
+      .finish_non_exhaustive()
+  }
+}
+
+// ログにPIIが残らないよう、Debug出力は常に MaskingPolicy::debug でマスクする
+impl fmt::Debug for PersonName {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let masked = self.masked(&MaskingPolicy::debug());
+    f.debug_struct("PersonName")
+      .field("first_name", &masked.first_name)
+      .field("last_name", &masked.last_name)
+      .field("first_name_kana", &masked.first_name_kana)
+      .field("last_name_kana", &masked.last_name_kana)
+      .finish()
+  }
+}
+
+impl fmt::Debug for Address {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let masked = self.masked(&MaskingPolicy::debug());
+    f.debug_struct("Address")
+      .field("postal_code", &masked.postal_code)
+      .field("pref", &masked.pref)
+      .field("address", &masked.address)
+      .field("building", &masked.building)
+      .finish()
+  }
+}
+
+impl fmt::Debug for PhoneNumber {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let masked = MaskingPolicy::debug().required(AddressEntryField::Phone, self.digits());
+    f.debug_tuple("PhoneNumber").field(&masked).finish()
+  }
+}
+
+impl fmt::Debug for EmailAddress {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let masked = MaskingPolicy::debug().required(AddressEntryField::Email, self.as_str());
+    f.debug_tuple("EmailAddress").field(&masked).finish()
+  }
+}
+
+// EntryDiff や SyncConflict、AddressBookEvent の Debug もこれを通してマスクされる
+impl fmt::Debug for FieldChange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let policy = MaskingPolicy::debug();
+    f.debug_struct("FieldChange")
+      .field("field", &self.field)
+      .field(
+        "old_value",
+        &policy.optional(self.field, self.old_value.as_deref()),
+      )
+      .field(
+        "new_value",
+        &policy.optional(self.field, self.new_value.as_deref()),
+      )
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{
+    test_entry, AddressBookDiff, AddressEntryId, ValidationRules, ContactLabel, CsvEncoding,
+  };
+
+  fn entry() -> AddressEntry {
+    let mut entry = test_entry(1, "太郎", "山本", "105-0011");
+    entry.name = entry.name.with_kana("たろう", "やまもと");
+    entry.address_mut().address = "港区芝公園4-2-8".to_owned();
+    entry.address_mut().building = Some("東京タワー".to_owned());
+    entry
+      .phones
+      .push(ContactLabel::Mobile, "090-1234-5678".parse().unwrap());
+    entry
+      .emails
+      .push(ContactLabel::Home, "taro@example.com".parse().unwrap());
+    entry
+  }
+
+  #[test]
+  fn test_masked() {
+    let policy = MaskingPolicy::none()
+      .mask(AddressEntryField::FirstName, Masking::Partial(1))
+      .mask(AddressEntryField::LastName, Masking::Hash)
+      .mask(AddressEntryField::Address, Masking::Partial(2))
+      .mask(AddressEntryField::Building, Masking::Remove)
+      .with_salt("vendor-a")
+      .unwrap();
+    let masked = entry().masked(&policy);
+    assert_eq!(masked.name.first_name, "太***");
+    assert_eq!(masked.name.last_name.len(), 17);
+    assert!(masked.name.last_name.starts_with('#'));
+    assert_eq!(masked.name.first_name_kana.as_deref(), Some("たろう"));
+    assert_eq!(masked.address().address, "港区***");
+    assert_eq!(masked.address().building, None);
+    assert_eq!(masked.address().postal_code, entry().address().postal_code);
+    assert_eq!(masked.phones.len(), 1);
+
+    // 同じソルトなら同じハッシュ、異なるソルトなら異なるハッシュ
+    assert_eq!(entry().masked(&policy).name, masked.name);
+    let other = entry().masked(&policy.clone().with_salt("vendor-b").unwrap());
+    assert_ne!(other.name.last_name, masked.name.last_name);
+    assert!(policy.clone().with_salt("").is_err());
+    assert!(!format!("{:?}", policy).contains("vendor-a"));
+
+    // ソルトを指定しなければ実行ごとに異なるランダムなソルトになる
+    let policy = MaskingPolicy::none().mask(AddressEntryField::LastName, Masking::Hash);
+    assert_eq!(policy.salt().unwrap().len(), 64);
+    // ハッシュを使わないポリシーはソルトを作らない
+    assert_eq!(MaskingPolicy::debug().salt(), None);
+    assert_eq!(MaskingPolicy::none().salt(), None);
+    assert_ne!(
+      entry().masked(&policy).name.last_name,
+      entry()
+        .masked(&MaskingPolicy::none().mask(AddressEntryField::LastName, Masking::Hash))
+        .name
+        .last_name
+    );
+    assert_eq!(
+      Masking::Partial(5).apply("短い", ""),
+      Some("短い".to_owned())
+    );
+
+    let masked = entry().masked(&MaskingPolicy::default());
+    assert!(masked.phones.is_empty());
+    assert_eq!(masked.name.last_name_kana, None);
+  }
+
+  #[test]
+  fn test_masked_export() {
+    let mut address_book = AddressBook::new("test");
+    address_book.add_entry(entry()).unwrap();
+    // ランダムなソルトではハッシュ値に偶然 090 が含まれることがあるため、ソルトを固定する
+    let policy = MaskingPolicy::default().with_salt("vendor-a").unwrap();
+    let csv = address_book
+      .masked(&policy)
+      .export_csv(&Default::default(), CsvEncoding::Utf8)
+      .unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.contains("105-0011"));
+    for pii in &["山本", "太郎", "芝公園", "東京タワー", "090", "taro@"] {
+      assert!(!csv.contains(pii), "{} in {}", pii, csv);
+    }
+    assert_eq!(address_book.get(&AddressEntryId::new(1)), Some(&entry()));
+  }
+
+  #[test]
+  fn test_masked_book_keeps_groups() {
+    let mut address_book = AddressBook::new("test")
+      .with_validation_rules(ValidationRules::default().without(AddressEntryField::FirstName));
+    address_book
+      .create_group("friends", Some("学生時代の友人"))
+      .unwrap();
+    address_book.create_group("empty", None).unwrap();
+    let mut member = entry();
+    member.groups.insert("friends".to_owned());
+    address_book.add_entry(member).unwrap();
+
+    let masked = address_book.masked(&MaskingPolicy::default());
+    assert_eq!(
+      masked.groups().collect::<Vec<_>>(),
+      address_book.groups().collect::<Vec<_>>()
+    );
+    assert_eq!(masked.members("friends").count(), 1);
+    assert_eq!(masked.validation_rules(), address_book.validation_rules());
+  }
+
+  #[test]
+  fn test_debug() {
+    let debug = format!("{:?}", entry());
+    assert!(debug.contains(
+      r#"name: PersonName { first_name: "太***", last_name: "山***", first_name_kana: Some("た***"), last_name_kana: Some("や***") }"#
+    ));
+    assert!(debug.contains(r#"address: "港区芝***", building: Some("***")"#));
+    assert!(debug.contains(r#"PhoneNumber("090***")"#));
+    assert!(!debug.contains("東京タワー"));
+    for pii in &["090-1234-5678", "09012345678", "1234", "taro@"] {
+      assert!(!debug.contains(pii), "{} in {}", pii, debug);
+    }
+
+    let mut after = entry();
+    after.name.first_name = "次郎".to_owned();
+    after.phones = Default::default();
+    after
+      .phones
+      .push(ContactLabel::Mobile, "080-9876-5432".parse().unwrap());
+    let mut before = AddressBook::new("before");
+    before.add_entry(entry()).unwrap();
+    let mut after_book = AddressBook::new("after");
+    after_book.add_entry(after).unwrap();
+    let diff = AddressBookDiff::between(&before, &after_book);
+    let debug = format!("{:?}", diff);
+    assert!(debug.contains(r#"old_value: Some("090***"), new_value: Some("080***")"#));
+    for pii in &["太郎", "次郎", "1234", "9876", "taro@"] {
+      assert!(!debug.contains(pii), "{} in {}", pii, debug);
+    }
+  }
+}
@@ -0,0 +1,103 @@
+use crate::address_book::kana::{collation_key, CollationKey};
+use crate::address_book::label::join_non_empty;
+use crate::address_book::{AddressBook, AddressEntry, PersonName};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameOrder {
+  // 姓 名
+  FamilyFirst,
+  // 名 姓
+  GivenFirst,
+}
+
+fn is_japanese(c: char) -> bool {
+  matches!(c,
+    '\u{3005}' | '\u{3040}'..='\u{30FF}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{FF66}'..='\u{FF9F}'
+  )
+}
+
+impl PersonName {
+  // かなや漢字を含む氏名は姓を先にする
+  pub fn name_order(&self) -> NameOrder {
+    if self
+      .last_name
+      .chars()
+      .chain(self.first_name.chars())
+      .any(is_japanese)
+    {
+      NameOrder::FamilyFirst
+    } else {
+      NameOrder::GivenFirst
+    }
+  }
+
+  pub fn full_name(&self) -> String {
+    match self.name_order() {
+      NameOrder::FamilyFirst => self.japanese_name(),
+      NameOrder::GivenFirst => self.western_name(),
+    }
+  }
+
+  // 姓・名の順の読み。ふりがなのない部分は表記をそのまま使う
+  pub fn reading(&self) -> String {
+    let last_name = self.last_name_kana.as_deref().unwrap_or(&self.last_name);
+    let first_name = self.first_name_kana.as_deref().unwrap_or(&self.first_name);
+    join_non_empty(&[last_name, first_name], " ")
+  }
+
+  pub fn collation_key(&self) -> CollationKey {
+    collation_key(&self.reading())
+  }
+}
+
+impl AddressBook {
+  // 読みの五十音順。同じ読みは登録順
+  pub fn sorted_by_reading(&self) -> Vec<&AddressEntry> {
+    let mut entries = self.iter().collect::<Vec<_>>();
+    entries.sort_by_cached_key(|e| e.name.collation_key());
+    entries
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::test_entry;
+
+  #[test]
+  fn test_name_order() {
+    let name = PersonName::new("太郎", "山本").with_kana("たろう", "ヤマモト");
+    assert_eq!(name.name_order(), NameOrder::FamilyFirst);
+    assert_eq!(name.full_name(), "山本 太郎");
+    assert_eq!(name.reading(), "ヤマモト たろう");
+
+    let name = PersonName::new("Taro", "Yamamoto").with_kana("", " ");
+    assert_eq!(name.name_order(), NameOrder::GivenFirst);
+    assert_eq!(name.full_name(), "Taro Yamamoto");
+    assert_eq!(name.last_name_kana, None);
+    assert_eq!(name.reading(), "Yamamoto Taro");
+  }
+
+  #[test]
+  fn test_sorted_by_reading() {
+    let mut address_book = AddressBook::new("test");
+    let names = [
+      PersonName::new("太郎", "渡辺").with_kana("たろう", "わたなべ"),
+      PersonName::new("純一", "加藤").with_kana("ジュンイチ", "カトウ"),
+      PersonName::new("花子", "青木").with_kana("ﾊﾅｺ", "ｱｵｷ"),
+      PersonName::new("一郎", "加藤").with_kana("いちろう", "かとう"),
+      PersonName::new("次郎", "後藤").with_kana("じろう", "ごとう"),
+    ];
+    for (i, name) in names.iter().enumerate() {
+      let mut entry = test_entry(i as u64 + 1, "", "", "111-0001");
+      entry.name = name.clone();
+      address_book.add_entry(entry).unwrap();
+    }
+    let ids = address_book
+      .sorted_by_reading()
+      .iter()
+      .map(|e| e.id.0)
+      .collect::<Vec<_>>();
+    assert_eq!(ids, vec![3, 4, 2, 5, 1]);
+  }
+}
@@ -0,0 +1,286 @@
+use std::fmt;
+
+use crate::address_book::{AddressEntry, PostalCode, Prefecture};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressEntryField {
+  FirstName,
+  LastName,
+  FirstNameKana,
+  LastNameKana,
+  PostalCode,
+  Pref,
+  Address,
+  Building,
+  // 主電話番号と主メールアドレス
+  Phone,
+  Email,
+}
+
+impl AddressEntryField {
+  pub const ALL: [AddressEntryField; 10] = [
+    AddressEntryField::FirstName,
+    AddressEntryField::LastName,
+    AddressEntryField::FirstNameKana,
+    AddressEntryField::LastNameKana,
+    AddressEntryField::PostalCode,
+    AddressEntryField::Pref,
+    AddressEntryField::Address,
+    AddressEntryField::Building,
+    AddressEntryField::Phone,
+    AddressEntryField::Email,
+  ];
+
+  pub fn get(&self, entry: &AddressEntry) -> Option<String> {
+    match self {
+      AddressEntryField::FirstName => Some(entry.name.first_name.clone()),
+      AddressEntryField::LastName => Some(entry.name.last_name.clone()),
+      AddressEntryField::FirstNameKana => entry.name.first_name_kana.clone(),
+      AddressEntryField::LastNameKana => entry.name.last_name_kana.clone(),
+      AddressEntryField::PostalCode => Some(entry.address().postal_code.to_string()),
+      AddressEntryField::Pref => Some(entry.address().pref.to_string()),
+      AddressEntryField::Address => Some(entry.address().address.clone()),
+      AddressEntryField::Building => entry.address().building.clone(),
+      AddressEntryField::Phone => entry.phones.primary().map(|p| p.to_string()),
+      AddressEntryField::Email => entry.emails.primary().map(|e| e.to_string()),
+    }
+  }
+}
+
+impl fmt::Display for AddressEntryField {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      AddressEntryField::FirstName => "name.first_name",
+      AddressEntryField::LastName => "name.last_name",
+      AddressEntryField::FirstNameKana => "name.first_name_kana",
+      AddressEntryField::LastNameKana => "name.last_name_kana",
+      AddressEntryField::PostalCode => "address.postal_code",
+      AddressEntryField::Pref => "address.pref",
+      AddressEntryField::Address => "address.address",
+      AddressEntryField::Building => "address.building",
+      AddressEntryField::Phone => "phone",
+      AddressEntryField::Email => "email",
+    };
+    write!(f, "{}", s)
+  }
+}
+
+// Debug は masking.rs で値をマスクする
+#[derive(Clone, PartialEq)]
+pub struct FieldChange {
+  pub field: AddressEntryField,
+  pub old_value: Option<String>,
+  pub new_value: Option<String>,
+}
+
+impl FieldChange {
+  // 比較するのは AddressEntryField の各項目、つまり氏名・主住所・主電話番号・主メールアドレスだけで、
+  // タグ・グループ・主以外の住所と連絡先の変更は含まれない。
+  // エントリ全体の変更の有無はエントリどうしを比較して判断すること
+  pub fn diff(before: &AddressEntry, after: &AddressEntry) -> Vec<FieldChange> {
+    AddressEntryField::ALL
+      .iter()
+      .filter_map(|field| {
+        let old_value = field.get(before);
+        let new_value = field.get(after);
+        if old_value == new_value {
+          None
+        } else {
+          Some(FieldChange {
+            field: *field,
+            old_value,
+            new_value,
+          })
+        }
+      })
+      .collect()
+  }
+}
+
+// Noneのフィールドは変更しない。buildingは Some(None) でクリアする
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddressEntryPatch {
+  pub first_name: Option<String>,
+  pub last_name: Option<String>,
+  pub first_name_kana: Option<Option<String>>,
+  pub last_name_kana: Option<Option<String>>,
+  pub postal_code: Option<PostalCode>,
+  pub pref: Option<Prefecture>,
+  pub address: Option<String>,
+  pub building: Option<Option<String>>,
+}
+
+impl AddressEntryPatch {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_first_name(mut self, first_name: &str) -> Self {
+    self.first_name = Some(first_name.to_owned());
+    self
+  }
+
+  pub fn with_last_name(mut self, last_name: &str) -> Self {
+    self.last_name = Some(last_name.to_owned());
+    self
+  }
+
+  // 空文字列を渡すとふりがなを消す
+  pub fn with_kana(mut self, first_name_kana: &str, last_name_kana: &str) -> Self {
+    let kana = |s: &str| Some(Some(s.trim().to_owned()).filter(|s| !s.is_empty()));
+    self.first_name_kana = kana(first_name_kana);
+    self.last_name_kana = kana(last_name_kana);
+    self
+  }
+
+  pub fn with_postal_code(mut self, postal_code: PostalCode) -> Self {
+    self.postal_code = Some(postal_code);
+    self
+  }
+
+  pub fn with_pref(mut self, pref: Prefecture) -> Self {
+    self.pref = Some(pref);
+    self
+  }
+
+  pub fn with_address(mut self, address: &str) -> Self {
+    self.address = Some(address.to_owned());
+    self
+  }
+
+  pub fn with_building(mut self, building: &str) -> Self {
+    self.building = Some(Some(building.to_owned()));
+    self
+  }
+
+  pub fn clear_building(mut self) -> Self {
+    self.building = Some(None);
+    self
+  }
+
+  pub fn is_empty(&self) -> bool {
+    *self == Self::default()
+  }
+
+  pub fn apply(&self, entry: &mut AddressEntry) -> Vec<FieldChange> {
+    let before = entry.clone();
+    if let Some(first_name) = &self.first_name {
+      entry.name.first_name = first_name.clone();
+    }
+    if let Some(last_name) = &self.last_name {
+      entry.name.last_name = last_name.clone();
+    }
+    if let Some(first_name_kana) = &self.first_name_kana {
+      entry.name.first_name_kana = first_name_kana.clone();
+    }
+    if let Some(last_name_kana) = &self.last_name_kana {
+      entry.name.last_name_kana = last_name_kana.clone();
+    }
+    if let Some(postal_code) = &self.postal_code {
+      entry.address_mut().postal_code = postal_code.clone();
+    }
+    if let Some(pref) = self.pref {
+      entry.address_mut().pref = pref;
+    }
+    if let Some(address) = &self.address {
+      entry.address_mut().address = address.clone();
+    }
+    if let Some(building) = &self.building {
+      entry.address_mut().building = building.clone();
+    }
+    FieldChange::diff(&before, entry)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{Address, AddressEntryId, ContactLabel, PersonName};
+
+  #[test]
+  fn test_apply_patch() {
+    let mut entry = AddressEntry::new(
+      AddressEntryId::new(1),
+      PersonName::new("Junichi", "Kato"),
+      Address::new(
+        PostalCode::new("111-0001").unwrap(),
+        Prefecture::Tokyo,
+        "minato-ku 1",
+        Some("hoge 1 building"),
+      ),
+    );
+    let patch = AddressEntryPatch::new()
+      .with_first_name("Junichi")
+      .with_postal_code(PostalCode::new("222-0002").unwrap())
+      .clear_building();
+    let changes = patch.apply(&mut entry);
+    assert_eq!(
+      changes,
+      vec![
+        FieldChange {
+          field: AddressEntryField::PostalCode,
+          old_value: Some("111-0001".to_owned()),
+          new_value: Some("222-0002".to_owned()),
+        },
+        FieldChange {
+          field: AddressEntryField::Building,
+          old_value: Some("hoge 1 building".to_owned()),
+          new_value: None,
+        },
+      ]
+    );
+    assert_eq!(entry.address().building, None);
+    assert!(AddressEntryPatch::new().apply(&mut entry).is_empty());
+
+    let changes = AddressEntryPatch::new()
+      .with_kana("じゅんいち", "")
+      .apply(&mut entry);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].field, AddressEntryField::FirstNameKana);
+    assert_eq!(entry.name.last_name_kana, None);
+  }
+
+  #[test]
+  fn test_diff_ignores_collections() {
+    let before = AddressEntry::new(
+      AddressEntryId::new(1),
+      PersonName::new("Junichi", "Kato"),
+      Address::new(
+        PostalCode::new("111-0001").unwrap(),
+        Prefecture::Tokyo,
+        "minato-ku 1",
+        None,
+      ),
+    );
+    let mut after = before.clone();
+    after.add_tag("vip");
+    after.groups.insert("friends".to_owned());
+    after.add_address(
+      ContactLabel::Work,
+      Address::new(
+        PostalCode::new("100-0005").unwrap(),
+        Prefecture::Tokyo,
+        "chiyoda-ku 1",
+        None,
+      ),
+    );
+    after
+      .phones
+      .push(ContactLabel::Home, "03-1234-5678".parse().unwrap());
+    after
+      .phones
+      .push(ContactLabel::Mobile, "090-1234-5678".parse().unwrap());
+    assert_ne!(before, after);
+    // 主電話番号だけが差分に現れる
+    let changes = FieldChange::diff(&before, &after);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].field, AddressEntryField::Phone);
+
+    let mut second = after.clone();
+    second
+      .phones
+      .push(ContactLabel::Work, "03-9876-5432".parse().unwrap());
+    second.add_tag("friend");
+    assert!(FieldChange::diff(&after, &second).is_empty());
+  }
+}
@@ -0,0 +1,605 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use toml::value::Table;
+use toml::Value;
+
+use crate::address_book::{
+  Address, AddressBook, AddressEntry, AddressEntryId, ContactLabel, ContactList, EmailAddress,
+  MergeRecord, PersonName, PhoneNumber, PostalCode, Prefecture,
+};
+
+pub const SCHEMA_VERSION: i64 = 4;
+
+// version から version + 1 へドキュメントを書き換える
+pub type Migration = fn(&mut Table) -> Result<()>;
+
+#[derive(Debug, Clone)]
+pub struct Migrations {
+  migrations: BTreeMap<i64, Migration>,
+}
+
+// 組み込みのマイグレーションを登録済みのもの
+impl Default for Migrations {
+  fn default() -> Self {
+    Self::new()
+      .register(1, migrate_single_address)
+      .register(2, migrate_tags_and_groups)
+      .register(3, migrate_name_kana)
+  }
+}
+
+impl Migrations {
+  pub fn new() -> Self {
+    Self {
+      migrations: BTreeMap::new(),
+    }
+  }
+
+  pub fn register(mut self, from_version: i64, migration: Migration) -> Self {
+    self.migrations.insert(from_version, migration);
+    self
+  }
+
+  pub fn migrate(&self, document: &mut Table, target_version: i64) -> Result<()> {
+    let mut version = document
+      .get("schema_version")
+      .and_then(|v| v.as_integer())
+      .ok_or_else(|| anyhow!("missing schema_version"))?;
+    if version > target_version {
+      return Err(anyhow!(
+        "unsupported schema_version: {} (latest is {})",
+        version,
+        target_version
+      ));
+    }
+    while version < target_version {
+      let migration = self
+        .migrations
+        .get(&version)
+        .ok_or_else(|| anyhow!("no migration from schema_version {}", version))?;
+      migration(document)?;
+      version += 1;
+      document.insert("schema_version".to_owned(), Value::Integer(version));
+    }
+    Ok(())
+  }
+}
+
+fn table<'a>(value: &'a Table, key: &str) -> Result<&'a Table> {
+  value
+    .get(key)
+    .and_then(|v| v.as_table())
+    .ok_or_else(|| anyhow!("missing table: {}", key))
+}
+
+fn string<'a>(value: &'a Table, key: &str) -> Result<&'a str> {
+  value
+    .get(key)
+    .and_then(|v| v.as_str())
+    .ok_or_else(|| anyhow!("missing string: {}", key))
+}
+
+fn address_to_toml(address: &Address) -> Table {
+  let mut table = Table::new();
+  table.insert(
+    "postal_code".to_owned(),
+    Value::from(address.postal_code.to_string()),
+  );
+  table.insert("pref".to_owned(), Value::from(address.pref.kanji()));
+  table.insert("address".to_owned(), Value::from(address.address.as_str()));
+  if let Some(building) = &address.building {
+    table.insert("building".to_owned(), Value::from(building.as_str()));
+  }
+  table
+}
+
+fn address_from_toml(value: &Table) -> Result<Address> {
+  Ok(Address::new(
+    PostalCode::new(string(value, "postal_code")?)?,
+    Prefecture::parse(string(value, "pref")?)?,
+    string(value, "address")?,
+    value.get("building").and_then(|v| v.as_str()),
+  ))
+}
+
+// 連絡先は label と primary を持つテーブルの配列にする
+fn contacts_to_toml<T, F>(list: &ContactList<T>, to_table: F) -> Value
+where
+  F: Fn(&T) -> Table,
+{
+  let items = list
+    .iter()
+    .enumerate()
+    .map(|(i, item)| {
+      let mut table = to_table(&item.value);
+      table.insert("label".to_owned(), Value::from(item.label.to_string()));
+      if list.primary_index() == Some(i) {
+        table.insert("primary".to_owned(), Value::Boolean(true));
+      }
+      Value::Table(table)
+    })
+    .collect();
+  Value::Array(items)
+}
+
+fn contacts_from_toml<T, F>(value: &Table, key: &str, from_table: F) -> Result<ContactList<T>>
+where
+  F: Fn(&Table) -> Result<T>,
+{
+  let items = match value.get(key) {
+    Some(Value::Array(items)) => items.as_slice(),
+    Some(_) => return Err(anyhow!("{} must be an array", key)),
+    None => &[],
+  };
+  let mut list = ContactList::new();
+  for (i, item) in items.iter().enumerate() {
+    let item = item
+      .as_table()
+      .ok_or_else(|| anyhow!("{}[{}]: must be a table", key, i))?;
+    let label = item.get("label").and_then(|v| v.as_str()).unwrap_or("home");
+    let index = list.push(
+      ContactLabel::from(label),
+      from_table(item).map_err(|e| anyhow!("{}[{}]: {}", key, i, e))?,
+    );
+    if item.get("primary").and_then(|v| v.as_bool()) == Some(true) {
+      list.set_primary(index)?;
+    }
+  }
+  Ok(list)
+}
+
+pub(crate) fn entry_to_toml(entry: &AddressEntry) -> Value {
+  let mut name = Table::new();
+  name.insert(
+    "first_name".to_owned(),
+    Value::from(entry.name.first_name.as_str()),
+  );
+  name.insert(
+    "last_name".to_owned(),
+    Value::from(entry.name.last_name.as_str()),
+  );
+  if let Some(kana) = &entry.name.first_name_kana {
+    name.insert("first_name_kana".to_owned(), Value::from(kana.as_str()));
+  }
+  if let Some(kana) = &entry.name.last_name_kana {
+    name.insert("last_name_kana".to_owned(), Value::from(kana.as_str()));
+  }
+  let mut table = Table::new();
+  table.insert("id".to_owned(), Value::Integer(entry.id.0 as i64));
+  table.insert("name".to_owned(), Value::Table(name));
+  table.insert(
+    "addresses".to_owned(),
+    contacts_to_toml(entry.addresses(), address_to_toml),
+  );
+  if !entry.phones.is_empty() {
+    table.insert(
+      "phones".to_owned(),
+      contacts_to_toml(&entry.phones, |phone| {
+        let mut table = Table::new();
+        table.insert("number".to_owned(), Value::from(phone.to_string()));
+        table
+      }),
+    );
+  }
+  if !entry.emails.is_empty() {
+    table.insert(
+      "emails".to_owned(),
+      contacts_to_toml(&entry.emails, |email| {
+        let mut table = Table::new();
+        table.insert("address".to_owned(), Value::from(email.as_str()));
+        table
+      }),
+    );
+  }
+  if !entry.tags.is_empty() {
+    table.insert("tags".to_owned(), strings_to_toml(entry.tags.iter()));
+  }
+  if !entry.groups.is_empty() {
+    table.insert("groups".to_owned(), strings_to_toml(entry.groups.iter()));
+  }
+  Value::Table(table)
+}
+
+pub(crate) fn entry_from_toml(value: &Table) -> Result<AddressEntry> {
+  let id = value
+    .get("id")
+    .and_then(|v| v.as_integer())
+    .filter(|id| *id >= 0)
+    .ok_or_else(|| anyhow!("missing id"))?;
+  let name = table(value, "name")?;
+  let mut entry = AddressEntry::with_addresses(
+    AddressEntryId::new(id as u64),
+    PersonName::new(string(name, "first_name")?, string(name, "last_name")?).with_kana(
+      name
+        .get("first_name_kana")
+        .and_then(|v| v.as_str())
+        .unwrap_or(""),
+      name
+        .get("last_name_kana")
+        .and_then(|v| v.as_str())
+        .unwrap_or(""),
+    ),
+    contacts_from_toml(value, "addresses", address_from_toml)?,
+  )?;
+  entry.phones = contacts_from_toml(value, "phones", |t| PhoneNumber::new(string(t, "number")?))?;
+  entry.emails = contacts_from_toml(value, "emails", |t| {
+    EmailAddress::new(string(t, "address")?)
+  })?;
+  entry.tags = strings_from_toml(value, "tags")?.into_iter().collect();
+  entry.groups = strings_from_toml(value, "groups")?.into_iter().collect();
+  Ok(entry)
+}
+
+fn strings_to_toml<'a>(values: impl Iterator<Item = &'a String>) -> Value {
+  Value::Array(values.map(|v| Value::from(v.as_str())).collect())
+}
+
+fn strings_from_toml(value: &Table, key: &str) -> Result<Vec<String>> {
+  match value.get(key) {
+    Some(Value::Array(values)) => values
+      .iter()
+      .map(|v| {
+        v.as_str()
+          .map(|s| s.to_owned())
+          .ok_or_else(|| anyhow!("{} must be an array of strings", key))
+      })
+      .collect(),
+    Some(_) => Err(anyhow!("{} must be an array", key)),
+    None => Ok(vec![]),
+  }
+}
+
+// v1: 住所は1件で entries[].address に入っていた
+fn migrate_single_address(document: &mut Table) -> Result<()> {
+  if let Some(Value::Array(entries)) = document.get_mut("entries") {
+    for entry in entries.iter_mut().filter_map(|e| e.as_table_mut()) {
+      if let Some(Value::Table(mut address)) = entry.remove("address") {
+        address.insert("label".to_owned(), Value::from("home"));
+        address.insert("primary".to_owned(), Value::Boolean(true));
+        entry.insert(
+          "addresses".to_owned(),
+          Value::Array(vec![Value::Table(address)]),
+        );
+      }
+    }
+  }
+  Ok(())
+}
+
+// v2: タグとグループがない。どちらも省略できるので書き換えは不要
+fn migrate_tags_and_groups(_document: &mut Table) -> Result<()> {
+  Ok(())
+}
+
+// v3: ふりがながない。省略できるので書き換えは不要
+fn migrate_name_kana(_document: &mut Table) -> Result<()> {
+  Ok(())
+}
+
+// 同じディレクトリの一時ファイルに書いてからrenameする
+fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+  let file_name = path
+    .file_name()
+    .ok_or_else(|| anyhow!("invalid path: {}", path.display()))?
+    .to_string_lossy();
+  let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+  let result = (|| {
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+  })();
+  if result.is_err() {
+    let _ = fs::remove_file(&tmp_path);
+  }
+  Ok(result?)
+}
+
+impl AddressBook {
+  pub fn to_toml(&self) -> Result<String> {
+    let mut document = Table::new();
+    document.insert("schema_version".to_owned(), Value::Integer(SCHEMA_VERSION));
+    document.insert("name".to_owned(), Value::from(self.name()));
+    document.insert(
+      "entries".to_owned(),
+      Value::Array(self.iter().map(entry_to_toml).collect()),
+    );
+    if !self.groups.is_empty() {
+      let groups = self
+        .groups()
+        .map(|group| {
+          let mut table = Table::new();
+          table.insert("name".to_owned(), Value::from(group.name.as_str()));
+          if let Some(description) = &group.description {
+            table.insert("description".to_owned(), Value::from(description.as_str()));
+          }
+          Value::Table(table)
+        })
+        .collect();
+      document.insert("groups".to_owned(), Value::Array(groups));
+    }
+    if !self.merges.is_empty() {
+      let merges = self
+        .merges
+        .iter()
+        .map(|record| {
+          let mut table = Table::new();
+          table.insert(
+            "survivor".to_owned(),
+            Value::Integer(record.survivor.0 as i64),
+          );
+          table.insert("merged".to_owned(), Value::Integer(record.merged.0 as i64));
+          Value::Table(table)
+        })
+        .collect();
+      document.insert("merges".to_owned(), Value::Array(merges));
+    }
+    Ok(toml::to_string(&Value::Table(document))?)
+  }
+
+  pub fn from_toml(content: &str, migrations: &Migrations) -> Result<Self> {
+    let mut document = match content.parse::<Value>()? {
+      Value::Table(document) => document,
+      _ => return Err(anyhow!("document must be a table")),
+    };
+    migrations.migrate(&mut document, SCHEMA_VERSION)?;
+    let mut address_book = AddressBook::new(string(&document, "name")?);
+    if let Some(Value::Array(groups)) = document.get("groups") {
+      for (i, group) in groups.iter().enumerate() {
+        group
+          .as_table()
+          .ok_or_else(|| anyhow!("must be a table"))
+          .and_then(|group| {
+            address_book.create_group(
+              string(group, "name")?,
+              group.get("description").and_then(|v| v.as_str()),
+            )
+          })
+          .map_err(|e| anyhow!("groups[{}]: {}", i, e))?;
+      }
+    }
+    let entries = match document.get("entries") {
+      Some(Value::Array(entries)) => entries.as_slice(),
+      Some(_) => return Err(anyhow!("entries must be an array")),
+      None => &[],
+    };
+    for (i, entry) in entries.iter().enumerate() {
+      entry
+        .as_table()
+        .ok_or_else(|| anyhow!("entries[{}]: must be a table", i))
+        .and_then(entry_from_toml)
+        .and_then(|entry| address_book.insert_entry(entry))
+        .map_err(|e| anyhow!("entries[{}]: {}", i, e))?;
+    }
+    if let Some(Value::Array(merges)) = document.get("merges") {
+      for (i, record) in merges.iter().enumerate() {
+        let id = |key: &str| {
+          record
+            .get(key)
+            .and_then(|v| v.as_integer())
+            .filter(|id| *id >= 0)
+            .map(|id| AddressEntryId::new(id as u64))
+            .ok_or_else(|| anyhow!("merges[{}]: missing {}", i, key))
+        };
+        address_book.merges.push(MergeRecord {
+          survivor: id("survivor")?,
+          merged: id("merged")?,
+        });
+      }
+    }
+    address_book.clear_history();
+    Ok(address_book)
+  }
+
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    Self::load_with_migrations(path, &Migrations::default())
+  }
+
+  pub fn load_with_migrations<P: AsRef<Path>>(path: P, migrations: &Migrations) -> Result<Self> {
+    let content = fs::read_to_string(path)?;
+    Self::from_toml(&content, migrations)
+  }
+
+  pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    write_atomic(path.as_ref(), self.to_toml()?.as_bytes())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sandbox-rs-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+  }
+
+  fn address_book() -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        AddressEntry::new(
+          AddressEntryId::new(2),
+          PersonName::new("Junichi", "Kato"),
+          Address::new(
+            PostalCode::new("111-0001").unwrap(),
+            Prefecture::Tokyo,
+            "minato-ku 1",
+            Some("hoge 1 building"),
+          ),
+        ),
+        AddressEntry::new(
+          AddressEntryId::new(1),
+          PersonName::new("Taro", "Yamamoto"),
+          Address::new(
+            PostalCode::new("530-0001").unwrap(),
+            Prefecture::Osaka,
+            "kita-ku 2",
+            None,
+          ),
+        ),
+      ])
+      .unwrap();
+    address_book
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let path = temp_path("test_save_and_load.toml");
+    let address_book = address_book();
+    address_book.save(&path).unwrap();
+    let loaded = AddressBook::load(&path).unwrap();
+    assert_eq!(loaded.name(), "test");
+    assert_eq!(
+      loaded.iter().map(|e| e.id.0).collect::<Vec<_>>(),
+      vec![2, 1]
+    );
+    let entry = loaded.get(&AddressEntryId::new(2)).unwrap();
+    assert_eq!(entry.address().building.as_deref(), Some("hoge 1 building"));
+    assert_eq!(
+      loaded
+        .get(&AddressEntryId::new(1))
+        .unwrap()
+        .address()
+        .building,
+      None
+    );
+    assert!(!path.with_file_name(".test_save_and_load.toml.tmp").exists());
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_contacts_round_trip() {
+    let mut address_book = address_book();
+    {
+      let mut entry = address_book.get_mut(&AddressEntryId::new(1)).unwrap();
+      let work = entry.add_address(
+        ContactLabel::Work,
+        Address::new(
+          PostalCode::new("100-0005").unwrap(),
+          Prefecture::Tokyo,
+          "千代田区丸の内1",
+          None,
+        ),
+      );
+      entry.set_primary_address(work).unwrap();
+      entry
+        .phones
+        .push(ContactLabel::Mobile, "090-1234-5678".parse().unwrap());
+      entry
+        .emails
+        .push(ContactLabel::Work, "taro@Example.COM".parse().unwrap());
+      entry.name = entry.name.clone().with_kana("じゅんいち", "かとう");
+    }
+    let loaded =
+      AddressBook::from_toml(&address_book.to_toml().unwrap(), &Migrations::default()).unwrap();
+    assert_eq!(
+      loaded.get(&AddressEntryId::new(1)),
+      address_book.get(&AddressEntryId::new(1))
+    );
+    let entry = loaded.get(&AddressEntryId::new(1)).unwrap();
+    assert_eq!(entry.address().pref, Prefecture::Tokyo);
+    assert_eq!(entry.addresses().len(), 2);
+    assert_eq!(entry.emails.primary().unwrap().as_str(), "taro@example.com");
+  }
+
+  #[test]
+  fn test_groups_round_trip() {
+    let mut address_book = address_book();
+    address_book
+      .create_group("customers", Some("取引先"))
+      .unwrap();
+    address_book.create_group("empty", None).unwrap();
+    address_book
+      .add_to_group(&AddressEntryId::new(2), "customers")
+      .unwrap();
+    address_book
+      .get_mut(&AddressEntryId::new(2))
+      .unwrap()
+      .add_tag("vip");
+    let loaded =
+      AddressBook::from_toml(&address_book.to_toml().unwrap(), &Migrations::default()).unwrap();
+    assert_eq!(
+      loaded.groups().collect::<Vec<_>>(),
+      address_book.groups().collect::<Vec<_>>()
+    );
+    assert_eq!(loaded.members("customers").count(), 1);
+    assert_eq!(loaded.find_by_tag("vip").count(), 1);
+  }
+
+  #[test]
+  fn test_migrate_single_address() {
+    let v1 = r#"
+schema_version = 1
+name = "v1"
+
+[[entries]]
+id = 1
+
+[entries.name]
+first_name = "Junichi"
+last_name = "Kato"
+
+[entries.address]
+postal_code = "111-0001"
+pref = "東京都"
+address = "minato-ku 1"
+"#;
+    let address_book = AddressBook::from_toml(v1, &Migrations::default()).unwrap();
+    let entry = address_book.get(&AddressEntryId::new(1)).unwrap();
+    assert_eq!(entry.addresses().len(), 1);
+    assert_eq!(entry.addresses().get(0).unwrap().label, ContactLabel::Home);
+    assert_eq!(entry.address().address, "minato-ku 1");
+    assert!(AddressBook::from_toml(v1, &Migrations::new()).is_err());
+  }
+
+  #[test]
+  fn test_migration() {
+    let old = r#"
+schema_version = 0
+name = "old"
+
+[[entries]]
+id = 1
+first_name = "Junichi"
+last_name = "Kato"
+postal_code = "1110001"
+pref = "Tokyo-to"
+address = "minato-ku 1"
+"#;
+    assert!(AddressBook::from_toml(old, &Migrations::default()).is_err());
+
+    fn nest_entries(document: &mut Table) -> Result<()> {
+      if let Some(Value::Array(entries)) = document.get_mut("entries") {
+        for entry in entries.iter_mut().filter_map(|e| e.as_table_mut()) {
+          let mut name = Table::new();
+          let mut address = Table::new();
+          for key in &["first_name", "last_name"] {
+            name.insert(key.to_string(), entry.remove(*key).unwrap());
+          }
+          for key in &["postal_code", "pref", "address"] {
+            address.insert(key.to_string(), entry.remove(*key).unwrap());
+          }
+          entry.insert("name".to_owned(), Value::Table(name));
+          entry.insert("address".to_owned(), Value::Table(address));
+        }
+      }
+      Ok(())
+    }
+    let migrations = Migrations::default().register(0, nest_entries);
+    let address_book = AddressBook::from_toml(old, &migrations).unwrap();
+    let entry = address_book.get(&AddressEntryId::new(1)).unwrap();
+    assert_eq!(entry.address().pref, Prefecture::Tokyo);
+    assert_eq!(entry.address().postal_code.to_string(), "111-0001");
+
+    let newer = "schema_version = 99\nname = \"new\"\n";
+    assert!(AddressBook::from_toml(newer, &migrations).is_err());
+  }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+// 全角数字・各種ハイフンを半角に揃える
+pub fn normalize_digits(s: &str) -> String {
+  s.chars()
+    .map(|c| match c {
+      '０'..='９' => std::char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap(),
+      '－' | '‐' | '‑' | '–' | '—' | '―' | 'ー' | '−' | 'ｰ' => '-',
+      _ => c,
+    })
+    .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PostalCode(String);
+
+impl PostalCode {
+  pub fn new(value: &str) -> Result<Self> {
+    let normalized = normalize_digits(value);
+    let trimmed = normalized
+      .trim_matches(|c: char| c.is_whitespace())
+      .trim_start_matches('〒')
+      .trim();
+    let digits = match trimmed.find('-') {
+      Some(3) if trimmed.len() == 8 => trimmed.replacen('-', "", 1),
+      Some(_) => return Err(anyhow!("invalid postal code: {}", value)),
+      None => trimmed.to_owned(),
+    };
+    if digits.len() != 7 || !digits.chars().all(|c| c.is_ascii_digit()) {
+      return Err(anyhow!("invalid postal code: {}", value));
+    }
+    Ok(Self(digits))
+  }
+
+  pub fn digits(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for PostalCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}-{}", &self.0[..3], &self.0[3..])
+  }
+}
+
+impl FromStr for PostalCode {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Self::new(s)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_normalize() {
+    let expected = PostalCode::new("111-0001").unwrap();
+    assert_eq!(PostalCode::new("1110001").unwrap(), expected);
+    assert_eq!(PostalCode::new("１１１－０００１").unwrap(), expected);
+    assert_eq!(PostalCode::new("〒111-0001 ").unwrap(), expected);
+    assert_eq!(expected.to_string(), "111-0001");
+    assert_eq!(expected.digits(), "1110001");
+  }
+
+  #[test]
+  fn test_invalid() {
+    assert!(PostalCode::new("").is_err());
+    assert!(PostalCode::new("111-000").is_err());
+    assert!(PostalCode::new("11-10001").is_err());
+    assert!(PostalCode::new("111-00a1").is_err());
+    assert!(PostalCode::new("111--0001").is_err());
+    assert!("11100011".parse::<PostalCode>().is_err());
+  }
+}
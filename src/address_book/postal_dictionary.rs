@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::csv::parse_records;
+use crate::address_book::{Address, CsvEncoding, PostalCode, Prefecture};
+
+// KEN_ALL.CSV の列位置
+const POSTAL_CODE_COLUMN: usize = 2;
+const PREF_COLUMN: usize = 6;
+const MUNICIPALITY_COLUMN: usize = 7;
+const TOWN_COLUMN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostalCodeRecord {
+  pub postal_code: PostalCode,
+  pub pref: Prefecture,
+  pub municipality: String,
+  pub town: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressSuggestion {
+  pub pref: Prefecture,
+  pub municipality: String,
+  // 同じ郵便番号に複数の町域がある場合はNone
+  pub town: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressConsistency {
+  Consistent,
+  UnknownPostalCode,
+  PrefectureMismatch { expected: Prefecture },
+  MunicipalityMismatch { expected: Vec<String> },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PostalCodeDictionary {
+  records: HashMap<PostalCode, Vec<PostalCodeRecord>>,
+}
+
+impl PostalCodeDictionary {
+  // 日本郵便が配布するKEN_ALL.CSVはShift_JIS
+  pub fn load<P: AsRef<Path>>(path: P, encoding: CsvEncoding) -> Result<Self> {
+    let content = encoding.decode(&std::fs::read(path)?)?;
+    Self::from_csv_str(&content)
+  }
+
+  // 町域名が長い場合、KEN_ALLは同じ郵便番号の連続する行に分割して載せる。
+  // 「（」が閉じていない町域は「）」が現れるまで後続の行とつなげる
+  pub fn from_csv_str(content: &str) -> Result<Self> {
+    let mut dictionary = Self::default();
+    let mut pending: Option<PostalCodeRecord> = None;
+    for (i, record) in parse_records(content)?.into_iter().enumerate() {
+      if record.len() <= TOWN_COLUMN {
+        return Err(anyhow!(
+          "line {}: expected at least {} columns",
+          i + 1,
+          TOWN_COLUMN + 1
+        ));
+      }
+      let postal_code = PostalCode::new(&record[POSTAL_CODE_COLUMN])
+        .map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+      let pref =
+        Prefecture::parse(&record[PREF_COLUMN]).map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+      let town = &record[TOWN_COLUMN];
+      let record = match pending.take() {
+        Some(mut continued) if continued.postal_code == postal_code => {
+          if let Some(t) = continued.town.as_mut() {
+            t.push_str(town);
+          }
+          continued
+        }
+        previous => {
+          // 閉じないまま郵便番号が変わった場合は、そこまでを1件とする
+          if let Some(previous) = previous {
+            dictionary.insert(previous);
+          }
+          PostalCodeRecord {
+            postal_code,
+            pref,
+            municipality: record[MUNICIPALITY_COLUMN].clone(),
+            town: if town.is_empty() || town == "以下に掲載がない場合" {
+              None
+            } else {
+              Some(town.clone())
+            },
+          }
+        }
+      };
+      match &record.town {
+        Some(town) if town.contains('（') && !town.contains('）') => pending = Some(record),
+        _ => dictionary.insert(record),
+      }
+    }
+    if let Some(record) = pending {
+      dictionary.insert(record);
+    }
+    Ok(dictionary)
+  }
+
+  pub fn insert(&mut self, record: PostalCodeRecord) {
+    self
+      .records
+      .entry(record.postal_code.clone())
+      .or_default()
+      .push(record);
+  }
+
+  pub fn len(&self) -> usize {
+    self.records.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.records.is_empty()
+  }
+
+  pub fn lookup(&self, postal_code: &PostalCode) -> &[PostalCodeRecord] {
+    self
+      .records
+      .get(postal_code)
+      .map(|records| records.as_slice())
+      .unwrap_or(&[])
+  }
+
+  pub fn suggest(&self, postal_code: &PostalCode) -> Option<AddressSuggestion> {
+    let records = self.lookup(postal_code);
+    let first = records.first()?;
+    let same = |f: &dyn Fn(&PostalCodeRecord) -> bool| records.iter().all(f);
+    if !same(&|r| r.pref == first.pref && r.municipality == first.municipality) {
+      return None;
+    }
+    Some(AddressSuggestion {
+      pref: first.pref,
+      municipality: first.municipality.clone(),
+      town: if same(&|r| r.town == first.town) {
+        first.town.clone()
+      } else {
+        None
+      },
+    })
+  }
+
+  pub fn check(&self, address: &Address) -> AddressConsistency {
+    let records = self.lookup(&address.postal_code);
+    if records.is_empty() {
+      return AddressConsistency::UnknownPostalCode;
+    }
+    if records.iter().all(|r| r.pref != address.pref) {
+      return AddressConsistency::PrefectureMismatch {
+        expected: records[0].pref,
+      };
+    }
+    if records
+      .iter()
+      .any(|r| r.pref == address.pref && address.address.starts_with(r.municipality.as_str()))
+    {
+      AddressConsistency::Consistent
+    } else {
+      let mut expected = records
+        .iter()
+        .map(|r| r.municipality.clone())
+        .collect::<Vec<_>>();
+      expected.dedup();
+      AddressConsistency::MunicipalityMismatch { expected }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KEN_ALL: &str = "\
+13103,\"105  \",\"1050011\",\"ﾄｳｷｮｳﾄ\",\"ﾐﾅﾄｸ\",\"ｼﾊﾞｺｳｴﾝ\",\"東京都\",\"港区\",\"芝公園\",0,0,1,0,0,0
+13103,\"105  \",\"1050000\",\"ﾄｳｷｮｳﾄ\",\"ﾐﾅﾄｸ\",\"ｲｶﾆｹｲｻｲｶﾞﾅｲﾊﾞｱｲ\",\"東京都\",\"港区\",\"以下に掲載がない場合\",0,0,0,0,0,0
+27127,\"530  \",\"5300001\",\"ｵｵｻｶﾌ\",\"ｵｵｻｶｼｷﾀｸ\",\"ｳﾒﾀﾞ\",\"大阪府\",\"大阪市北区\",\"梅田\",0,0,1,0,0,0
+27127,\"530  \",\"5300001\",\"ｵｵｻｶﾌ\",\"ｵｵｻｶｼｷﾀｸ\",\"ｳﾒﾀﾞ\",\"大阪府\",\"大阪市北区\",\"梅田（次のビルを除く）\",0,0,1,0,0,0
+";
+
+  fn postal_code(value: &str) -> PostalCode {
+    PostalCode::new(value).unwrap()
+  }
+
+  #[test]
+  fn test_suggest() {
+    let dictionary = PostalCodeDictionary::from_csv_str(KEN_ALL).unwrap();
+    assert_eq!(dictionary.len(), 3);
+    assert_eq!(
+      dictionary.suggest(&postal_code("105-0011")),
+      Some(AddressSuggestion {
+        pref: Prefecture::Tokyo,
+        municipality: "港区".to_owned(),
+        town: Some("芝公園".to_owned()),
+      })
+    );
+    assert_eq!(
+      dictionary.suggest(&postal_code("105-0000")).unwrap().town,
+      None
+    );
+    assert_eq!(
+      dictionary.suggest(&postal_code("530-0001")).unwrap().town,
+      None
+    );
+    assert_eq!(dictionary.suggest(&postal_code("999-9999")), None);
+  }
+
+  #[test]
+  fn test_check() {
+    let dictionary = PostalCodeDictionary::from_csv_str(KEN_ALL).unwrap();
+    let address = |code: &str, pref: Prefecture, address: &str| {
+      Address::new(postal_code(code), pref, address, None)
+    };
+    assert_eq!(
+      dictionary.check(&address("105-0011", Prefecture::Tokyo, "港区芝公園4-2-8")),
+      AddressConsistency::Consistent
+    );
+    assert_eq!(
+      dictionary.check(&address("105-0011", Prefecture::Osaka, "港区芝公園4-2-8")),
+      AddressConsistency::PrefectureMismatch {
+        expected: Prefecture::Tokyo
+      }
+    );
+    assert_eq!(
+      dictionary.check(&address("105-0011", Prefecture::Tokyo, "千代田区1-1")),
+      AddressConsistency::MunicipalityMismatch {
+        expected: vec!["港区".to_owned()]
+      }
+    );
+    assert_eq!(
+      dictionary.check(&address("999-9999", Prefecture::Tokyo, "港区")),
+      AddressConsistency::UnknownPostalCode
+    );
+  }
+
+  #[test]
+  fn test_continuation_rows() {
+    let content = "\
+01101,\"060  \",\"0600042\",\"ﾎｯｶｲﾄﾞｳ\",\"ｻｯﾎﾟﾛｼﾁｭｳｵｳｸ\",\"ｵｵﾄﾞｵﾘﾆｼ(1-19ﾁｮｳﾒ)\",\"北海道\",\"札幌市中央区\",\"大通西（１～１９丁目）\",1,0,1,0,0,0
+26104,\"604  \",\"6048101\",\"ｷｮｳﾄﾌ\",\"ｷｮｳﾄｼﾅｶｷﾞｮｳｸ\",\"ﾔﾅｷﾞﾉﾊﾞﾝﾊﾞﾁｮｳ\",\"京都府\",\"京都市中京区\",\"柳馬場町（御池通上る、御池通下る、\",0,0,0,0,0,0
+26104,\"604  \",\"6048101\",\"ｷｮｳﾄﾌ\",\"ｷｮｳﾄｼﾅｶｷﾞｮｳｸ\",\"ﾔﾅｷﾞﾉﾊﾞﾝﾊﾞﾁｮｳ\",\"京都府\",\"京都市中京区\",\"姉小路通下る）\",0,0,0,0,0,0
+26104,\"604  \",\"6048101\",\"ｷｮｳﾄﾌ\",\"ｷｮｳﾄｼﾅｶｷﾞｮｳｸ\",\"ﾔﾅｷﾞﾉﾊﾞﾝﾊﾞﾁｮｳ\",\"京都府\",\"京都市中京区\",\"柳馬場町\",0,0,0,0,0,0
+";
+    let dictionary = PostalCodeDictionary::from_csv_str(content).unwrap();
+    let towns = dictionary
+      .lookup(&postal_code("604-8101"))
+      .iter()
+      .map(|r| r.town.as_deref().unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      towns,
+      vec![
+        "柳馬場町（御池通上る、御池通下る、姉小路通下る）",
+        "柳馬場町"
+      ]
+    );
+    assert_eq!(dictionary.lookup(&postal_code("060-0042")).len(), 1);
+  }
+
+  #[test]
+  fn test_load_shift_jis() {
+    let path = std::env::temp_dir().join(format!("sandbox-rs-ken-all-{}.csv", std::process::id()));
+    std::fs::write(&path, CsvEncoding::ShiftJis.encode(KEN_ALL).unwrap()).unwrap();
+    let dictionary = PostalCodeDictionary::load(&path, CsvEncoding::ShiftJis).unwrap();
+    assert_eq!(
+      dictionary
+        .suggest(&postal_code("105-0011"))
+        .unwrap()
+        .municipality,
+      "港区"
+    );
+    assert!(PostalCodeDictionary::load(&path, CsvEncoding::Utf8).is_err());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_invalid_csv() {
+    assert!(PostalCodeDictionary::from_csv_str("13103,\"105\",\"10500\"\n").is_err());
+    assert!(PostalCodeDictionary::from_csv_str(
+      "13103,\"105  \",\"1050011\",\"\",\"\",\"\",\"Atlantis\",\"港区\",\"芝公園\"\n"
+    )
+    .is_err());
+  }
+}
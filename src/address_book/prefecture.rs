@@ -0,0 +1,312 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::kana::{katakana_to_hiragana, phonetic_key};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Region {
+  Hokkaido,
+  Tohoku,
+  Kanto,
+  Chubu,
+  Kinki,
+  Chugoku,
+  Shikoku,
+  // 沖縄を含む
+  Kyushu,
+}
+
+impl Region {
+  pub fn prefectures(&self) -> impl Iterator<Item = Prefecture> + '_ {
+    Prefecture::ALL
+      .iter()
+      .copied()
+      .filter(move |p| p.region() == *self)
+  }
+}
+
+// 列挙子の値はJIS X 0401の都道府県コード。並び順もコード順になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Prefecture {
+  Hokkaido = 1,
+  Aomori = 2,
+  Iwate = 3,
+  Miyagi = 4,
+  Akita = 5,
+  Yamagata = 6,
+  Fukushima = 7,
+  Ibaraki = 8,
+  Tochigi = 9,
+  Gunma = 10,
+  Saitama = 11,
+  Chiba = 12,
+  Tokyo = 13,
+  Kanagawa = 14,
+  Niigata = 15,
+  Toyama = 16,
+  Ishikawa = 17,
+  Fukui = 18,
+  Yamanashi = 19,
+  Nagano = 20,
+  Gifu = 21,
+  Shizuoka = 22,
+  Aichi = 23,
+  Mie = 24,
+  Shiga = 25,
+  Kyoto = 26,
+  Osaka = 27,
+  Hyogo = 28,
+  Nara = 29,
+  Wakayama = 30,
+  Tottori = 31,
+  Shimane = 32,
+  Okayama = 33,
+  Hiroshima = 34,
+  Yamaguchi = 35,
+  Tokushima = 36,
+  Kagawa = 37,
+  Ehime = 38,
+  Kochi = 39,
+  Fukuoka = 40,
+  Saga = 41,
+  Nagasaki = 42,
+  Kumamoto = 43,
+  Oita = 44,
+  Miyazaki = 45,
+  Kagoshima = 46,
+  Okinawa = 47,
+}
+
+impl Prefecture {
+  pub const ALL: [Prefecture; 47] = [
+    Prefecture::Hokkaido,
+    Prefecture::Aomori,
+    Prefecture::Iwate,
+    Prefecture::Miyagi,
+    Prefecture::Akita,
+    Prefecture::Yamagata,
+    Prefecture::Fukushima,
+    Prefecture::Ibaraki,
+    Prefecture::Tochigi,
+    Prefecture::Gunma,
+    Prefecture::Saitama,
+    Prefecture::Chiba,
+    Prefecture::Tokyo,
+    Prefecture::Kanagawa,
+    Prefecture::Niigata,
+    Prefecture::Toyama,
+    Prefecture::Ishikawa,
+    Prefecture::Fukui,
+    Prefecture::Yamanashi,
+    Prefecture::Nagano,
+    Prefecture::Gifu,
+    Prefecture::Shizuoka,
+    Prefecture::Aichi,
+    Prefecture::Mie,
+    Prefecture::Shiga,
+    Prefecture::Kyoto,
+    Prefecture::Osaka,
+    Prefecture::Hyogo,
+    Prefecture::Nara,
+    Prefecture::Wakayama,
+    Prefecture::Tottori,
+    Prefecture::Shimane,
+    Prefecture::Okayama,
+    Prefecture::Hiroshima,
+    Prefecture::Yamaguchi,
+    Prefecture::Tokushima,
+    Prefecture::Kagawa,
+    Prefecture::Ehime,
+    Prefecture::Kochi,
+    Prefecture::Fukuoka,
+    Prefecture::Saga,
+    Prefecture::Nagasaki,
+    Prefecture::Kumamoto,
+    Prefecture::Oita,
+    Prefecture::Miyazaki,
+    Prefecture::Kagoshima,
+    Prefecture::Okinawa,
+  ];
+
+  fn info(&self) -> (&'static str, &'static str, &'static str, Region) {
+    match self {
+      Prefecture::Hokkaido => ("北海道", "ほっかいどう", "Hokkaido", Region::Hokkaido),
+      Prefecture::Aomori => ("青森県", "あおもりけん", "Aomori-ken", Region::Tohoku),
+      Prefecture::Iwate => ("岩手県", "いわてけん", "Iwate-ken", Region::Tohoku),
+      Prefecture::Miyagi => ("宮城県", "みやぎけん", "Miyagi-ken", Region::Tohoku),
+      Prefecture::Akita => ("秋田県", "あきたけん", "Akita-ken", Region::Tohoku),
+      Prefecture::Yamagata => ("山形県", "やまがたけん", "Yamagata-ken", Region::Tohoku),
+      Prefecture::Fukushima => ("福島県", "ふくしまけん", "Fukushima-ken", Region::Tohoku),
+      Prefecture::Ibaraki => ("茨城県", "いばらきけん", "Ibaraki-ken", Region::Kanto),
+      Prefecture::Tochigi => ("栃木県", "とちぎけん", "Tochigi-ken", Region::Kanto),
+      Prefecture::Gunma => ("群馬県", "ぐんまけん", "Gunma-ken", Region::Kanto),
+      Prefecture::Saitama => ("埼玉県", "さいたまけん", "Saitama-ken", Region::Kanto),
+      Prefecture::Chiba => ("千葉県", "ちばけん", "Chiba-ken", Region::Kanto),
+      Prefecture::Tokyo => ("東京都", "とうきょうと", "Tokyo-to", Region::Kanto),
+      Prefecture::Kanagawa => ("神奈川県", "かながわけん", "Kanagawa-ken", Region::Kanto),
+      Prefecture::Niigata => ("新潟県", "にいがたけん", "Niigata-ken", Region::Chubu),
+      Prefecture::Toyama => ("富山県", "とやまけん", "Toyama-ken", Region::Chubu),
+      Prefecture::Ishikawa => ("石川県", "いしかわけん", "Ishikawa-ken", Region::Chubu),
+      Prefecture::Fukui => ("福井県", "ふくいけん", "Fukui-ken", Region::Chubu),
+      Prefecture::Yamanashi => ("山梨県", "やまなしけん", "Yamanashi-ken", Region::Chubu),
+      Prefecture::Nagano => ("長野県", "ながのけん", "Nagano-ken", Region::Chubu),
+      Prefecture::Gifu => ("岐阜県", "ぎふけん", "Gifu-ken", Region::Chubu),
+      Prefecture::Shizuoka => ("静岡県", "しずおかけん", "Shizuoka-ken", Region::Chubu),
+      Prefecture::Aichi => ("愛知県", "あいちけん", "Aichi-ken", Region::Chubu),
+      Prefecture::Mie => ("三重県", "みえけん", "Mie-ken", Region::Kinki),
+      Prefecture::Shiga => ("滋賀県", "しがけん", "Shiga-ken", Region::Kinki),
+      Prefecture::Kyoto => ("京都府", "きょうとふ", "Kyoto-fu", Region::Kinki),
+      Prefecture::Osaka => ("大阪府", "おおさかふ", "Osaka-fu", Region::Kinki),
+      Prefecture::Hyogo => ("兵庫県", "ひょうごけん", "Hyogo-ken", Region::Kinki),
+      Prefecture::Nara => ("奈良県", "ならけん", "Nara-ken", Region::Kinki),
+      Prefecture::Wakayama => ("和歌山県", "わかやまけん", "Wakayama-ken", Region::Kinki),
+      Prefecture::Tottori => ("鳥取県", "とっとりけん", "Tottori-ken", Region::Chugoku),
+      Prefecture::Shimane => ("島根県", "しまねけん", "Shimane-ken", Region::Chugoku),
+      Prefecture::Okayama => ("岡山県", "おかやまけん", "Okayama-ken", Region::Chugoku),
+      Prefecture::Hiroshima => ("広島県", "ひろしまけん", "Hiroshima-ken", Region::Chugoku),
+      Prefecture::Yamaguchi => ("山口県", "やまぐちけん", "Yamaguchi-ken", Region::Chugoku),
+      Prefecture::Tokushima => ("徳島県", "とくしまけん", "Tokushima-ken", Region::Shikoku),
+      Prefecture::Kagawa => ("香川県", "かがわけん", "Kagawa-ken", Region::Shikoku),
+      Prefecture::Ehime => ("愛媛県", "えひめけん", "Ehime-ken", Region::Shikoku),
+      Prefecture::Kochi => ("高知県", "こうちけん", "Kochi-ken", Region::Shikoku),
+      Prefecture::Fukuoka => ("福岡県", "ふくおかけん", "Fukuoka-ken", Region::Kyushu),
+      Prefecture::Saga => ("佐賀県", "さがけん", "Saga-ken", Region::Kyushu),
+      Prefecture::Nagasaki => ("長崎県", "ながさきけん", "Nagasaki-ken", Region::Kyushu),
+      Prefecture::Kumamoto => ("熊本県", "くまもとけん", "Kumamoto-ken", Region::Kyushu),
+      Prefecture::Oita => ("大分県", "おおいたけん", "Oita-ken", Region::Kyushu),
+      Prefecture::Miyazaki => ("宮崎県", "みやざきけん", "Miyazaki-ken", Region::Kyushu),
+      Prefecture::Kagoshima => ("鹿児島県", "かごしまけん", "Kagoshima-ken", Region::Kyushu),
+      Prefecture::Okinawa => ("沖縄県", "おきなわけん", "Okinawa-ken", Region::Kyushu),
+    }
+  }
+
+  pub fn from_code(code: u8) -> Option<Self> {
+    Self::ALL.get((code as usize).checked_sub(1)?).copied()
+  }
+
+  pub fn code(&self) -> u8 {
+    *self as u8
+  }
+
+  pub fn kanji(&self) -> &'static str {
+    self.info().0
+  }
+
+  pub fn kana(&self) -> &'static str {
+    self.info().1
+  }
+
+  pub fn romaji(&self) -> &'static str {
+    self.info().2
+  }
+
+  pub fn region(&self) -> Region {
+    self.info().3
+  }
+
+  // 「都」「府」「県」を除いた表記。北海道はそのまま
+  pub fn kanji_short(&self) -> &'static str {
+    let kanji = self.kanji();
+    match *self {
+      Prefecture::Hokkaido => kanji,
+      _ => &kanji[..kanji.len() - '県'.len_utf8()],
+    }
+  }
+
+  // 漢字は完全一致(接尾辞の省略可)、かな・ローマ字は読みで照合する
+  pub fn parse(value: &str) -> Result<Self> {
+    let value = value.trim();
+    if let Some(code) = value.parse::<u8>().ok().and_then(Self::from_code) {
+      return Ok(code);
+    }
+    let found = Self::ALL
+      .iter()
+      .find(|p| p.kanji() == value || p.kanji_short() == value)
+      .copied()
+      .or_else(|| {
+        let key = phonetic_key(&katakana_to_hiragana(value));
+        if key.is_empty() {
+          return None;
+        }
+        Self::ALL
+          .iter()
+          .copied()
+          .find(|p| phonetic_key(p.romaji()) == key || phonetic_key(p.name_without_suffix()) == key)
+      });
+    found.ok_or_else(|| anyhow!("unknown prefecture: {}", value))
+  }
+
+  pub(crate) fn name_without_suffix(&self) -> &'static str {
+    let romaji = self.romaji();
+    romaji.split('-').next().unwrap_or(romaji)
+  }
+}
+
+impl fmt::Display for Prefecture {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.kanji())
+  }
+}
+
+impl FromStr for Prefecture {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Self::parse(s)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_codes() {
+    for (i, p) in Prefecture::ALL.iter().enumerate() {
+      assert_eq!(p.code() as usize, i + 1);
+      assert_eq!(Prefecture::from_code(p.code()), Some(*p));
+    }
+    assert_eq!(Prefecture::from_code(0), None);
+    assert_eq!(Prefecture::from_code(48), None);
+    assert_eq!(Prefecture::Tokyo.code(), 13);
+  }
+
+  #[test]
+  fn test_parse() {
+    for s in &[
+      "Tokyo-to",
+      "tokyo",
+      "TOKYO TO",
+      "東京都",
+      "東京",
+      "とうきょうと",
+      "トウキョウ",
+      "13",
+    ] {
+      assert_eq!(Prefecture::parse(s).unwrap(), Prefecture::Tokyo, "{}", s);
+    }
+    assert_eq!(Prefecture::parse("Hyougo").unwrap(), Prefecture::Hyogo);
+    assert_eq!(Prefecture::parse("北海道").unwrap(), Prefecture::Hokkaido);
+    assert_eq!(Prefecture::parse("Kyoto-fu").unwrap(), Prefecture::Kyoto);
+    assert_eq!(Prefecture::parse("おおいたけん").unwrap(), Prefecture::Oita);
+    assert!(Prefecture::parse("Atlantis").is_err());
+    assert!(Prefecture::parse("").is_err());
+    for p in Prefecture::ALL.iter() {
+      assert_eq!(Prefecture::parse(p.kanji()).unwrap(), *p);
+      assert_eq!(Prefecture::parse(p.kana()).unwrap(), *p);
+      assert_eq!(Prefecture::parse(p.romaji()).unwrap(), *p);
+    }
+  }
+
+  #[test]
+  fn test_region() {
+    assert_eq!(Region::Kanto.prefectures().count(), 7);
+    assert_eq!(Region::Shikoku.prefectures().count(), 4);
+    assert_eq!(Prefecture::Okinawa.region(), Region::Kyushu);
+    assert_eq!(Prefecture::Osaka.to_string(), "大阪府");
+    assert_eq!(Prefecture::Osaka.kanji_short(), "大阪");
+  }
+}
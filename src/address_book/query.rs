@@ -0,0 +1,312 @@
+use std::cmp::Ordering;
+use std::ops::Not;
+
+use crate::address_book::postal_code::normalize_digits;
+use crate::address_book::{AddressBook, AddressEntry, AddressEntryField, Prefecture};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+  Pref(Prefecture),
+  PostalCodePrefix(String),
+  NameContains(String),
+  NameStartsWith(String),
+  HasBuilding,
+  Tag(String),
+  InGroup(String),
+  And(Vec<Condition>),
+  Or(Vec<Condition>),
+  Not(Box<Condition>),
+}
+
+impl Condition {
+  pub fn pref(pref: Prefecture) -> Self {
+    Condition::Pref(pref)
+  }
+
+  // ハイフンの有無・全角を問わず、数字の並びで前方一致させる
+  pub fn postal_code_prefix(prefix: &str) -> Self {
+    Condition::PostalCodePrefix(
+      normalize_digits(prefix)
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect(),
+    )
+  }
+
+  pub fn name_contains(value: &str) -> Self {
+    Condition::NameContains(value.to_lowercase())
+  }
+
+  pub fn name_starts_with(value: &str) -> Self {
+    Condition::NameStartsWith(value.to_lowercase())
+  }
+
+  pub fn has_building() -> Self {
+    Condition::HasBuilding
+  }
+
+  pub fn tag(tag: &str) -> Self {
+    Condition::Tag(tag.trim().to_owned())
+  }
+
+  pub fn in_group(group: &str) -> Self {
+    Condition::InGroup(group.trim().to_owned())
+  }
+
+  pub fn and(self, other: Condition) -> Self {
+    match self {
+      Condition::And(mut conditions) => {
+        conditions.push(other);
+        Condition::And(conditions)
+      }
+      _ => Condition::And(vec![self, other]),
+    }
+  }
+
+  pub fn or(self, other: Condition) -> Self {
+    match self {
+      Condition::Or(mut conditions) => {
+        conditions.push(other);
+        Condition::Or(conditions)
+      }
+      _ => Condition::Or(vec![self, other]),
+    }
+  }
+
+  pub fn matches(&self, entry: &AddressEntry) -> bool {
+    let names = || {
+      vec![
+        entry.name.first_name.to_lowercase(),
+        entry.name.last_name.to_lowercase(),
+      ]
+    };
+    match self {
+      Condition::Pref(pref) => entry.address().pref == *pref,
+      Condition::PostalCodePrefix(prefix) => entry
+        .address()
+        .postal_code
+        .digits()
+        .starts_with(prefix.as_str()),
+      Condition::NameContains(value) => names().iter().any(|n| n.contains(value.as_str())),
+      Condition::NameStartsWith(value) => names().iter().any(|n| n.starts_with(value.as_str())),
+      Condition::HasBuilding => entry.address().building.is_some(),
+      Condition::Tag(tag) => entry.tags.contains(tag),
+      Condition::InGroup(group) => entry.groups.contains(group),
+      Condition::And(conditions) => conditions.iter().all(|c| c.matches(entry)),
+      Condition::Or(conditions) => conditions.iter().any(|c| c.matches(entry)),
+      Condition::Not(condition) => !condition.matches(entry),
+    }
+  }
+
+  // 都道府県の完全一致が必須条件なら、二次インデックスで候補を絞り込める
+  fn required_pref(&self) -> Option<Prefecture> {
+    match self {
+      Condition::Pref(pref) => Some(*pref),
+      Condition::And(conditions) => conditions.iter().find_map(|c| c.required_pref()),
+      _ => None,
+    }
+  }
+}
+
+impl Not for Condition {
+  type Output = Condition;
+
+  fn not(self) -> Self::Output {
+    Condition::Not(Box::new(self))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+  Id,
+  Field(AddressEntryField),
+  // 氏名の読みの五十音順
+  Reading,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+  Asc,
+  Desc,
+}
+
+impl SortKey {
+  fn compare(&self, a: &AddressEntry, b: &AddressEntry) -> Ordering {
+    match self {
+      SortKey::Id => a.id.0.cmp(&b.id.0),
+      SortKey::Reading => a.name.collation_key().cmp(&b.name.collation_key()),
+      SortKey::Field(AddressEntryField::Pref) => a.address().pref.cmp(&b.address().pref),
+      SortKey::Field(AddressEntryField::PostalCode) => {
+        a.address().postal_code.cmp(&b.address().postal_code)
+      }
+      SortKey::Field(field) => field.get(a).cmp(&field.get(b)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+  condition: Option<Condition>,
+  sort_keys: Vec<(SortKey, SortOrder)>,
+  offset: usize,
+  limit: Option<usize>,
+}
+
+impl Query {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn filter(mut self, condition: Condition) -> Self {
+    self.condition = Some(match self.condition {
+      Some(current) => current.and(condition),
+      None => condition,
+    });
+    self
+  }
+
+  pub fn sort_by(mut self, key: SortKey, order: SortOrder) -> Self {
+    self.sort_keys.push((key, order));
+    self
+  }
+
+  pub fn offset(mut self, offset: usize) -> Self {
+    self.offset = offset;
+    self
+  }
+
+  pub fn limit(mut self, limit: usize) -> Self {
+    self.limit = Some(limit);
+    self
+  }
+
+  pub fn matches(&self, entry: &AddressEntry) -> bool {
+    self.condition.as_ref().is_none_or(|c| c.matches(entry))
+  }
+
+  pub fn execute<'a>(&self, address_book: &'a AddressBook) -> QueryResult<'a> {
+    let candidates: Box<dyn Iterator<Item = &'a AddressEntry>> =
+      match self.condition.as_ref().and_then(|c| c.required_pref()) {
+        Some(pref) => Box::new(address_book.find_by_pref(pref)),
+        None => Box::new(address_book.iter()),
+      };
+    let mut entries = candidates.filter(|e| self.matches(e)).collect::<Vec<_>>();
+    if !self.sort_keys.is_empty() {
+      entries.sort_by(|a, b| {
+        self
+          .sort_keys
+          .iter()
+          .map(|(key, order)| match order {
+            SortOrder::Asc => key.compare(a, b),
+            SortOrder::Desc => key.compare(b, a),
+          })
+          .find(|ordering| *ordering != Ordering::Equal)
+          .unwrap_or(Ordering::Equal)
+      });
+    }
+    let total = entries.len();
+    let entries = entries
+      .into_iter()
+      .skip(self.offset)
+      .take(self.limit.unwrap_or(usize::MAX))
+      .collect();
+    QueryResult { total, entries }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryResult<'a> {
+  pub total: usize,
+  pub entries: Vec<&'a AddressEntry>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{Address, AddressEntryId, PersonName, PostalCode};
+
+  fn address_book() -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        AddressEntry::new(
+          AddressEntryId::new(1),
+          PersonName::new("Junichi", "Kato"),
+          Address::new(
+            PostalCode::new("111-0001").unwrap(),
+            Prefecture::Tokyo,
+            "minato-ku 1",
+            Some("hoge 1 building"),
+          ),
+        ),
+        AddressEntry::new(
+          AddressEntryId::new(2),
+          PersonName::new("Taro", "Yamamoto"),
+          Address::new(
+            PostalCode::new("530-0001").unwrap(),
+            Prefecture::Osaka,
+            "kita-ku 2",
+            None,
+          ),
+        ),
+        AddressEntry::new(
+          AddressEntryId::new(3),
+          PersonName::new("Hanako", "Yamada"),
+          Address::new(
+            PostalCode::new("111-0003").unwrap(),
+            Prefecture::Tokyo,
+            "minato-ku 3",
+            None,
+          ),
+        ),
+      ])
+      .unwrap();
+    address_book
+  }
+
+  fn ids(result: &QueryResult<'_>) -> Vec<u64> {
+    result.entries.iter().map(|e| e.id.0).collect()
+  }
+
+  #[test]
+  fn test_filter() {
+    let address_book = address_book();
+    let result = Query::new()
+      .filter(Condition::pref(Prefecture::Tokyo).and(!Condition::has_building()))
+      .execute(&address_book);
+    assert_eq!(ids(&result), vec![3]);
+
+    let result = Query::new()
+      .filter(Condition::postal_code_prefix("５３０-").or(Condition::name_contains("KAT")))
+      .execute(&address_book);
+    assert_eq!(ids(&result), vec![1, 2]);
+
+    let result = Query::new()
+      .filter(Condition::name_starts_with("yama"))
+      .execute(&address_book);
+    assert_eq!(ids(&result), vec![2, 3]);
+  }
+
+  #[test]
+  fn test_sort_and_paginate() {
+    let address_book = address_book();
+    let result = Query::new()
+      .sort_by(SortKey::Field(AddressEntryField::LastName), SortOrder::Desc)
+      .offset(1)
+      .limit(1)
+      .execute(&address_book);
+    assert_eq!(result.total, 3);
+    assert_eq!(ids(&result), vec![3]);
+
+    let result = Query::new()
+      .sort_by(SortKey::Field(AddressEntryField::Pref), SortOrder::Asc)
+      .sort_by(SortKey::Id, SortOrder::Desc)
+      .execute(&address_book);
+    assert_eq!(ids(&result), vec![3, 1, 2]);
+
+    let result = Query::new()
+      .sort_by(SortKey::Reading, SortOrder::Asc)
+      .execute(&address_book);
+    assert_eq!(ids(&result), vec![1, 3, 2]);
+  }
+}
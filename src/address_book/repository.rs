@@ -0,0 +1,105 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::address_book::{AddressEntry, AddressEntryId, Query};
+
+pub use crate::address_book::repository::append_only_file::AppendOnlyFileRepository;
+pub use crate::address_book::repository::in_memory::InMemoryRepository;
+
+mod append_only_file;
+mod in_memory;
+
+// QueryResult の所有版。リポジトリはロックの外へ参照を返せないため
+#[derive(Debug, Clone, Default)]
+pub struct QueryPage {
+  pub total: usize,
+  pub entries: Vec<AddressEntry>,
+}
+
+#[async_trait]
+pub trait AddressBookRepository: Send + Sync {
+  async fn find_by_id(&self, id: &AddressEntryId) -> Result<Option<AddressEntry>>;
+
+  async fn find_by_query(&self, query: &Query) -> Result<QueryPage>;
+
+  // 同じIDのエントリがあれば置き換える
+  async fn store(&self, entry: AddressEntry) -> Result<()>;
+
+  async fn delete(&self, id: &AddressEntryId) -> Result<Option<AddressEntry>>;
+}
+
+#[cfg(test)]
+pub(crate) mod conformance {
+  use super::*;
+  use crate::address_book::{test_entry, AddressEntryField, Condition, Prefecture, SortKey, SortOrder};
+
+  // 住所はCSVの区切り文字を含める
+  fn entry(id: u64, first_name: &str, last_name: &str, pref: Prefecture) -> AddressEntry {
+    let mut entry = test_entry(id, first_name, last_name, "111-0001");
+    entry.address_mut().pref = pref;
+    entry.address_mut().address = "minato-ku, 1".to_owned();
+    entry
+  }
+
+  // すべてのバックエンドが満たすべき振る舞い。空のリポジトリを渡すこと
+  pub(crate) async fn run<R: AddressBookRepository>(repository: &R) {
+    let id = AddressEntryId::new(1);
+    assert!(repository.find_by_id(&id).await.unwrap().is_none());
+    assert!(repository.delete(&id).await.unwrap().is_none());
+
+    repository
+      .store(entry(1, "Junichi", "Kato", Prefecture::Tokyo))
+      .await
+      .unwrap();
+    repository
+      .store(entry(2, "Taro", "Yamamoto", Prefecture::Osaka))
+      .await
+      .unwrap();
+    repository
+      .store(entry(3, "Hanako", "Yamada", Prefecture::Tokyo))
+      .await
+      .unwrap();
+    let found = repository.find_by_id(&id).await.unwrap().unwrap();
+    assert_eq!(found.name.first_name, "Junichi");
+    assert_eq!(found.address().address, "minato-ku, 1");
+
+    repository
+      .store(entry(1, "Jun", "Kato", Prefecture::Tokyo))
+      .await
+      .unwrap();
+    let found = repository.find_by_id(&id).await.unwrap().unwrap();
+    assert_eq!(found.name.first_name, "Jun");
+
+    let too_large = AddressEntryId::new(u64::MAX);
+    assert!(repository
+      .store(entry(u64::MAX, "Taro", "Suzuki", Prefecture::Tokyo))
+      .await
+      .is_err());
+    assert!(repository.find_by_id(&too_large).await.unwrap().is_none());
+
+    let page = repository
+      .find_by_query(
+        &Query::new()
+          .filter(Condition::pref(Prefecture::Tokyo))
+          .sort_by(SortKey::Field(AddressEntryField::FirstName), SortOrder::Asc)
+          .limit(1),
+      )
+      .await
+      .unwrap();
+    assert_eq!(page.total, 2);
+    assert_eq!(page.entries[0].id, AddressEntryId::new(3));
+
+    let deleted = repository.delete(&AddressEntryId::new(2)).await.unwrap();
+    assert_eq!(deleted.unwrap().name.last_name, "Yamamoto");
+    assert!(repository
+      .find_by_id(&AddressEntryId::new(2))
+      .await
+      .unwrap()
+      .is_none());
+    let page = repository.find_by_query(&Query::new()).await.unwrap();
+    assert_eq!(
+      page.entries.iter().map(|e| e.id.0).collect::<Vec<_>>(),
+      vec![1, 3]
+    );
+  }
+}
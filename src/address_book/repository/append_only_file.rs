@@ -0,0 +1,378 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+
+use crate::address_book::csv::{build_entry, parse_records, write_record};
+use crate::address_book::id_generator::checked_id;
+use crate::address_book::persistence::{entry_from_toml, entry_to_toml};
+use crate::address_book::repository::{AddressBookRepository, QueryPage};
+use crate::address_book::{
+  AddressBook, AddressEntry, AddressEntryField, AddressEntryId, CsvColumn, Query,
+};
+
+// 1行1操作のCSVログ
+//   store,<id>,<エントリのTOML>
+//   delete,<id>
+// 旧形式の store,<id>,<first_name>,<last_name>,<postal_code>,<pref>,<address>,<building> も読める
+const STORE: &str = "store";
+const DELETE: &str = "delete";
+
+const LEGACY_FIELDS: [AddressEntryField; 6] = [
+  AddressEntryField::FirstName,
+  AddressEntryField::LastName,
+  AddressEntryField::PostalCode,
+  AddressEntryField::Pref,
+  AddressEntryField::Address,
+  AddressEntryField::Building,
+];
+
+fn store_record(entry: &AddressEntry) -> Result<String> {
+  Ok(write_record(&[
+    STORE.to_owned(),
+    entry.id.0.to_string(),
+    toml::to_string(&entry_to_toml(entry))?,
+  ]))
+}
+
+fn parse_store_record(record: &[String], id: AddressEntryId) -> Result<AddressEntry> {
+  if record.len() == 3 {
+    let entry = match record[2].parse::<toml::Value>()? {
+      toml::Value::Table(table) => entry_from_toml(&table)?,
+      _ => return Err(anyhow!("entry must be a table")),
+    };
+    if entry.id != id {
+      return Err(anyhow!("id mismatch: {}", entry.id.0));
+    }
+    return Ok(entry);
+  }
+  let values = |column: CsvColumn| match column {
+    CsvColumn::Id => None,
+    CsvColumn::Field(field) => {
+      let index = LEGACY_FIELDS.iter().position(|f| *f == field)?;
+      record.get(index + 2).cloned()
+    }
+  };
+  build_entry(values, id)
+}
+
+fn delete_record(id: &AddressEntryId) -> String {
+  write_record(&[DELETE.to_owned(), id.0.to_string()])
+}
+
+// 引用符の外の改行で終わるレコードごとに分け、書きかけの末尾を除いた長さも返す
+fn split_records(content: &str) -> (Vec<&str>, usize) {
+  let mut records = vec![];
+  let mut start = 0;
+  let mut in_quotes = false;
+  for (i, b) in content.bytes().enumerate() {
+    match b {
+      // "" のエスケープは2回反転するので状態は変わらない
+      b'"' => in_quotes = !in_quotes,
+      b'\n' if !in_quotes => {
+        records.push(&content[start..=i]);
+        start = i + 1;
+      }
+      _ => {}
+    }
+  }
+  (records, start)
+}
+
+fn apply_record(address_book: &mut AddressBook, record: &[String]) -> Result<()> {
+  let id = record
+    .get(1)
+    .and_then(|id| id.parse::<u64>().ok())
+    .map(AddressEntryId::new)
+    .ok_or_else(|| anyhow!("invalid id"))?;
+  match record[0].as_str() {
+    STORE => {
+      address_book.put_entry(parse_store_record(record, id)?);
+    }
+    DELETE => {
+      if address_book.contains(&id) {
+        address_book.remove_entry(id);
+      }
+    }
+    op => return Err(anyhow!("unknown operation: {}", op)),
+  }
+  Ok(())
+}
+
+// ログと食い違うため取り消し履歴は残さない
+// 完結したレコードまでの状態と、そのバイト長を返す。書きかけの末尾は含めない
+fn replay(content: &str) -> Result<(AddressBook, usize)> {
+  let mut address_book = AddressBook::default().with_history_limit(0);
+  let (records, len) = split_records(content);
+  for (i, text) in records.into_iter().enumerate() {
+    parse_records(text)
+      .and_then(|records| match records.first() {
+        Some(record) => apply_record(&mut address_book, record),
+        None => Err(anyhow!("empty record")),
+      })
+      .map_err(|e| anyhow!("record {}: {}", i + 1, e))?;
+  }
+  Ok((address_book, len))
+}
+
+struct State {
+  address_book: AddressBook,
+  file: File,
+}
+
+impl State {
+  async fn append(&mut self, record: &str) -> Result<()> {
+    self.file.write_all(record.as_bytes()).await?;
+    self.file.sync_data().await?;
+    Ok(())
+  }
+}
+
+pub struct AppendOnlyFileRepository {
+  path: PathBuf,
+  state: RwLock<State>,
+  dropped_bytes: usize,
+}
+
+impl AppendOnlyFileRepository {
+  pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let (address_book, len, total) = match fs::read(&path).await {
+      Ok(bytes) => {
+        // 書き込み途中で落ちると末尾の文字が途切れていることがある
+        let content = match std::str::from_utf8(&bytes) {
+          Ok(content) => content,
+          Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()])?,
+          Err(e) => return Err(e.into()),
+        };
+        let (address_book, len) = replay(content)?;
+        (address_book, len, bytes.len())
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        (AddressBook::default().with_history_limit(0), 0, 0)
+      }
+      Err(e) => return Err(e.into()),
+    };
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)
+      .await?;
+    // 次の追記が書きかけの行に続かないよう、完結したレコードの直後まで切り詰める
+    if len < total {
+      file.set_len(len as u64).await?;
+      file.sync_data().await?;
+    }
+    Ok(Self {
+      path,
+      state: RwLock::new(State { address_book, file }),
+      dropped_bytes: total - len,
+    })
+  }
+
+  // open で切り捨てた書きかけのレコードのバイト数
+  pub fn dropped_bytes(&self) -> usize {
+    self.dropped_bytes
+  }
+
+  // 現在の状態だけを書いたログに置き換える
+  pub async fn compact(&self) -> Result<()> {
+    let mut state = self.state.write().await;
+    let content = state
+      .address_book
+      .iter()
+      .map(store_record)
+      .collect::<Result<String>>()?;
+    let file_name = self
+      .path
+      .file_name()
+      .ok_or_else(|| anyhow!("invalid path: {}", self.path.display()))?
+      .to_string_lossy();
+    let tmp_path = self.path.with_file_name(format!(".{}.tmp", file_name));
+    let mut tmp = File::create(&tmp_path).await?;
+    tmp.write_all(content.as_bytes()).await?;
+    tmp.sync_all().await?;
+    fs::rename(&tmp_path, &self.path).await?;
+    state.file = OpenOptions::new().append(true).open(&self.path).await?;
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl AddressBookRepository for AppendOnlyFileRepository {
+  async fn find_by_id(&self, id: &AddressEntryId) -> Result<Option<AddressEntry>> {
+    Ok(self.state.read().await.address_book.get(id).cloned())
+  }
+
+  async fn find_by_query(&self, query: &Query) -> Result<QueryPage> {
+    let state = self.state.read().await;
+    let result = state.address_book.query(query);
+    Ok(QueryPage {
+      total: result.total,
+      entries: result.entries.into_iter().cloned().collect(),
+    })
+  }
+
+  async fn store(&self, entry: AddressEntry) -> Result<()> {
+    // TOML の整数に収まらない id は書けても読み戻せない
+    checked_id(entry.id.0)?;
+    let mut state = self.state.write().await;
+    state.append(&store_record(&entry)?).await?;
+    state.address_book.put_entry(entry);
+    Ok(())
+  }
+
+  async fn delete(&self, id: &AddressEntryId) -> Result<Option<AddressEntry>> {
+    let mut state = self.state.write().await;
+    if !state.address_book.contains(id) {
+      return Ok(None);
+    }
+    state.append(&delete_record(id)).await?;
+    Ok(Some(state.address_book.remove_entry(id.clone())))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::repository::conformance;
+  use crate::address_book::{test_entry, ContactLabel};
+
+  fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sandbox-rs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+  }
+
+  #[tokio::test]
+  async fn test_conformance() {
+    let path = temp_path("append_only_conformance.log");
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    conformance::run(&repository).await;
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_reopen_and_compact() {
+    let path = temp_path("append_only_reopen.log");
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    conformance::run(&repository).await;
+    drop(repository);
+
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    let ids = |page: QueryPage| page.entries.iter().map(|e| e.id.0).collect::<Vec<_>>();
+    let records = || parse_records(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+      ids(repository.find_by_query(&Query::new()).await.unwrap()),
+      vec![1, 3]
+    );
+    assert_eq!(records().len(), 5);
+
+    repository.compact().await.unwrap();
+    assert_eq!(records().len(), 2);
+    repository
+      .delete(&AddressEntryId::new(1))
+      .await
+      .unwrap()
+      .unwrap();
+    drop(repository);
+
+    // 末尾の書きかけレコードは無視される
+    std::fs::OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .and_then(|mut f| std::io::Write::write_all(&mut f, b"store,9,Jiro"))
+      .unwrap();
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    assert_eq!(
+      ids(repository.find_by_query(&Query::new()).await.unwrap()),
+      vec![3]
+    );
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_truncated_store_record() {
+    let path = temp_path("append_only_truncated.log");
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    repository
+      .store(test_entry(1, "Taro", "Kato", "111-0001"))
+      .await
+      .unwrap();
+    // 新しいファイルでも読み戻した場合と同じく取り消し履歴を残さない
+    assert!(!repository.state.read().await.address_book.can_undo());
+    drop(repository);
+    let complete = std::fs::read(&path).unwrap();
+
+    // TOMLの途中、引用符の内側で書き込みが途切れた STORE レコード
+    let record = store_record(&test_entry(2, "Taro", "Yamamoto", "111-0001")).unwrap();
+    let torn = &record.as_bytes()[..record.find("last_name").unwrap()];
+    std::fs::OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .and_then(|mut f| std::io::Write::write_all(&mut f, torn))
+      .unwrap();
+
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    assert_eq!(repository.dropped_bytes(), torn.len());
+    assert_eq!(std::fs::read(&path).unwrap(), complete);
+    repository
+      .store(test_entry(3, "Taro", "Yamada", "111-0001"))
+      .await
+      .unwrap();
+    drop(repository);
+
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    assert_eq!(repository.dropped_bytes(), 0);
+    let page = repository.find_by_query(&Query::new()).await.unwrap();
+    assert_eq!(
+      page.entries.iter().map(|e| e.id.0).collect::<Vec<_>>(),
+      vec![1, 3]
+    );
+    assert_eq!(page.entries[1], test_entry(3, "Taro", "Yamada", "111-0001"));
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_legacy_records() {
+    let path = temp_path("append_only_legacy.log");
+    std::fs::write(
+      &path,
+      "store,1,Junichi,Kato,111-0001,東京都,minato-ku 1,\nstore,2,Taro,Yamamoto,530-0001,大阪府,kita-ku 2,\ndelete,2\n",
+    )
+    .unwrap();
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    let mut entry = repository
+      .find_by_id(&AddressEntryId::new(1))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(entry.address().address, "minato-ku 1");
+    assert!(repository
+      .find_by_id(&AddressEntryId::new(2))
+      .await
+      .unwrap()
+      .is_none());
+
+    entry
+      .phones
+      .push(ContactLabel::Work, "03-1234-5678".parse().unwrap());
+    repository.store(entry.clone()).await.unwrap();
+    drop(repository);
+    let repository = AppendOnlyFileRepository::open(&path).await.unwrap();
+    assert_eq!(
+      repository
+        .find_by_id(&AddressEntryId::new(1))
+        .await
+        .unwrap(),
+      Some(entry)
+    );
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,65 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::address_book::id_generator::checked_id;
+use crate::address_book::repository::{AddressBookRepository, QueryPage};
+use crate::address_book::{AddressBook, AddressEntry, AddressEntryId, Query};
+
+#[derive(Debug, Default)]
+pub struct InMemoryRepository {
+  address_book: RwLock<AddressBook>,
+}
+
+impl InMemoryRepository {
+  pub fn new(address_book: AddressBook) -> Self {
+    Self {
+      address_book: RwLock::new(address_book),
+    }
+  }
+
+  pub async fn snapshot(&self) -> AddressBook {
+    self.address_book.read().await.clone()
+  }
+}
+
+#[async_trait]
+impl AddressBookRepository for InMemoryRepository {
+  async fn find_by_id(&self, id: &AddressEntryId) -> Result<Option<AddressEntry>> {
+    Ok(self.address_book.read().await.get(id).cloned())
+  }
+
+  async fn find_by_query(&self, query: &Query) -> Result<QueryPage> {
+    let address_book = self.address_book.read().await;
+    let result = address_book.query(query);
+    Ok(QueryPage {
+      total: result.total,
+      entries: result.entries.into_iter().cloned().collect(),
+    })
+  }
+
+  async fn store(&self, entry: AddressEntry) -> Result<()> {
+    checked_id(entry.id.0)?;
+    self.address_book.write().await.put_entry(entry);
+    Ok(())
+  }
+
+  async fn delete(&self, id: &AddressEntryId) -> Result<Option<AddressEntry>> {
+    let mut address_book = self.address_book.write().await;
+    if !address_book.contains(id) {
+      return Ok(None);
+    }
+    Ok(Some(address_book.remove_entry(id.clone())))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::repository::conformance;
+
+  #[tokio::test]
+  async fn test_conformance() {
+    conformance::run(&InMemoryRepository::default()).await;
+  }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::{broadcast, watch, Mutex};
+
+use crate::address_book::{AddressBook, AddressBookEvent};
+
+// 1回の書き込みで起きた変更。version は変更のあった書き込みごとに1つ増える
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressBookChange {
+  pub version: u64,
+  pub events: Vec<AddressBookEvent>,
+}
+
+#[derive(Debug)]
+struct Inner {
+  book: Mutex<(u64, AddressBook)>,
+  snapshot: watch::Sender<Arc<AddressBook>>,
+  // 受信側が1つもないと送信した値が捨てられるので、常に1つ持っておく
+  latest: watch::Receiver<Arc<AddressBook>>,
+  changes: broadcast::Sender<AddressBookChange>,
+}
+
+// 複数のタスクから共有するためのハンドル。書き込みは直列化し、
+// 読み込みは最後に確定したスナップショットに対して行うので書き込みを待たない。
+// 変更のあった書き込みごとにアドレス帳全体を複製してスナップショットにするため、
+// 大きなアドレス帳に続けて書き込む場合は write_batch や transaction で1回にまとめること
+#[derive(Debug, Clone)]
+pub struct SharedAddressBook {
+  inner: Arc<Inner>,
+}
+
+impl SharedAddressBook {
+  pub fn new(address_book: AddressBook) -> Self {
+    Self::with_capacity(address_book, 64)
+  }
+
+  // capacity は変更通知のバッファ数。遅れた購読者は古い通知を取りこぼす
+  pub fn with_capacity(address_book: AddressBook, capacity: usize) -> Self {
+    let mut address_book = address_book.with_events();
+    address_book.take_events();
+    let (snapshot, latest) = watch::channel(Arc::new(address_book.clone()));
+    let (changes, _) = broadcast::channel(capacity);
+    Self {
+      inner: Arc::new(Inner {
+        book: Mutex::new((0, address_book)),
+        snapshot,
+        latest,
+        changes,
+      }),
+    }
+  }
+
+  pub fn snapshot(&self) -> Arc<AddressBook> {
+    self.inner.latest.borrow().clone()
+  }
+
+  pub async fn read<T, F>(&self, f: F) -> T
+  where
+    F: FnOnce(&AddressBook) -> T,
+  {
+    f(&self.snapshot())
+  }
+
+  pub async fn write<T, F>(&self, f: F) -> T
+  where
+    F: FnOnce(&mut AddressBook) -> T,
+  {
+    let mut guard = self.inner.book.lock().await;
+    let revision = guard.1.revision();
+    let result = f(&mut guard.1);
+    self.publish(&mut guard, revision);
+    result
+  }
+
+  // items ごとに f を呼び、スナップショットの作成と通知は最後に1回だけ行う
+  pub async fn write_batch<I, T, F>(&self, items: I, mut f: F) -> Vec<T>
+  where
+    I: IntoIterator,
+    F: FnMut(&mut AddressBook, I::Item) -> T,
+  {
+    self
+      .write(|book| items.into_iter().map(|item| f(book, item)).collect())
+      .await
+  }
+
+  // Err の場合は f の中の変更をすべて元に戻し、通知もしない
+  pub async fn transaction<T, F>(&self, label: &str, f: F) -> Result<T>
+  where
+    F: FnOnce(&mut AddressBook) -> Result<T>,
+  {
+    let mut guard = self.inner.book.lock().await;
+    let revision = guard.1.revision();
+    let result = guard.1.transaction(label, f);
+    if result.is_ok() {
+      self.publish(&mut guard, revision);
+    }
+    result
+  }
+
+  fn publish(&self, guard: &mut (u64, AddressBook), revision: u64) {
+    let events = guard.1.take_events();
+    if events.is_empty() && guard.1.revision() == revision {
+      return;
+    }
+    guard.0 += 1;
+    let _ = self.inner.snapshot.send(Arc::new(guard.1.clone()));
+    // 変更通知の購読者がいなければ送信は失敗するが問題ない
+    let _ = self.inner.changes.send(AddressBookChange {
+      version: guard.0,
+      events,
+    });
+  }
+
+  // 変更のたびに最新のスナップショットを受け取る
+  pub fn subscribe(&self) -> watch::Receiver<Arc<AddressBook>> {
+    self.inner.snapshot.subscribe()
+  }
+
+  pub fn subscribe_changes(&self) -> broadcast::Receiver<AddressBookChange> {
+    self.inner.changes.subscribe()
+  }
+
+  pub async fn version(&self) -> u64 {
+    self.inner.book.lock().await.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{test_entry, AddressEntryId, Group};
+  use anyhow::anyhow;
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+  async fn test_concurrent_writes() {
+    let shared = SharedAddressBook::new(AddressBook::new("test"));
+    let mut changes = shared.subscribe_changes();
+    let tasks = (1..=20)
+      .map(|id| {
+        let shared = shared.clone();
+        tokio::spawn(async move {
+          shared
+            .write(|book| book.add_entry(test_entry(id, "Taro", "Yamamoto", "111-0001")))
+            .await
+        })
+      })
+      .collect::<Vec<_>>();
+    for task in tasks {
+      task.await.unwrap().unwrap();
+    }
+    assert_eq!(shared.read(|book| book.len()).await, 20);
+    assert_eq!(shared.version().await, 20);
+    let mut versions = vec![];
+    for _ in 0..20 {
+      let change = changes.recv().await.unwrap();
+      assert_eq!(change.events.len(), 1);
+      versions.push(change.version);
+    }
+    assert_eq!(versions, (1..=20).collect::<Vec<_>>());
+  }
+
+  #[tokio::test]
+  async fn test_snapshot_and_subscribe() {
+    let shared = SharedAddressBook::new(AddressBook::new("test"));
+    let snapshot = shared.snapshot();
+    let mut receiver = shared.subscribe();
+    let mut changes = shared.subscribe_changes();
+
+    let subscriber = tokio::spawn(async move {
+      receiver.changed().await.unwrap();
+      let len = receiver.borrow().len();
+      len
+    });
+    shared
+      .transaction("bulk", |book| {
+        book.add_entries(&[
+          test_entry(1, "Taro", "Yamamoto", "111-0001"),
+          test_entry(2, "Taro", "Yamamoto", "111-0001"),
+        ])?;
+        book.remove_entry(AddressEntryId::new(1));
+        Ok(())
+      })
+      .await
+      .unwrap();
+    assert_eq!(subscriber.await.unwrap(), 1);
+    // 取得済みのスナップショットは変わらない
+    assert!(snapshot.is_empty());
+    assert_eq!(changes.recv().await.unwrap().events.len(), 3);
+
+    // 失敗したトランザクションと変更のない書き込みは通知しない
+    let result = shared
+      .transaction("failed", |book| {
+        book.add_entry(test_entry(3, "Taro", "Yamamoto", "111-0001"))?;
+        Err::<(), _>(anyhow!("abort"))
+      })
+      .await;
+    assert!(result.is_err());
+    shared
+      .write(|book| book.get(&AddressEntryId::new(2)).is_some())
+      .await;
+    assert!(changes.try_recv().is_err());
+    assert_eq!(shared.snapshot().len(), 1);
+    assert_eq!(shared.version().await, 1);
+  }
+
+  #[tokio::test]
+  async fn test_publish_group_changes() {
+    let shared = SharedAddressBook::new(AddressBook::new("test"));
+    let mut changes = shared.subscribe_changes();
+    shared
+      .write(|book| book.create_group("friends", None))
+      .await
+      .unwrap();
+    assert!(shared.snapshot().group("friends").is_some());
+    let change = changes.recv().await.unwrap();
+    assert_eq!(change.version, 1);
+    assert_eq!(
+      change.events,
+      vec![AddressBookEvent::GroupCreated(Group::new("friends"))]
+    );
+
+    let results = shared
+      .write_batch(
+        vec![
+          test_entry(1, "Taro", "Yamamoto", "111-0001"),
+          test_entry(2, "Taro", "Yamamoto", "111-0001"),
+          test_entry(1, "Taro", "Yamamoto", "111-0001"),
+        ],
+        |book, entry| book.add_entry(entry).is_ok(),
+      )
+      .await;
+    assert_eq!(results, vec![true, true, false]);
+    assert_eq!(shared.snapshot().len(), 2);
+    assert_eq!(changes.recv().await.unwrap().events.len(), 2);
+    assert_eq!(shared.version().await, 2);
+  }
+}
@@ -0,0 +1,613 @@
+use std::collections::BTreeSet;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::dedup::set_primary_value;
+use crate::address_book::id_generator::checked_id;
+use crate::address_book::{
+  AddressBook, AddressEntry, AddressEntryField, AddressEntryId, ContactList, FieldChange,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryDiff {
+  Added(AddressEntry),
+  Removed(AddressEntry),
+  // changes には主以外の住所や連絡先、タグ、グループの変更は含まれない(FieldChange::diff を参照)。
+  // それらだけが変わった場合も Changed になり、changes は空になる
+  Changed {
+    before: Box<AddressEntry>,
+    after: Box<AddressEntry>,
+    changes: Vec<FieldChange>,
+  },
+}
+
+impl EntryDiff {
+  pub fn id(&self) -> &AddressEntryId {
+    match self {
+      EntryDiff::Added(entry) | EntryDiff::Removed(entry) => &entry.id,
+      EntryDiff::Changed { after, .. } => &after.id,
+    }
+  }
+}
+
+// 削除と変更は元の並び順、追加は新しい側の並び順
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddressBookDiff {
+  pub entries: Vec<EntryDiff>,
+}
+
+impl AddressBookDiff {
+  pub fn between(from: &AddressBook, to: &AddressBook) -> Self {
+    let mut entries = vec![];
+    for before in from.iter() {
+      match to.get(&before.id) {
+        None => entries.push(EntryDiff::Removed(before.clone())),
+        Some(after) if after != before => entries.push(EntryDiff::Changed {
+          before: Box::new(before.clone()),
+          after: Box::new(after.clone()),
+          changes: FieldChange::diff(before, after),
+        }),
+        Some(_) => {}
+      }
+    }
+    entries.extend(
+      to.iter()
+        .filter(|e| !from.contains(&e.id))
+        .map(|e| EntryDiff::Added(e.clone())),
+    );
+    Self { entries }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncConflictKind {
+  // 両方で違う値に変更されたフィールド
+  Fields(Vec<AddressEntryField>),
+  // こちらで削除し、相手が変更した
+  RemovedByUs,
+  // 相手が削除し、こちらで変更した
+  RemovedByThem,
+}
+
+// 衝突したエントリはこちらの状態をそのまま残す。こちらで削除したエントリは削除したままにし、
+// 相手の変更を取り込む場合は theirs を使う
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncConflict {
+  pub id: AddressEntryId,
+  pub kind: SyncConflictKind,
+  pub ours: Option<AddressEntry>,
+  pub theirs: Option<AddressEntry>,
+}
+
+const NAME_FIELDS: [AddressEntryField; 4] = [
+  AddressEntryField::FirstName,
+  AddressEntryField::LastName,
+  AddressEntryField::FirstNameKana,
+  AddressEntryField::LastNameKana,
+];
+
+fn set_field(
+  entry: &mut AddressEntry,
+  field: AddressEntryField,
+  value: Option<String>,
+) -> Result<()> {
+  let required = || value.clone().ok_or_else(|| anyhow!("missing {}", field));
+  match field {
+    AddressEntryField::FirstName => entry.name.first_name = required()?,
+    AddressEntryField::LastName => entry.name.last_name = required()?,
+    AddressEntryField::FirstNameKana => entry.name.first_name_kana = value,
+    AddressEntryField::LastNameKana => entry.name.last_name_kana = value,
+    AddressEntryField::PostalCode => entry.address_mut().postal_code = required()?.parse()?,
+    AddressEntryField::Pref => entry.address_mut().pref = required()?.parse()?,
+    AddressEntryField::Address => entry.address_mut().address = required()?,
+    AddressEntryField::Building => entry.address_mut().building = value,
+    AddressEntryField::Phone | AddressEntryField::Email => {
+      return Err(anyhow!("{} cannot be set directly", field))
+    }
+  }
+  Ok(())
+}
+
+fn contains<T: PartialEq>(list: Option<&ContactList<T>>, value: &T) -> bool {
+  list.is_some_and(|list| list.iter().any(|x| x.value == *value))
+}
+
+// 片方だけが変えた一覧はその内容を使い、両方が変えた場合は双方の追加と削除を反映する。
+// 主連絡先を両方が違うものに変えた場合は衝突
+fn merge_list<T: Clone + PartialEq>(
+  base: Option<&ContactList<T>>,
+  ours: &ContactList<T>,
+  theirs: &ContactList<T>,
+) -> Result<(ContactList<T>, bool)> {
+  if Some(ours) == base {
+    return Ok((theirs.clone(), false));
+  }
+  if Some(theirs) == base || ours == theirs {
+    return Ok((ours.clone(), false));
+  }
+  let mut list = ContactList::new();
+  for item in ours.iter() {
+    if contains(Some(theirs), &item.value) || !contains(base, &item.value) {
+      list.push(item.label.clone(), item.value.clone());
+    }
+  }
+  for item in theirs.iter() {
+    if !contains(Some(ours), &item.value) && !contains(base, &item.value) {
+      list.push(item.label.clone(), item.value.clone());
+    }
+  }
+  let base_primary = base.and_then(|b| b.primary());
+  let (ours_primary, theirs_primary) = (ours.primary(), theirs.primary());
+  let primary = if ours_primary == base_primary {
+    theirs_primary
+  } else {
+    ours_primary
+  };
+  set_primary_value(&mut list, primary)?;
+  let conflict = ours_primary != base_primary
+    && theirs_primary != base_primary
+    && ours_primary != theirs_primary;
+  Ok((list, conflict))
+}
+
+fn merge_set(
+  base: Option<&BTreeSet<String>>,
+  ours: &BTreeSet<String>,
+  theirs: &BTreeSet<String>,
+) -> BTreeSet<String> {
+  let in_base = |value: &String| base.is_some_and(|b| b.contains(value));
+  ours
+    .union(theirs)
+    .filter(|v| (ours.contains(*v) && theirs.contains(*v)) || !in_base(v))
+    .cloned()
+    .collect()
+}
+
+// 両方が変更したエントリをフィールド単位でマージする。衝突したフィールドはこちらの値を残す
+fn merge_entry(
+  base: Option<&AddressEntry>,
+  ours: &AddressEntry,
+  theirs: &AddressEntry,
+) -> Result<(AddressEntry, Vec<AddressEntryField>)> {
+  let mut merged = ours.clone();
+  let mut conflicts = vec![];
+  for field in &NAME_FIELDS {
+    let base_value = base.and_then(|b| field.get(b));
+    let (ours_value, theirs_value) = (field.get(ours), field.get(theirs));
+    if ours_value == theirs_value || theirs_value == base_value {
+      continue;
+    }
+    if ours_value == base_value {
+      set_field(&mut merged, *field, theirs_value)?;
+    } else {
+      conflicts.push(*field);
+    }
+  }
+
+  // 住所の一覧も電話番号などと同じくマージし、主住所が食い違えば衝突にする。
+  // 双方が相手の残した住所を消して空になる場合は、こちらの一覧を残して衝突にする
+  let (addresses, conflict) = merge_list(
+    base.map(|b| b.addresses()),
+    ours.addresses(),
+    theirs.addresses(),
+  )?;
+  if addresses.is_empty() {
+    conflicts.push(AddressEntryField::Address);
+  } else {
+    merged.addresses = addresses;
+    if conflict {
+      conflicts.push(AddressEntryField::Address);
+    }
+  }
+
+  let (phones, conflict) = merge_list(base.map(|b| &b.phones), &ours.phones, &theirs.phones)?;
+  merged.phones = phones;
+  if conflict {
+    conflicts.push(AddressEntryField::Phone);
+  }
+  let (emails, conflict) = merge_list(base.map(|b| &b.emails), &ours.emails, &theirs.emails)?;
+  merged.emails = emails;
+  if conflict {
+    conflicts.push(AddressEntryField::Email);
+  }
+  merged.tags = merge_set(base.map(|b| &b.tags), &ours.tags, &theirs.tags);
+  merged.groups = merge_set(base.map(|b| &b.groups), &ours.groups, &theirs.groups);
+  Ok((merged, conflicts))
+}
+
+impl AddressBook {
+  // self から other への差分
+  pub fn diff(&self, other: &AddressBook) -> AddressBookDiff {
+    AddressBookDiff::between(self, other)
+  }
+
+  // 差分の元になった状態から変わっているエントリや検証を通らないエントリがあれば何も適用せずにErr
+  pub fn apply_diff(&mut self, diff: &AddressBookDiff) -> Result<()> {
+    self.transaction("apply_diff", |book| {
+      for entry_diff in &diff.entries {
+        let before = match entry_diff {
+          EntryDiff::Added(_) => None,
+          EntryDiff::Removed(before) => Some(before),
+          EntryDiff::Changed { before, .. } => Some(before.as_ref()),
+        };
+        if before.is_some_and(|before| book.get(&before.id) != Some(before)) {
+          return Err(anyhow!(
+            "address entry has been modified: {:?}",
+            entry_diff.id()
+          ));
+        }
+        match entry_diff {
+          EntryDiff::Added(entry) => book.add_entry(entry.clone())?,
+          EntryDiff::Removed(before) => {
+            book.remove_entry(before.id.clone());
+          }
+          EntryDiff::Changed { before, after, .. } => {
+            if after.id != before.id {
+              return Err(anyhow!(
+                "address entry id has changed: {:?} -> {:?}",
+                before.id,
+                after.id
+              ));
+            }
+            book.validate(after)?;
+            book.put_entry(*after.clone());
+          }
+        }
+      }
+      Ok(())
+    })
+  }
+
+  // base を共通の祖先として theirs の変更を取り込み、衝突を返す。
+  // マージ全体をひとつの操作として取り消せる。取り込む結果が検証を通らなければ何もせずにErr
+  pub fn merge_from(
+    &mut self,
+    base: &AddressBook,
+    theirs: &AddressBook,
+  ) -> Result<Vec<SyncConflict>> {
+    let mut ids = self.iter().map(|e| e.id.clone()).collect::<Vec<_>>();
+    ids.extend(
+      theirs
+        .iter()
+        .filter(|e| !self.contains(&e.id))
+        .map(|e| e.id.clone()),
+    );
+    self.transaction("merge_from", |book| {
+      for group in theirs.groups() {
+        if book.group(&group.name).is_none() {
+          book.create_group(&group.name, group.description.as_deref())?;
+        }
+      }
+      let mut conflicts = vec![];
+      for id in &ids {
+        let base_entry = base.get(id);
+        let ours = book.get(id).cloned();
+        let theirs_entry = theirs.get(id);
+        if ours.as_ref() == theirs_entry || theirs_entry == base_entry {
+          continue;
+        }
+        let conflict = |kind| SyncConflict {
+          id: id.clone(),
+          kind,
+          ours: ours.clone(),
+          theirs: theirs_entry.cloned(),
+        };
+        match (&ours, theirs_entry) {
+          _ if ours.as_ref() == base_entry => match theirs_entry {
+            Some(entry) => {
+              checked_id(entry.id.0)?;
+              book.validate(entry)?;
+              book.put_entry(entry.clone());
+            }
+            None => {
+              book.remove_entry(id.clone());
+            }
+          },
+          (Some(_), None) => conflicts.push(conflict(SyncConflictKind::RemovedByThem)),
+          (None, Some(_)) => conflicts.push(conflict(SyncConflictKind::RemovedByUs)),
+          (Some(ours_entry), Some(theirs_entry)) => {
+            let (merged, fields) = merge_entry(base_entry, ours_entry, theirs_entry)?;
+            if !fields.is_empty() {
+              conflicts.push(conflict(SyncConflictKind::Fields(fields)));
+            }
+            if merged != *ours_entry {
+              book.validate(&merged)?;
+              book.put_entry(merged);
+            }
+          }
+          (None, None) => {}
+        }
+      }
+      Ok(conflicts)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{
+    test_entry, Address, ContactLabel, PostalCode, Prefecture, ValidationRules,
+  };
+
+  fn address_book(entries: &[AddressEntry]) -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    address_book.add_entries(entries).unwrap();
+    address_book
+  }
+
+  #[test]
+  fn test_diff_and_apply() {
+    let from = address_book(&[
+      test_entry(1, "Taro", "Kato", "111-0001"),
+      test_entry(2, "Taro", "Yamamoto", "111-0001"),
+      test_entry(3, "Taro", "Yamada", "111-0001"),
+    ]);
+    let mut changed = test_entry(2, "Taro", "Suzuki", "111-0001");
+    changed.add_tag("vip");
+    let to = address_book(&[
+      test_entry(4, "Taro", "Sato", "111-0001"),
+      changed,
+      test_entry(1, "Taro", "Kato", "111-0001"),
+    ]);
+
+    let diff = from.diff(&to);
+    assert_eq!(diff.len(), 3);
+    match &diff.entries[0] {
+      EntryDiff::Changed { changes, .. } => {
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, AddressEntryField::LastName);
+        assert_eq!(changes[0].new_value.as_deref(), Some("Suzuki"));
+      }
+      other => panic!("unexpected diff: {:?}", other),
+    }
+    assert!(matches!(&diff.entries[1], EntryDiff::Removed(e) if e.id.0 == 3));
+    assert!(matches!(&diff.entries[2], EntryDiff::Added(e) if e.id.0 == 4));
+    assert!(to.diff(&to).is_empty());
+
+    let mut book = from.clone();
+    book.apply_diff(&diff).unwrap();
+    assert!(book.diff(&to).is_empty());
+    assert_eq!(book.undo_label(), Some("apply_diff"));
+
+    // タグだけの変更も差分として検出され、適用される
+    let mut tagged = to.clone();
+    tagged
+      .update_entry(&AddressEntryId::new(4), |e| {
+        e.add_tag("new");
+      })
+      .unwrap();
+    let tag_diff = to.diff(&tagged);
+    assert!(matches!(
+      &tag_diff.entries[..],
+      [EntryDiff::Changed { changes, .. }] if changes.is_empty()
+    ));
+    book.apply_diff(&tag_diff).unwrap();
+    assert!(book.diff(&tagged).is_empty());
+
+    // 適用先が差分の元と違えば何も適用しない
+    let mut book = from.clone();
+    book
+      .patch_entry(
+        &AddressEntryId::new(2),
+        &crate::address_book::AddressEntryPatch::new().with_first_name("Jiro"),
+      )
+      .unwrap();
+    let before = book.clone();
+    assert!(book.apply_diff(&diff).is_err());
+    assert!(book.diff(&before).is_empty());
+    // 変更後のエントリも検証する
+    let mut invalid = test_entry(2, "Jiro", "", "111-0001");
+    let invalid_diff = AddressBookDiff {
+      entries: vec![EntryDiff::Changed {
+        before: Box::new(book.get(&AddressEntryId::new(2)).unwrap().clone()),
+        after: Box::new(invalid.clone()),
+        changes: vec![],
+      }],
+    };
+    assert!(book.apply_diff(&invalid_diff).is_err());
+    invalid.name.last_name = "Suzuki".to_owned();
+    invalid.id = AddressEntryId::new(9);
+    let moved_diff = AddressBookDiff {
+      entries: vec![EntryDiff::Changed {
+        before: Box::new(book.get(&AddressEntryId::new(2)).unwrap().clone()),
+        after: Box::new(invalid),
+        changes: vec![],
+      }],
+    };
+    assert!(book.apply_diff(&moved_diff).is_err());
+    assert!(book.diff(&before).is_empty());
+  }
+
+  #[test]
+  fn test_merge_from() {
+    let mut base_entry = test_entry(1, "Taro", "Kato", "111-0001");
+    base_entry.add_tag("customer");
+    let base = address_book(&[
+      base_entry.clone(),
+      test_entry(2, "Taro", "Yamamoto", "111-0001"),
+      test_entry(3, "Taro", "Yamada", "111-0001"),
+    ]);
+
+    // こちら: 1の名と電話番号を変更、2を削除
+    let mut ours = base.clone();
+    ours
+      .update_entry(&AddressEntryId::new(1), |e| {
+        e.name.first_name = "Junichi".to_owned();
+        e.phones
+          .push(ContactLabel::Mobile, "090-1111-2222".parse().unwrap());
+        e.add_tag("vip");
+      })
+      .unwrap();
+    ours.remove_entry(AddressEntryId::new(2));
+
+    // 相手: 1の住所とタグ、3の姓を変更、2を変更、4を追加
+    let mut theirs = base.clone();
+    theirs
+      .update_entry(&AddressEntryId::new(1), |e| {
+        e.address_mut().address = "chiyoda-ku".to_owned();
+        e.phones
+          .push(ContactLabel::Home, "03-1234-5678".parse().unwrap());
+        e.remove_tag("customer");
+      })
+      .unwrap();
+    theirs
+      .update_entry(&AddressEntryId::new(2), |e| {
+        e.name.first_name = "Jiro".to_owned()
+      })
+      .unwrap();
+    theirs
+      .update_entry(&AddressEntryId::new(3), |e| {
+        e.name.last_name = "Suzuki".to_owned()
+      })
+      .unwrap();
+    theirs
+      .add_entry(test_entry(4, "Taro", "Sato", "111-0001"))
+      .unwrap();
+
+    let conflicts = ours.merge_from(&base, &theirs).unwrap();
+    assert_eq!(conflicts.len(), 2);
+    assert_eq!(conflicts[0].id, AddressEntryId::new(1));
+    assert_eq!(
+      conflicts[0].kind,
+      SyncConflictKind::Fields(vec![AddressEntryField::Phone])
+    );
+    assert_eq!(conflicts[1].id, AddressEntryId::new(2));
+    assert_eq!(conflicts[1].kind, SyncConflictKind::RemovedByUs);
+
+    let merged = ours.get(&AddressEntryId::new(1)).unwrap();
+    assert_eq!(merged.name.first_name, "Junichi");
+    assert_eq!(merged.address().address, "chiyoda-ku");
+    assert_eq!(merged.phones.len(), 2);
+    assert_eq!(
+      merged.phones.primary().unwrap().to_string(),
+      "090-1111-2222"
+    );
+    assert_eq!(merged.tags.iter().collect::<Vec<_>>(), vec!["vip"]);
+    // こちらで削除したエントリは削除したまま、相手の変更は衝突に残る
+    assert!(!ours.contains(&AddressEntryId::new(2)));
+    assert_eq!(conflicts[1].ours, None);
+    assert_eq!(
+      conflicts[1].theirs.as_ref().unwrap().name.first_name,
+      "Jiro"
+    );
+    assert_eq!(
+      ours.get(&AddressEntryId::new(3)).unwrap().name.last_name,
+      "Suzuki"
+    );
+    assert!(ours.contains(&AddressEntryId::new(4)));
+
+    // 両方が同じフィールドを違う値に変えた場合
+    let mut ours = base.clone();
+    ours
+      .update_entry(&AddressEntryId::new(3), |e| {
+        e.name.last_name = "Sato".to_owned()
+      })
+      .unwrap();
+    let conflicts = ours.merge_from(&base, &theirs).unwrap();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(
+      conflicts[0].kind,
+      SyncConflictKind::Fields(vec![AddressEntryField::LastName])
+    );
+    assert_eq!(
+      ours.get(&AddressEntryId::new(3)).unwrap().name.last_name,
+      "Sato"
+    );
+    ours.undo().unwrap();
+    assert!(ours.get(&AddressEntryId::new(4)).is_none());
+
+    // 相手が削除し、こちらで変更した場合もこちらの変更を残す
+    let mut removed = base.clone();
+    removed.remove_entry(AddressEntryId::new(3));
+    let conflicts = ours.merge_from(&base, &removed).unwrap();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].kind, SyncConflictKind::RemovedByThem);
+    assert_eq!(conflicts[0].theirs, None);
+    assert_eq!(
+      ours.get(&AddressEntryId::new(3)).unwrap().name.last_name,
+      "Sato"
+    );
+    // 取り込む相手の変更も検証する
+    let mut invalid = base.clone().with_validation_rules(ValidationRules::none());
+    invalid
+      .update_entry(&AddressEntryId::new(2), |e| {
+        e.name.last_name = "".to_owned()
+      })
+      .unwrap();
+    let before = ours.clone();
+    assert!(ours.merge_from(&base, &invalid).is_err());
+    assert!(ours.diff(&before).is_empty());
+  }
+
+  #[test]
+  fn test_merge_addresses() {
+    let base = address_book(&[test_entry(1, "Taro", "Kato", "111-0001")]);
+    let address = |postal_code: &str| {
+      Address::new(
+        PostalCode::new(postal_code).unwrap(),
+        Prefecture::Osaka,
+        "kita-ku",
+        None,
+      )
+    };
+    let id = AddressEntryId::new(1);
+
+    // 双方が別の住所を追加した場合はどちらも残す
+    let mut ours = base.clone();
+    ours
+      .update_entry(&id, |e| {
+        e.add_address(ContactLabel::Work, address("530-0001"));
+      })
+      .unwrap();
+    let mut theirs = base.clone();
+    theirs
+      .update_entry(&id, |e| {
+        e.add_address(ContactLabel::Home, address("530-0002"));
+      })
+      .unwrap();
+    assert!(ours.merge_from(&base, &theirs).unwrap().is_empty());
+    let merged = ours.get(&id).unwrap();
+    assert_eq!(
+      merged
+        .addresses()
+        .iter()
+        .map(|a| a.value.postal_code.to_string())
+        .collect::<Vec<_>>(),
+      vec!["111-0001", "530-0001", "530-0002"]
+    );
+    assert_eq!(merged.address().postal_code.to_string(), "111-0001");
+
+    // 主住所を双方が違う値に変えた場合は衝突にして、こちらの主住所を残す
+    let mut ours = base.clone();
+    ours
+      .update_entry(&id, |e| {
+        e.address_mut().building = Some("hoge building".to_owned())
+      })
+      .unwrap();
+    let mut theirs = base.clone();
+    theirs
+      .update_entry(&id, |e| {
+        e.add_address(ContactLabel::Work, address("530-0001"));
+        e.address_mut().address = "chiyoda-ku".to_owned();
+      })
+      .unwrap();
+    let conflicts = ours.merge_from(&base, &theirs).unwrap();
+    assert_eq!(
+      conflicts[0].kind,
+      SyncConflictKind::Fields(vec![AddressEntryField::Address])
+    );
+    let merged = ours.get(&id).unwrap();
+    assert_eq!(merged.address().building.as_deref(), Some("hoge building"));
+    assert_eq!(merged.addresses().len(), 3);
+  }
+}
@@ -0,0 +1,350 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::address_book::kana::normalize_width;
+use crate::address_book::{Address, AddressBook, AddressEntry, AddressEntryField};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CharacterSet {
+  // 制御文字以外
+  Printable,
+  // ひらがな・カタカナ(半角を含む)・長音符・空白
+  Kana,
+  Ascii,
+  // 列挙した文字のみ
+  Only(String),
+}
+
+impl CharacterSet {
+  fn contains(&self, c: char) -> bool {
+    match self {
+      CharacterSet::Printable => !c.is_control(),
+      CharacterSet::Kana => matches!(c,
+        '\u{3041}'..='\u{309F}' | '\u{30A0}'..='\u{30FF}' | '\u{FF66}'..='\u{FF9F}' | ' ' | '　'
+      ),
+      CharacterSet::Ascii => c.is_ascii() && !c.is_ascii_control(),
+      CharacterSet::Only(chars) => chars.contains(c),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+  // 空白のみの値も未入力とみなす
+  Required,
+  // 文字数。全角・半角を問わず1文字と数える
+  MaxLength(usize),
+  AllowedChars(CharacterSet),
+}
+
+impl Rule {
+  fn check(&self, value: Option<&str>) -> Option<String> {
+    match (self, value) {
+      (Rule::Required, None) => Some("is required".to_owned()),
+      (Rule::Required, Some(value)) if value.trim().is_empty() => Some("is required".to_owned()),
+      (Rule::MaxLength(max), Some(value)) if value.chars().count() > *max => Some(format!(
+        "must be at most {} characters (got {})",
+        max,
+        value.chars().count()
+      )),
+      (Rule::AllowedChars(set), Some(value)) => value
+        .chars()
+        .find(|c| !set.contains(*c))
+        .map(|c| format!("contains a disallowed character: {:?}", c)),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+  // name.first_name や addresses[1].address のようなフィールドのパス
+  pub path: String,
+  pub field: AddressEntryField,
+  pub rule: Rule,
+  pub message: String,
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.path, self.message)
+  }
+}
+
+// 違反をすべてまとめたエラー。anyhow::Error から downcast_ref で取り出せる
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+  pub violations: Vec<Violation>,
+}
+
+impl fmt::Display for ValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid address entry: ")?;
+    for (i, violation) in self.violations.iter().enumerate() {
+      if i > 0 {
+        write!(f, "; ")?;
+      }
+      write!(f, "{}", violation)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationRules {
+  rules: Vec<(AddressEntryField, Rule)>,
+}
+
+impl Default for ValidationRules {
+  fn default() -> Self {
+    use AddressEntryField::*;
+    let mut rules = Self::none();
+    for field in [FirstName, LastName] {
+      rules = rules
+        .rule(field, Rule::Required)
+        .rule(field, Rule::MaxLength(50))
+        .rule(field, Rule::AllowedChars(CharacterSet::Printable));
+    }
+    for field in [FirstNameKana, LastNameKana] {
+      rules = rules
+        .rule(field, Rule::MaxLength(50))
+        .rule(field, Rule::AllowedChars(CharacterSet::Kana));
+    }
+    rules
+      .rule(Address, Rule::Required)
+      .rule(Address, Rule::MaxLength(200))
+      .rule(Address, Rule::AllowedChars(CharacterSet::Printable))
+      .rule(Building, Rule::MaxLength(100))
+      .rule(Building, Rule::AllowedChars(CharacterSet::Printable))
+  }
+}
+
+impl ValidationRules {
+  // 何も検査しない
+  pub fn none() -> Self {
+    Self { rules: vec![] }
+  }
+
+  pub fn rule(mut self, field: AddressEntryField, rule: Rule) -> Self {
+    self.rules.push((field, rule));
+    self
+  }
+
+  // 指定したフィールドの規則をすべて外す
+  pub fn without(mut self, field: AddressEntryField) -> Self {
+    self.rules.retain(|(f, _)| *f != field);
+    self
+  }
+
+  pub fn rules(&self, field: AddressEntryField) -> impl Iterator<Item = &Rule> {
+    self
+      .rules
+      .iter()
+      .filter(move |(f, _)| *f == field)
+      .map(|(_, rule)| rule)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rules.is_empty()
+  }
+}
+
+// 住所のフィールドは主住所以外にも適用する
+fn field_values(entry: &AddressEntry, field: AddressEntryField) -> Vec<(String, Option<String>)> {
+  let address_field = match field {
+    AddressEntryField::Address => |a: &Address| Some(a.address.clone()),
+    AddressEntryField::Building => |a: &Address| a.building.clone(),
+    _ => return vec![(field.to_string(), field.get(entry))],
+  };
+  let addresses = entry.addresses();
+  addresses
+    .iter()
+    .enumerate()
+    .map(|(i, item)| {
+      let path = if addresses.primary_index() == Some(i) {
+        field.to_string()
+      } else {
+        format!(
+          "addresses[{}].{}",
+          i,
+          field.to_string().trim_start_matches("address.")
+        )
+      };
+      (path, address_field(&item.value))
+    })
+    .collect()
+}
+
+impl AddressEntry {
+  pub fn violations(&self, rules: &ValidationRules) -> Vec<Violation> {
+    let mut violations = vec![];
+    for field in AddressEntryField::ALL.iter() {
+      for (path, value) in field_values(self, *field) {
+        // 全角英数字は半角に揃えてから検査する
+        let value = value.map(|v| normalize_width(&v));
+        for rule in rules.rules(*field) {
+          if let Some(message) = rule.check(value.as_deref()) {
+            violations.push(Violation {
+              path: path.clone(),
+              field: *field,
+              rule: rule.clone(),
+              message,
+            });
+          }
+        }
+      }
+    }
+    violations
+  }
+
+  pub fn validate(&self, rules: &ValidationRules) -> Result<()> {
+    let violations = self.violations(rules);
+    if violations.is_empty() {
+      Ok(())
+    } else {
+      Err(ValidationError { violations }.into())
+    }
+  }
+}
+
+impl AddressBook {
+  pub fn with_validation_rules(mut self, rules: ValidationRules) -> Self {
+    self.validation_rules = rules;
+    self
+  }
+
+  pub fn validation_rules(&self) -> &ValidationRules {
+    &self.validation_rules
+  }
+
+  pub fn validate(&self, address_entry: &AddressEntry) -> Result<()> {
+    address_entry.validate(&self.validation_rules)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address_book::{
+    test_entry, AddressEntryId, AddressEntryPatch, ContactLabel, PersonName, PostalCode, Prefecture,
+  };
+
+  #[test]
+  fn test_violations() {
+    let rules = ValidationRules::default();
+    assert_eq!(
+      test_entry(1, "太郎", "山本", "105-0011").violations(&rules),
+      vec![]
+    );
+
+    let mut invalid = test_entry(1, "", "山本\n", "105-0011");
+    invalid.address_mut().address = " ".to_owned();
+    invalid.name.last_name_kana = Some("やまもとX".to_owned());
+    invalid.add_address(
+      ContactLabel::Work,
+      Address::new(
+        PostalCode::new("100-0005").unwrap(),
+        Prefecture::Tokyo,
+        &"丸".repeat(201),
+        None,
+      ),
+    );
+    let paths = invalid
+      .violations(&rules)
+      .iter()
+      .map(|v| v.to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      paths,
+      vec![
+        "name.first_name: is required",
+        "name.last_name: contains a disallowed character: '\\n'",
+        "name.last_name_kana: contains a disallowed character: 'X'",
+        "address.address: is required",
+        "addresses[1].address: must be at most 200 characters (got 201)",
+      ]
+    );
+
+    let rules = ValidationRules::none()
+      .rule(AddressEntryField::Email, Rule::Required)
+      .rule(
+        AddressEntryField::Building,
+        Rule::AllowedChars(CharacterSet::Ascii),
+      );
+    let mut entry = test_entry(1, "", "", "105-0011");
+    entry.address_mut().building = Some("ＡＢＣビル".to_owned());
+    let violations = entry.violations(&rules);
+    assert_eq!(violations.len(), 2);
+    assert_eq!(violations[0].field, AddressEntryField::Building);
+    assert_eq!(
+      violations[0].message,
+      "contains a disallowed character: 'ビ'"
+    );
+    assert_eq!(violations[1].to_string(), "email: is required");
+  }
+
+  #[test]
+  fn test_add_entry() {
+    let mut address_book = AddressBook::new("test");
+    let error = address_book
+      .add_entry(test_entry(1, "", "", "105-0011"))
+      .unwrap_err();
+    let error = error.downcast_ref::<ValidationError>().unwrap();
+    assert_eq!(error.violations.len(), 2);
+    assert_eq!(
+      error.to_string(),
+      "invalid address entry: name.first_name: is required; name.last_name: is required"
+    );
+    assert!(address_book.is_empty());
+
+    let mut address_book = AddressBook::new("test")
+      .with_validation_rules(ValidationRules::default().without(AddressEntryField::FirstName));
+    address_book
+      .add_entry(test_entry(1, "", "山本", "105-0011"))
+      .unwrap();
+    assert_eq!(address_book.len(), 1);
+  }
+
+  #[test]
+  fn test_update_entry() {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entry(test_entry(1, "太郎", "山本", "105-0011"))
+      .unwrap();
+    let id = AddressEntryId::new(1);
+    let error = address_book
+      .update_entry(&id, |e| e.name.last_name = " ".to_owned())
+      .unwrap_err();
+    assert!(error.downcast_ref::<ValidationError>().is_some());
+    let patch = AddressEntryPatch::new().with_address(&"丸".repeat(201));
+    assert!(address_book.patch_entry(&id, &patch).is_err());
+    assert_eq!(
+      address_book.get(&id),
+      Some(&test_entry(1, "太郎", "山本", "105-0011"))
+    );
+    assert!(address_book.find_by_last_name("山本").next().is_some());
+
+    let error = address_book
+      .add(
+        PersonName::new("", "山本"),
+        Address::new(
+          PostalCode::new("105-0011").unwrap(),
+          Prefecture::Tokyo,
+          "港区",
+          None,
+        ),
+      )
+      .unwrap_err();
+    assert!(error.downcast_ref::<ValidationError>().is_some());
+    assert_eq!(address_book.len(), 1);
+
+    let changes = address_book
+      .update_entry(&id, |e| e.name.first_name = "次郎".to_owned())
+      .unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(address_book.get(&id).unwrap().name.first_name, "次郎");
+  }
+}
@@ -0,0 +1,737 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::address_book::id_generator::checked_id;
+use crate::address_book::{
+  Address, AddressBook, AddressEntry, AddressEntryId, ContactLabel, ContactList, EmailAddress,
+  NameOrder, PersonName, PhoneNumber, PostalCode, Prefecture, ValidationRules,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VCardVersion {
+  V3,
+  V4,
+}
+
+impl fmt::Display for VCardVersion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      VCardVersion::V3 => write!(f, "3.0"),
+      VCardVersion::V4 => write!(f, "4.0"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VCardError {
+  // 0始まりのカード番号と、BEGIN:VCARD の行番号
+  pub card_index: usize,
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for VCardError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "card {} (line {}): {}",
+      self.card_index, self.line, self.message
+    )
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VCardImportOptions {
+  // trueの場合はUIDが既存のエントリと一致するカードでそのエントリを置き換える。falseならそのカードはエラー
+  pub replace_existing: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VCardImportReport {
+  // 新しく追加したエントリ
+  pub imported: Vec<AddressEntryId>,
+  // replace_existing で置き換えた既存のエントリ
+  pub replaced: Vec<AddressEntryId>,
+  pub errors: Vec<VCardError>,
+}
+
+fn escape(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace(',', "\\,")
+    .replace(';', "\\;")
+    .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+  let mut result = String::new();
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c == '\\' {
+      match chars.next() {
+        Some('n') | Some('N') => result.push('\n'),
+        Some(other) => result.push(other),
+        None => result.push('\\'),
+      }
+    } else {
+      result.push(c);
+    }
+  }
+  result
+}
+
+// エスケープされていない区切り文字で分割する
+fn split_unescaped(value: &str, separator: char) -> Vec<String> {
+  let mut parts = vec![];
+  let mut current = String::new();
+  let mut escaped = false;
+  for c in value.chars() {
+    if escaped {
+      current.push('\\');
+      current.push(c);
+      escaped = false;
+    } else if c == '\\' {
+      escaped = true;
+    } else if c == separator {
+      parts.push(unescape(&std::mem::take(&mut current)));
+    } else {
+      current.push(c);
+    }
+  }
+  if escaped {
+    current.push('\\');
+  }
+  parts.push(unescape(&current));
+  parts
+}
+
+// 75オクテットを超える行を折り返す(UTF-8の文字境界は保つ)
+fn fold(line: &str) -> String {
+  let mut result = String::new();
+  let mut width = 0;
+  for c in line.chars() {
+    if width + c.len_utf8() > 75 {
+      result.push_str("\r\n ");
+      width = 1;
+    }
+    result.push(c);
+    width += c.len_utf8();
+  }
+  result.push_str("\r\n");
+  result
+}
+
+// TYPE パラメータの値。主連絡先には PREF を付ける
+fn type_params(label: &ContactLabel, primary: bool, version: VCardVersion) -> String {
+  let label = match label {
+    ContactLabel::Mobile => "cell".to_owned(),
+    label => label.to_string(),
+  };
+  match (version, primary) {
+    (VCardVersion::V3, false) => format!(";TYPE={}", escape(&label.to_uppercase())),
+    (VCardVersion::V3, true) => format!(";TYPE={},PREF", escape(&label.to_uppercase())),
+    (VCardVersion::V4, false) => format!(";TYPE={}", escape(&label)),
+    (VCardVersion::V4, true) => format!(";TYPE={};PREF=1", escape(&label)),
+  }
+}
+
+fn contact_lines<T, F>(
+  name: &str,
+  list: &ContactList<T>,
+  version: VCardVersion,
+  value: F,
+) -> Vec<String>
+where
+  F: Fn(&T) -> String,
+{
+  list
+    .iter()
+    .enumerate()
+    .map(|(i, item)| {
+      format!(
+        "{}{}:{}",
+        name,
+        type_params(&item.label, list.primary_index() == Some(i), version),
+        value(&item.value)
+      )
+    })
+    .collect()
+}
+
+pub fn to_vcard(entry: &AddressEntry, version: VCardVersion) -> String {
+  let name = &entry.name;
+  let mut lines = vec![
+    "BEGIN:VCARD".to_owned(),
+    format!("VERSION:{}", version),
+    format!("UID:{}", entry.id.0),
+    format!(
+      "N:{};{};;;",
+      escape(&name.last_name),
+      escape(&name.first_name)
+    ),
+    format!("FN:{}", escape(&name.full_name())),
+  ];
+  // ふりがなは主要なクライアントが読む拡張プロパティで出力する
+  if let Some(kana) = &name.last_name_kana {
+    lines.push(format!("X-PHONETIC-LAST-NAME:{}", escape(kana)));
+  }
+  if let Some(kana) = &name.first_name_kana {
+    lines.push(format!("X-PHONETIC-FIRST-NAME:{}", escape(kana)));
+  }
+  lines.extend(contact_lines(
+    "ADR",
+    entry.addresses(),
+    version,
+    |address| {
+      format!(
+        ";{};{};;{};{};",
+        escape(address.building.as_deref().unwrap_or("")),
+        escape(&address.address),
+        escape(address.pref.kanji()),
+        address.postal_code
+      )
+    },
+  ));
+  lines.extend(contact_lines("TEL", &entry.phones, version, |phone| {
+    phone.to_string()
+  }));
+  lines.extend(contact_lines("EMAIL", &entry.emails, version, |email| {
+    escape(email.as_str())
+  }));
+  if !entry.tags.is_empty() {
+    let tags = entry.tags.iter().map(|t| escape(t)).collect::<Vec<_>>();
+    lines.push(format!("CATEGORIES:{}", tags.join(",")));
+  }
+  lines.push("END:VCARD".to_owned());
+  lines.iter().map(|line| fold(line)).collect()
+}
+
+struct Property {
+  name: String,
+  // 大文字にしたパラメータ名と値。名前のないパラメータ(v2.1)は TYPE とみなす
+  params: Vec<(String, String)>,
+  value: String,
+}
+
+impl Property {
+  fn types(&self) -> impl Iterator<Item = String> + '_ {
+    self
+      .params
+      .iter()
+      .filter(|(name, _)| name == "TYPE")
+      .flat_map(|(_, value)| split_unescaped(value, ','))
+      .map(|t| t.trim_matches('"').to_lowercase())
+  }
+
+  fn label(&self) -> ContactLabel {
+    self
+      .types()
+      .find(|t| !matches!(t.as_str(), "pref" | "voice" | "internet"))
+      .map(|t| ContactLabel::from(t.as_str()))
+      .unwrap_or(ContactLabel::Home)
+  }
+
+  fn is_preferred(&self) -> bool {
+    self.types().any(|t| t == "pref") || self.params.iter().any(|(name, _)| name == "PREF")
+  }
+}
+
+fn parse_property(line: &str) -> Option<Property> {
+  let colon = line.find(':')?;
+  let (head, value) = (&line[..colon], &line[colon + 1..]);
+  let mut head = head.split(';');
+  let name = head.next()?;
+  let name = name.rsplit('.').next()?.to_uppercase();
+  let params = head
+    .map(|param| match param.find('=') {
+      Some(eq) => (param[..eq].to_uppercase(), param[eq + 1..].to_owned()),
+      None => ("TYPE".to_owned(), param.to_owned()),
+    })
+    .collect();
+  Some(Property {
+    name,
+    params,
+    value: value.to_owned(),
+  })
+}
+
+fn parse_address(adr: &Property) -> Result<Address> {
+  let parts = split_unescaped(&adr.value, ';');
+  let part = |i: usize| parts.get(i).map(|s| s.as_str()).unwrap_or("");
+  let postal_code = PostalCode::new(part(5))?;
+  let pref = Prefecture::parse(part(4))?;
+  let street = match part(3) {
+    "" => part(2).to_owned(),
+    locality => format!("{}{}", locality, part(2)),
+  };
+  let building = Some(part(1)).filter(|b| !b.is_empty());
+  Ok(Address::new(postal_code, pref, &street, building))
+}
+
+// PREF の付いたものを主連絡先にする。なければ最初のもの
+fn collect_contacts<T, F>(properties: &[Property], name: &str, parse: F) -> Result<ContactList<T>>
+where
+  F: Fn(&Property) -> Result<T>,
+{
+  let mut list = ContactList::new();
+  let mut primary = None;
+  for property in properties.iter().filter(|p| p.name == name) {
+    let index = list.push(property.label(), parse(property)?);
+    if primary.is_none() && property.is_preferred() {
+      primary = Some(index);
+    }
+  }
+  if let Some(index) = primary {
+    list.set_primary(index)?;
+  }
+  Ok(list)
+}
+
+fn build_entry(properties: &[Property], id: AddressEntryId) -> Result<AddressEntry> {
+  let find = |name: &str| properties.iter().find(|p| p.name == name);
+  let name = match (find("N"), find("FN")) {
+    (Some(n), _) => {
+      let parts = split_unescaped(&n.value, ';');
+      let last_name = parts.first().cloned().unwrap_or_default();
+      let first_name = parts.get(1).cloned().unwrap_or_default();
+      PersonName::new(&first_name, &last_name)
+    }
+    (None, Some(fn_)) => {
+      // 漢字やかなの氏名は姓が先、それ以外は名が先とみなす。区切りのない漢字の氏名は姓に入れる
+      let full_name = unescape(&fn_.value);
+      let mut words = full_name.split_whitespace();
+      let head = words.next().unwrap_or_default().to_owned();
+      let tail = words.collect::<Vec<_>>().join(" ");
+      let name = PersonName::new(&head, &tail);
+      match name.name_order() {
+        NameOrder::FamilyFirst => PersonName::new(&tail, &head),
+        NameOrder::GivenFirst => name,
+      }
+    }
+    (None, None) => return Err(anyhow!("missing N and FN")),
+  };
+  let kana = |name: &str| find(name).map(|p| unescape(&p.value)).unwrap_or_default();
+  let name = name.with_kana(
+    &kana("X-PHONETIC-FIRST-NAME"),
+    &kana("X-PHONETIC-LAST-NAME"),
+  );
+  let addresses = collect_contacts(properties, "ADR", parse_address)?;
+  if addresses.is_empty() {
+    return Err(anyhow!("missing ADR"));
+  }
+  let mut entry = AddressEntry::with_addresses(id, name, addresses)?;
+  entry.phones = collect_contacts(properties, "TEL", |p| {
+    PhoneNumber::new(unescape(&p.value).trim_start_matches("tel:"))
+  })?;
+  entry.emails = collect_contacts(properties, "EMAIL", |p| {
+    EmailAddress::new(&unescape(&p.value))
+  })?;
+  for categories in properties.iter().filter(|p| p.name == "CATEGORIES") {
+    for tag in split_unescaped(&categories.value, ',') {
+      entry.add_tag(&tag);
+    }
+  }
+  Ok(entry)
+}
+
+// UIDが数値でないカードには next_id で採番する。rules に違反するカードと、
+// 同じファイル内でUIDが重複するカードはエラーにする
+pub fn parse_vcards<F>(
+  input: &str,
+  rules: &ValidationRules,
+  next_id: F,
+) -> (Vec<AddressEntry>, Vec<VCardError>)
+where
+  F: FnMut() -> Result<AddressEntryId>,
+{
+  let (cards, errors) = parse_cards(input, rules, next_id);
+  (
+    cards.into_iter().map(|(_, _, entry)| entry).collect(),
+    errors,
+  )
+}
+
+// エントリと、そのカード番号・BEGIN:VCARD の行番号
+fn parse_cards<F>(
+  input: &str,
+  rules: &ValidationRules,
+  mut next_id: F,
+) -> (Vec<(usize, usize, AddressEntry)>, Vec<VCardError>)
+where
+  F: FnMut() -> Result<AddressEntryId>,
+{
+  let mut lines: Vec<(usize, String)> = vec![];
+  for (i, raw) in input.lines().enumerate() {
+    match (raw.chars().next(), lines.last_mut()) {
+      (Some(' '), Some((_, last))) | (Some('\t'), Some((_, last))) => last.push_str(&raw[1..]),
+      _ => lines.push((i + 1, raw.trim_end_matches('\r').to_owned())),
+    }
+  }
+
+  let mut entries = vec![];
+  let mut errors = vec![];
+  let mut seen = HashSet::new();
+  let mut card: Option<(usize, Vec<Property>)> = None;
+  let mut card_index = 0;
+  for (line_no, line) in lines {
+    if line.trim().is_empty() {
+      continue;
+    }
+    let upper = line.to_uppercase();
+    if upper == "BEGIN:VCARD" {
+      if let Some((start, _)) = card.replace((line_no, vec![])) {
+        errors.push(VCardError {
+          card_index,
+          line: start,
+          message: "missing END:VCARD".to_owned(),
+        });
+        card_index += 1;
+      }
+      continue;
+    }
+    if upper == "END:VCARD" {
+      match card.take() {
+        Some((start, properties)) => {
+          let id = match properties
+            .iter()
+            .find(|p| p.name == "UID")
+            .and_then(|p| p.value.trim().parse::<u64>().ok())
+          {
+            Some(id) => checked_id(id),
+            None => next_id(),
+          };
+          match id
+            .and_then(|id| build_entry(&properties, id))
+            .and_then(|e| e.validate(rules).map(|_| e))
+            .and_then(|e| {
+              if seen.insert(e.id.clone()) {
+                Ok(e)
+              } else {
+                Err(anyhow!("duplicate UID in file: {}", e.id.0))
+              }
+            }) {
+            Ok(entry) => entries.push((card_index, start, entry)),
+            Err(e) => errors.push(VCardError {
+              card_index,
+              line: start,
+              message: e.to_string(),
+            }),
+          }
+          card_index += 1;
+        }
+        None => errors.push(VCardError {
+          card_index,
+          line: line_no,
+          message: "END:VCARD without BEGIN:VCARD".to_owned(),
+        }),
+      }
+      continue;
+    }
+    if let Some((_, properties)) = card.as_mut() {
+      match parse_property(&line) {
+        Some(property) => properties.push(property),
+        None => errors.push(VCardError {
+          card_index,
+          line: line_no,
+          message: format!("malformed line: {}", line),
+        }),
+      }
+    }
+  }
+  if let Some((start, _)) = card {
+    errors.push(VCardError {
+      card_index,
+      line: start,
+      message: "missing END:VCARD".to_owned(),
+    });
+  }
+  (entries, errors)
+}
+
+impl AddressBook {
+  pub fn export_vcards(&self, version: VCardVersion) -> String {
+    self.iter().map(|entry| to_vcard(entry, version)).collect()
+  }
+
+  pub fn import_vcards(&mut self, input: &str, options: &VCardImportOptions) -> VCardImportReport {
+    let rules = self.validation_rules().clone();
+    let (cards, errors) =
+      self.with_id_sequence(false, |next_id| parse_cards(input, &rules, next_id));
+    let mut report = VCardImportReport {
+      errors,
+      ..VCardImportReport::default()
+    };
+    let mut entries = vec![];
+    for (card_index, line, entry) in cards {
+      match (self.contains(&entry.id), options.replace_existing) {
+        (false, _) => report.imported.push(entry.id.clone()),
+        (true, true) => report.replaced.push(entry.id.clone()),
+        (true, false) => {
+          report.errors.push(VCardError {
+            card_index,
+            line,
+            message: format!("address entry already exists: {}", entry.id.0),
+          });
+          continue;
+        }
+      }
+      entries.push(entry);
+    }
+    report.errors.sort_by_key(|e| (e.card_index, e.line));
+    self
+      .transaction("import_vcards", |book| {
+        entries.into_iter().for_each(|e| {
+          book.put_entry(e);
+        });
+        Ok(())
+      })
+      .unwrap();
+    report
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn address_book() -> AddressBook {
+    let mut address_book = AddressBook::new("test");
+    address_book
+      .add_entries(&[
+        AddressEntry::new(
+          AddressEntryId::new(1),
+          PersonName::new("Junichi", "Kato"),
+          Address::new(
+            PostalCode::new("111-0001").unwrap(),
+            Prefecture::Tokyo,
+            "港区芝公園1;2,3",
+            Some("hoge 1 building"),
+          ),
+        ),
+        AddressEntry::new(
+          AddressEntryId::new(2),
+          PersonName::new("Taro", "Yamamoto").with_kana("たろう", "やまもと"),
+          Address::new(
+            PostalCode::new("530-0001").unwrap(),
+            Prefecture::Osaka,
+            "大阪市北区梅田".repeat(5).as_str(),
+            None,
+          ),
+        ),
+      ])
+      .unwrap();
+    address_book
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for version in &[VCardVersion::V3, VCardVersion::V4] {
+      let exported = address_book().export_vcards(*version);
+      assert!(exported.lines().all(|l| l.len() <= 75));
+      let mut imported = AddressBook::new("imported");
+      let report = imported.import_vcards(&exported, &VCardImportOptions::default());
+      assert!(report.errors.is_empty(), "{:?}", report.errors);
+      assert_eq!(
+        report.imported,
+        vec![AddressEntryId::new(1), AddressEntryId::new(2)]
+      );
+      let entry = imported.get(&AddressEntryId::new(1)).unwrap();
+      assert_eq!(entry.name.last_name, "Kato");
+      assert_eq!(entry.address().address, "港区芝公園1;2,3");
+      assert_eq!(entry.address().building.as_deref(), Some("hoge 1 building"));
+      assert_eq!(entry.name.last_name_kana, None);
+      let entry = imported.get(&AddressEntryId::new(2)).unwrap();
+      assert_eq!(entry.name, address_book().iter().nth(1).unwrap().name);
+      assert_eq!(entry.address().address, "大阪市北区梅田".repeat(5));
+      assert_eq!(entry.address().pref, Prefecture::Osaka);
+    }
+  }
+
+  #[test]
+  fn test_import_reports_errors_per_card() {
+    let input = "\
+BEGIN:VCARD\r
+VERSION:4.0\r
+FN:Hanako Yamada\r
+item1.ADR;TYPE=work:;;芝公園1;港区;東京都;105-0011;Japan\r
+END:VCARD\r
+BEGIN:VCARD\r
+VERSION:3.0\r
+N:Suzuki;Ichiro;;;\r
+ADR:;;梅田;;大阪府;invalid;\r
+END:VCARD\r
+BEGIN:VCARD\r
+VERSION:3.0\r
+N:Sato;Jiro;;;\r
+END:VCARD\r
+";
+    let mut address_book = address_book();
+    let report = address_book.import_vcards(input, &VCardImportOptions::default());
+    assert_eq!(report.imported, vec![AddressEntryId::new(3)]);
+    let entry = address_book.get(&AddressEntryId::new(3)).unwrap();
+    assert_eq!(entry.name.first_name, "Hanako");
+    assert_eq!(entry.name.last_name, "Yamada");
+    assert_eq!(entry.address().address, "港区芝公園1");
+    assert_eq!(
+      report
+        .errors
+        .iter()
+        .map(|e| (e.card_index, e.line))
+        .collect::<Vec<_>>(),
+      vec![(1, 6), (2, 11)]
+    );
+    assert_eq!(report.errors[1].message, "missing ADR");
+  }
+
+  #[test]
+  fn test_import_japanese_full_name() {
+    let card = |full_name: &str| {
+      format!(
+        "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:{}\r\n\
+         ADR:;;芝公園1;港区;東京都;105-0011;\r\nEND:VCARD\r\n",
+        full_name
+      )
+    };
+    let input = [card("山本 太郎"), card("山本太郎")].concat();
+    let mut address_book = AddressBook::new("test").with_validation_rules(ValidationRules::none());
+    let report = address_book.import_vcards(&input, &VCardImportOptions::default());
+    assert_eq!(report.imported.len(), 2);
+    let names = report
+      .imported
+      .iter()
+      .map(|id| address_book.get(id).unwrap().name.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(names[0], PersonName::new("太郎", "山本"));
+    assert_eq!(names[1], PersonName::new("", "山本太郎"));
+  }
+
+  #[test]
+  fn test_import_duplicate_and_existing_uids() {
+    let card = |uid: u64, first_name: &str| {
+      format!(
+        "BEGIN:VCARD\r\nVERSION:3.0\r\nUID:{}\r\nN:Yamada;{};;;\r\n\
+         ADR:;;芝公園1;港区;東京都;105-0011;\r\nEND:VCARD\r\n",
+        uid, first_name
+      )
+    };
+    let input = [card(1, "Hanako"), card(5, "Jiro"), card(5, "Saburo")].concat();
+    let mut address_book = address_book();
+    let report = address_book.import_vcards(&input, &VCardImportOptions::default());
+    assert_eq!(report.imported, vec![AddressEntryId::new(5)]);
+    assert_eq!(
+      report
+        .errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>(),
+      vec![
+        "card 0 (line 1): address entry already exists: 1",
+        "card 2 (line 13): duplicate UID in file: 5",
+      ]
+    );
+    assert_eq!(
+      address_book
+        .get(&AddressEntryId::new(5))
+        .unwrap()
+        .name
+        .first_name,
+      "Jiro"
+    );
+
+    let options = VCardImportOptions {
+      replace_existing: true,
+    };
+    let report = address_book.import_vcards(&input, &options);
+    assert!(report.imported.is_empty());
+    assert_eq!(
+      report.replaced,
+      vec![AddressEntryId::new(1), AddressEntryId::new(5)]
+    );
+    assert_eq!(
+      address_book
+        .get(&AddressEntryId::new(1))
+        .unwrap()
+        .name
+        .first_name,
+      "Hanako"
+    );
+  }
+
+  #[test]
+  fn test_contacts() {
+    let mut address_book = address_book();
+    {
+      let mut entry = address_book.get_mut(&AddressEntryId::new(1)).unwrap();
+      let work = entry.add_address(
+        ContactLabel::Work,
+        Address::new(
+          PostalCode::new("100-0005").unwrap(),
+          Prefecture::Tokyo,
+          "千代田区丸の内1",
+          None,
+        ),
+      );
+      entry.set_primary_address(work).unwrap();
+      entry
+        .phones
+        .push(ContactLabel::Home, "03-1234-5678".parse().unwrap());
+      let mobile = entry
+        .phones
+        .push(ContactLabel::Mobile, "090-1234-5678".parse().unwrap());
+      entry.phones.set_primary(mobile).unwrap();
+      entry
+        .emails
+        .push(ContactLabel::Work, "kato@example.com".parse().unwrap());
+      entry.add_tag("vip");
+      entry.add_tag("a,b");
+    }
+    let original = address_book.get(&AddressEntryId::new(1)).unwrap();
+    for version in &[VCardVersion::V3, VCardVersion::V4] {
+      let exported = to_vcard(original, *version);
+      let mut imported = AddressBook::new("imported");
+      let report = imported.import_vcards(&exported, &VCardImportOptions::default());
+      assert!(report.errors.is_empty(), "{:?}", report.errors);
+      assert_eq!(imported.get(&AddressEntryId::new(1)), Some(original));
+    }
+
+    let input = "\
+BEGIN:VCARD\r
+VERSION:3.0\r
+N:Suzuki;Ichiro;;;\r
+ADR;TYPE=HOME:;;梅田;;大阪府;530-0001;\r
+TEL;TYPE=WORK,VOICE:06-1234-5678\r
+TEL;TYPE=CELL,PREF:+81 90 1111 2222\r
+EMAIL;TYPE=INTERNET:ichiro@example.com\r
+END:VCARD\r
+BEGIN:VCARD\r
+VERSION:3.0\r
+N:Sato;Jiro;;;\r
+ADR:;;梅田;;大阪府;530-0001;\r
+EMAIL:not an address\r
+END:VCARD\r
+";
+    let mut address_book = AddressBook::new("test");
+    let report = address_book.import_vcards(input, &VCardImportOptions::default());
+    assert_eq!(report.imported.len(), 1);
+    assert_eq!(report.errors.len(), 1);
+    let entry = address_book.get(&report.imported[0]).unwrap();
+    assert_eq!(entry.phones.len(), 2);
+    assert_eq!(entry.phones.primary().unwrap().to_string(), "090-1111-2222");
+    assert_eq!(
+      entry
+        .phones
+        .find_by_label(&ContactLabel::Work)
+        .unwrap()
+        .to_string(),
+      "06-1234-5678"
+    );
+    assert_eq!(
+      entry.emails.primary().unwrap().as_str(),
+      "ichiro@example.com"
+    );
+    assert_eq!(entry.addresses().get(0).unwrap().label, ContactLabel::Home);
+  }
+}
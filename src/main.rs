@@ -66,6 +66,12 @@ fn gcd(a: u64, b: u64) -> u64 {
 // }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  if args.first().map(|a| a.as_str()) == Some("address") {
+    let code = address_book::cli::run(&args[1..], &mut std::io::stdout(), &mut std::io::stderr());
+    std::process::exit(code);
+  }
+
   let rt = Builder::new_multi_thread()
     .enable_all()
     .worker_threads(4)